@@ -0,0 +1,12 @@
+ALTER TABLE mneme_states
+  DROP CONSTRAINT mneme_states_params_fk;
+
+DROP INDEX params_profile_version_idx;
+
+ALTER TABLE params
+  DROP COLUMN profile,
+  DROP COLUMN target_retention,
+  DROP COLUMN round_to_days,
+  DROP COLUMN first_interval,
+  DROP COLUMN second_interval,
+  DROP COLUMN third_interval;
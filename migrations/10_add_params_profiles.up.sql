@@ -0,0 +1,21 @@
+-- Every row of params is one version of a named profile. Changing any setting
+-- of a profile inserts a new version, so that the states recorded with the old
+-- one keep pointing at the parameters that actually produced them.
+ALTER TABLE params
+  ADD COLUMN profile text NOT NULL DEFAULT 'default',
+  ADD COLUMN target_retention float8 NOT NULL DEFAULT 0.9
+    CHECK (target_retention > 0 AND target_retention < 1),
+  ADD COLUMN round_to_days boolean NOT NULL DEFAULT false,
+  -- the learning steps
+  ADD COLUMN first_interval interval NOT NULL DEFAULT '1 minute',
+  ADD COLUMN second_interval interval NOT NULL DEFAULT '5 minutes',
+  ADD COLUMN third_interval interval NOT NULL DEFAULT '10 minutes';
+
+ALTER TABLE params
+  ALTER COLUMN profile DROP DEFAULT;
+
+CREATE INDEX params_profile_version_idx ON params (profile, version DESC);
+
+ALTER TABLE mneme_states
+  ADD CONSTRAINT mneme_states_params_fk FOREIGN KEY (params_version)
+    REFERENCES params (version);
@@ -0,0 +1,2 @@
+DROP TABLE review_session_items;
+DROP TABLE review_sessions;
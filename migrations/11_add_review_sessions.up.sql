@@ -0,0 +1,18 @@
+-- A session is a batch of mnemes picked for review in a fixed order, so that
+-- the review page does not have to decide what comes next on every request.
+CREATE TABLE review_sessions (
+  id uuid PRIMARY KEY,
+  created_at timestamptz NOT NULL
+);
+
+CREATE TABLE review_session_items (
+  session_id uuid NOT NULL REFERENCES review_sessions (id) ON DELETE CASCADE,
+  position int NOT NULL,
+  mneme_id uuid NOT NULL REFERENCES mnemes (id) ON DELETE CASCADE,
+  -- set when the mneme is reviewed, from wherever that happens
+  done_at timestamptz,
+  PRIMARY KEY (session_id, position)
+);
+
+CREATE INDEX review_session_items_pending_idx ON review_session_items (mneme_id)
+WHERE done_at IS NULL;
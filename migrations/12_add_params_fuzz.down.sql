@@ -0,0 +1,3 @@
+ALTER TABLE params
+  DROP COLUMN fuzz,
+  DROP COLUMN load_balance;
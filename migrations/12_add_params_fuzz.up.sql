@@ -0,0 +1,3 @@
+ALTER TABLE params
+  ADD COLUMN fuzz boolean NOT NULL DEFAULT false,
+  ADD COLUMN load_balance boolean NOT NULL DEFAULT false;
@@ -0,0 +1,8 @@
+DELETE FROM mneme_states
+WHERE undone_at IS NOT NULL;
+
+DROP INDEX mneme_states_mneme_id_index_unique;
+
+ALTER TABLE mneme_states
+  DROP COLUMN undone_at,
+  ADD CONSTRAINT mneme_states_mneme_id_index_unique UNIQUE (mneme_id, index);
@@ -0,0 +1,8 @@
+-- Undoing a review keeps its state around, marked as undone, so that the next
+-- review of the mneme can take its place in the log.
+ALTER TABLE mneme_states
+  ADD COLUMN undone_at timestamptz,
+  DROP CONSTRAINT mneme_states_mneme_id_index_unique;
+
+CREATE UNIQUE INDEX mneme_states_mneme_id_index_unique ON mneme_states (mneme_id, index)
+WHERE undone_at IS NULL;
@@ -0,0 +1,3 @@
+ALTER TABLE mnemes
+  DROP COLUMN suspended,
+  DROP COLUMN buried_until;
@@ -0,0 +1,3 @@
+ALTER TABLE mnemes
+  ADD COLUMN suspended boolean NOT NULL DEFAULT false,
+  ADD COLUMN buried_until timestamptz;
@@ -0,0 +1,3 @@
+DROP VIEW variant_mnemes;
+DROP TABLE variant_cards;
+DROP TYPE card_kind;
@@ -0,0 +1,25 @@
+-- What a card for a variant asks for. Recognition cards are the ones that
+-- already existed, kept in variants.mneme_id since that is what documents are
+-- highlighted by.
+CREATE TYPE card_kind AS ENUM (
+  'Recognition',
+  'Meaning',
+  'Production',
+  'Listening'
+);
+
+CREATE TABLE variant_cards (
+  variant_id uuid NOT NULL REFERENCES variants (id),
+  kind card_kind NOT NULL CHECK (kind <> 'Recognition'),
+  mneme_id uuid NOT NULL UNIQUE REFERENCES mnemes (id),
+  PRIMARY KEY (variant_id, kind)
+);
+
+-- Every card of every variant, whatever its kind.
+CREATE VIEW variant_mnemes AS
+SELECT id AS variant_id, 'Recognition'::card_kind AS kind, mneme_id
+FROM variants
+WHERE mneme_id IS NOT NULL
+UNION ALL
+SELECT variant_id, kind, mneme_id
+FROM variant_cards;
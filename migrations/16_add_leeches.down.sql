@@ -0,0 +1,5 @@
+ALTER TABLE mnemes DROP COLUMN leech;
+
+ALTER TABLE params
+  DROP COLUMN leech_threshold,
+  DROP COLUMN suspend_leeches;
@@ -0,0 +1,5 @@
+ALTER TABLE params
+  ADD COLUMN leech_threshold int NOT NULL DEFAULT 8 CHECK (leech_threshold >= 1),
+  ADD COLUMN suspend_leeches boolean NOT NULL DEFAULT false;
+
+ALTER TABLE mnemes ADD COLUMN leech boolean NOT NULL DEFAULT false;
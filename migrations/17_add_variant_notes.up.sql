@@ -0,0 +1,8 @@
+-- What the learner writes about a variant, shared by all of its cards.
+CREATE TABLE variant_notes (
+  variant_id uuid PRIMARY KEY REFERENCES variants (id),
+  gloss text NOT NULL DEFAULT '',
+  mnemonic text NOT NULL DEFAULT '',
+  notes text NOT NULL DEFAULT '',
+  updated_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+ALTER TABLE params DROP COLUMN slow_okay_after;
+
+ALTER TABLE mneme_states DROP COLUMN latency_ms;
@@ -0,0 +1,5 @@
+-- How long the answer took, when it was timed.
+ALTER TABLE mneme_states ADD COLUMN latency_ms int;
+
+-- Okay answers that take longer than this count as hard. Never if null.
+ALTER TABLE params ADD COLUMN slow_okay_after interval;
@@ -0,0 +1,3 @@
+ALTER TABLE params DROP COLUMN scheduler;
+
+DROP TYPE scheduler_kind;
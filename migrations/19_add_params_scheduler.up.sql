@@ -0,0 +1,7 @@
+-- Which algorithm updates the memory state of mnemes on review.
+CREATE TYPE scheduler_kind AS ENUM (
+  'Fsrs',
+  'Sm2'
+);
+
+ALTER TABLE params ADD COLUMN scheduler scheduler_kind NOT NULL DEFAULT 'Fsrs';
@@ -0,0 +1,7 @@
+-- The profile reviews are performed with. There is only ever one row.
+CREATE TABLE active_profile (
+  id boolean PRIMARY KEY DEFAULT true CHECK (id),
+  profile text NOT NULL
+);
+
+INSERT INTO active_profile (profile) VALUES ('default');
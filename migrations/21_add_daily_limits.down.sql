@@ -0,0 +1,3 @@
+ALTER TABLE params
+  DROP COLUMN new_per_day,
+  DROP COLUMN reviews_per_day;
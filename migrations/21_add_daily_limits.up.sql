@@ -0,0 +1,4 @@
+-- How many new mnemes and reviews a review session takes on per day.
+ALTER TABLE params
+  ADD COLUMN new_per_day int NOT NULL DEFAULT 20 CHECK (new_per_day >= 0),
+  ADD COLUMN reviews_per_day int NOT NULL DEFAULT 200 CHECK (reviews_per_day >= 0);
@@ -0,0 +1,2 @@
+ALTER TABLE mneme_states
+  DROP COLUMN reset;
@@ -0,0 +1,5 @@
+-- Resetting a mneme adds a state to its log that starts it over, rather than
+-- marking the log as undone, so that the reviews before it still count for
+-- statistics and fitting weights.
+ALTER TABLE mneme_states
+  ADD COLUMN reset boolean NOT NULL DEFAULT false;
@@ -0,0 +1 @@
+DROP FUNCTION retrievability;
@@ -0,0 +1,9 @@
+-- Probability of recall of a mneme whose current state has the given stability
+-- and was reviewed at `reviewed_at`, as of `at`. This is the same curve as
+-- `Mneme::retrievability_at`, where only whole days since the review count.
+CREATE FUNCTION retrievability(stability float8, reviewed_at timestamptz, at timestamptz)
+RETURNS float8
+LANGUAGE sql IMMUTABLE
+AS $$
+  SELECT 1.0 / (1.0 + trunc(extract(epoch FROM at - reviewed_at) / 86400) / (9.0 * stability))
+$$;
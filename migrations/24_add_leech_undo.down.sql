@@ -0,0 +1,3 @@
+ALTER TABLE mneme_states
+  DROP COLUMN tagged_leech,
+  DROP COLUMN suspended_leech;
@@ -0,0 +1,5 @@
+-- What a review did to the flags of its mneme when it made it a leech, so that
+-- undoing the review can take it back.
+ALTER TABLE mneme_states
+  ADD COLUMN tagged_leech boolean NOT NULL DEFAULT false,
+  ADD COLUMN suspended_leech boolean NOT NULL DEFAULT false;
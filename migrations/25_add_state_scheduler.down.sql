@@ -0,0 +1 @@
+ALTER TABLE mneme_states DROP COLUMN scheduler;
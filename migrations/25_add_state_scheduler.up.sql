@@ -0,0 +1,6 @@
+-- Which scheduler a state was computed with, which decides what its difficulty
+-- and stability mean.
+ALTER TABLE mneme_states ADD COLUMN scheduler scheduler_kind NOT NULL DEFAULT 'Fsrs';
+UPDATE mneme_states SET scheduler = params.scheduler
+FROM params
+WHERE params.version = mneme_states.params_version;
@@ -0,0 +1,12 @@
+ALTER TABLE mneme_states
+  DROP CONSTRAINT mneme_states_mneme_id_index_unique,
+  DROP CONSTRAINT mneme_states_mnemes_fk,
+  DROP COLUMN params_version,
+  DROP COLUMN elapsed_days,
+  DROP COLUMN index,
+  DROP COLUMN mneme_id;
+
+INSERT INTO mneme_states
+SELECT * FROM orphaned_mneme_states;
+
+DROP TABLE orphaned_mneme_states;
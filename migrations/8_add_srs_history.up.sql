@@ -0,0 +1,37 @@
+-- States that are not the current state of any mneme were orphaned by earlier
+-- reviews and cannot be attributed to a mneme anymore. They are kept aside
+-- rather than thrown away, since they are still review history.
+CREATE TABLE orphaned_mneme_states (LIKE mneme_states INCLUDING ALL);
+
+INSERT INTO orphaned_mneme_states
+SELECT * FROM mneme_states
+WHERE id NOT IN (SELECT state_id FROM mnemes);
+
+DELETE FROM mneme_states
+WHERE id IN (SELECT id FROM orphaned_mneme_states);
+
+ALTER TABLE mneme_states
+  ADD COLUMN mneme_id uuid,
+  -- position of the state in the review log of its mneme
+  ADD COLUMN index int,
+  -- time since the previous review, in (fractional) days
+  ADD COLUMN elapsed_days float8,
+  ADD COLUMN params_version int;
+
+-- What remains is the current state of each mneme, which now starts its log.
+UPDATE mneme_states
+SET mneme_id = mnemes.id, index = 0, elapsed_days = 0, params_version = 0
+FROM mnemes
+WHERE mnemes.state_id = mneme_states.id;
+
+ALTER TABLE mneme_states
+  ALTER COLUMN mneme_id SET NOT NULL,
+  ALTER COLUMN index SET NOT NULL,
+  ALTER COLUMN elapsed_days SET NOT NULL,
+  ALTER COLUMN params_version SET NOT NULL,
+  -- a mneme and its first state reference each other, so one of the two
+  -- constraints has to wait until the end of the transaction
+  ADD CONSTRAINT mneme_states_mnemes_fk FOREIGN KEY (mneme_id)
+    REFERENCES mnemes (id)
+    DEFERRABLE INITIALLY DEFERRED,
+  ADD CONSTRAINT mneme_states_mneme_id_index_unique UNIQUE (mneme_id, index);
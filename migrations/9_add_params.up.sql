@@ -0,0 +1,13 @@
+CREATE TABLE params (
+  version int GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  -- the FSRS weight vector
+  weights float8[] NOT NULL CHECK (cardinality(weights) = 17),
+  -- how well the weights fit the review log they were trained on, if any
+  log_loss float8,
+  num_reviews int
+);
+
+-- The weights that every review so far has been performed with.
+INSERT INTO params (version, weights)
+VALUES (0, ARRAY[0.4, 0.6, 2.4, 5.8, 4.93, 0.94, 0.86, 0.01, 1.49, 0.14, 0.94, 2.18, 0.05, 0.34, 1.26, 0.29, 2.61]);
@@ -21,15 +21,52 @@ function toggleVis () {
             }
         }
     };
-    doToggle(document.getElementById("section-definitions"));
+    // Only set when reviewing, so lookups hide what recognition cards do.
+    let kind = document.body.dataset.cardKind;
+    if (kind !== "Production") {
+        doToggle(document.getElementById("section-definitions"));
+    }
+    // Glosses and mnemonics give away every kind of card.
+    doToggle(document.getElementById("section-notes"));
     doToggle(document.getElementById("section-links"));
     doToggle(document.getElementById("section-examples"));
     doToggle(document.getElementById("variants-content"));
-    document.querySelectorAll("#lookup-header rt").forEach(e => doToggle(e));
+    if (kind === "Production" || kind === "Listening") {
+        doToggle(document.getElementById("lookup-header"));
+    } else if (kind !== "Meaning") {
+        document.querySelectorAll("#lookup-header rt").forEach(e => doToggle(e));
+    }
+}
+
+// When the current card was shown, to time answers.
+let cardShownAt = performance.now();
+
+document.addEventListener("htmx:oobAfterSwap", (event) => {
+    if (event.target.id === "lookup-header") {
+        cardShownAt = performance.now();
+    }
+});
+
+// Reviews of the card on the review page say how long the answer took. Other
+// reviews, such as those from the sidebar while reading, are not timed.
+document.addEventListener("htmx:configRequest", (event) => {
+    let path = event.detail.path;
+    if (document.body.dataset.cardKind && path.includes("/review/")) {
+        let latency = Math.round(performance.now() - cardShownAt);
+        let separator = path.includes("?") ? "&" : "?";
+        event.detail.path = `${path}${separator}latency_ms=${latency}`;
+    }
+});
+
+function speak (text) {
+    let utterance = new SpeechSynthesisUtterance(text);
+    utterance.lang = "ja-JP";
+    speechSynthesis.speak(utterance);
 }
 
 function onBodyKeypress () {
-    if (event.ctrlKey) {
+    // Leave typing in the notes form alone.
+    if (event.ctrlKey || event.target.closest("input, textarea")) {
         return;
     }
     let bulk_okay = ".line:hover .bulk-okay";
@@ -41,6 +78,8 @@ function onBodyKeypress () {
         "k": "#sidebar-hard-button",
         "l": "#sidebar-okay-button",
         ";": "#sidebar-easy-button",
+        "z": "#sidebar-undo-button",
+        "p": "#card-play-button",
         " ": ".variant:hover",
     }
     targets["f"] = targets["j"];
@@ -1,13 +1,17 @@
 #![allow(dead_code)]
 mod types;
 
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::Path,
+};
 
 use serde::Deserialize;
 use snafu::{prelude::*, ResultExt};
-use szr_morph::{Blob, Cache, Dict, FormatToken, UserDict};
+use szr_morph::{compile::write_dictionary, Blob, Cache, Dict, DictEntry, DictKind};
 use szr_tokenise::{AnnToken, AnnTokens, Tokeniser};
-use tracing::{error, info, instrument, trace};
+use tracing::{error, info, instrument, trace, warn};
 use uuid::Uuid;
 
 pub use crate::types::{
@@ -28,20 +32,19 @@ pub enum Error {
     },
 }
 
+const SYSTEM_DICT_DIR: &str = "data/system/unidic-cwj-3.1.0";
+
 fn open_blob(s: &str) -> Result<Blob> {
-    Blob::open(&format!("data/system/unidic-cwj-3.1.0/{}", s))
+    Blob::open(&format!("{SYSTEM_DICT_DIR}/{s}"))
         .whatever_context(format!("loading blob file {s}"))
 }
 
-fn load_mecab_dict(user_dict: Vec<(String, String, FormatToken)>) -> Result<Dict> {
+fn load_mecab_dict() -> Result<Dict> {
     let sysdic = open_blob("sys.dic")?;
     let unkdic = open_blob("unk.dic")?;
     let matrix = open_blob("matrix.bin")?;
     let charbin = open_blob("char.bin")?;
-    let mut dict = Dict::load(sysdic, unkdic, matrix, charbin).whatever_context("loading dict")?;
-    dict.load_user_dictionary(user_dict)
-        .whatever_context("loading userdict")?;
-    Ok(dict)
+    Dict::load(sysdic, unkdic, matrix, charbin).whatever_context("loading dict")
 }
 
 pub struct UnidicSession {
@@ -58,6 +61,10 @@ pub struct AnalysisResult<'a> {
 
 const NAME_COST: i64 = 5000;
 
+/// Goes up whenever names are compiled into something else, so that user
+/// dictionaries compiled before are not reused.
+const USER_DICT_VERSION: u32 = 1;
+
 const SEI_LEFT: u16 = 2793;
 const SEI_RIGHT: u16 = 11570;
 const SEI_POS: &'static str = "名詞,普通名詞,人名,姓";
@@ -84,18 +91,83 @@ pub enum LemmaSource {
 }
 
 impl UnidicSession {
+    /// Start a session with the names in `user_dict_path` as the user
+    /// dictionary. They are compiled into a dictionary next to them, with the
+    /// extension `dic`, which later sessions map instead for as long as it
+    /// was compiled from the same names for the same system dictionary. What
+    /// it was compiled from is noted in a file with the extension `dic.key`.
     pub fn new(user_dict_path: impl AsRef<Path>) -> Result<Self> {
-        let user_dict_raw = Self::build_from_names(user_dict_path)?;
-        let user_dict = user_dict_raw
-            .into_iter()
-            .map(|(l, r, c, i, s, f)| UserDict::build_entry(l, r, c, i, &s, &f))
-            .collect();
-        let dict = load_mecab_dict(user_dict).whatever_context("loading unidic")?;
+        let mut dict = load_mecab_dict().whatever_context("loading unidic")?;
+        Self::load_user_dict(&mut dict, user_dict_path.as_ref())?;
         let cache = Cache::new();
         info!("initialised unidic session");
         Ok(Self { dict, cache })
     }
 
+    /// Identifies everything a compiled user dictionary depends on: the
+    /// names, the system dictionary it goes with and how names are compiled.
+    fn user_dict_key(dict: &Dict, names_path: &Path) -> Result<String> {
+        let names = std::fs::read(names_path).whatever_context("reading userdict")?;
+        let sys_dic = std::fs::metadata(format!("{SYSTEM_DICT_DIR}/sys.dic"))
+            .whatever_context("reading sys.dic metadata")?;
+        let mut hasher = DefaultHasher::new();
+        USER_DICT_VERSION.hash(&mut hasher);
+        names.hash(&mut hasher);
+        dict.contexts().hash(&mut hasher);
+        sys_dic.len().hash(&mut hasher);
+        sys_dic.modified().ok().hash(&mut hasher);
+        Ok(format!("{:016x}", hasher.finish()))
+    }
+
+    fn load_user_dict(dict: &mut Dict, names_path: &Path) -> Result<()> {
+        let compiled_path = names_path.with_extension("dic");
+        let key_path = names_path.with_extension("dic.key");
+        let key = Self::user_dict_key(dict, names_path)?;
+        if std::fs::read_to_string(&key_path).is_ok_and(|compiled_key| compiled_key == key) {
+            let loaded: Result<()> = Blob::open(&compiled_path)
+                .whatever_context("mapping compiled userdict")
+                .and_then(|blob| {
+                    dict.load_user_dictionary(blob)
+                        .whatever_context("loading compiled userdict")
+                });
+            match loaded {
+                Ok(()) => return Ok(()),
+                Err(e) => warn!("recompiling {}: {e}", compiled_path.display()),
+            }
+        }
+
+        let entries: Vec<_> = Self::build_from_names(names_path)?
+            .into_iter()
+            .map(
+                |(left_context, right_context, cost, _, surface, feature)| DictEntry {
+                    surface,
+                    left_context,
+                    right_context,
+                    cost: cost.clamp(i16::MIN as i64, i16::MAX as i64) as i16,
+                    feature,
+                },
+            )
+            .collect();
+        let mut compiled = Vec::new();
+        write_dictionary(&mut compiled, DictKind::User, &entries, dict.contexts())
+            .whatever_context("compiling userdict")?;
+        // The old key goes first and the new one last, so that neither ever
+        // vouches for a dictionary that was not written in full.
+        let _ = std::fs::remove_file(&key_path);
+        match std::fs::write(&compiled_path, &compiled)
+            .and_then(|()| std::fs::write(&key_path, &key))
+        {
+            Ok(()) => info!(
+                "compiled {} names into {}",
+                entries.len(),
+                compiled_path.display()
+            ),
+            Err(e) => warn!("cannot write {}: {e}", compiled_path.display()),
+        }
+        dict.load_user_dictionary(Blob::new(compiled))
+            .whatever_context("loading userdict")
+    }
+
     fn build_unidic_feature_string(
         id: u32,
         pos_str: &str,
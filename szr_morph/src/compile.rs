@@ -0,0 +1,801 @@
+//! Compiling mecab dictionary sources into the binary files [`Dict::load`]
+//! reads, without mecab-dict-index.
+//!
+//! A source directory holds the entries as `*.csv` files, the connection costs
+//! as `matrix.def`, the character categories as `char.def` and the entries for
+//! unknown words as `unk.def`, all in UTF-8. Entries are lines of a surface,
+//! left context ID, right context ID and cost, followed by the feature string,
+//! which is everything past the fourth comma. Fields other than the feature
+//! may be quoted, as UniDic does for surfaces with a comma in them. Sources in
+//! other encodings, such as IPADIC in EUC-JP, have to be converted first.
+//!
+//! [`Dict::load`]: crate::Dict::load
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use snafu::{ensure, ResultExt};
+use tracing::{info, instrument};
+
+use crate::{
+    dart::DoubleArrayTrieBuilder, InvalidDictionaryError, InvalidSourceError, IoError, Result,
+};
+
+/// Identifies a mecab dictionary file. mecab stores it xored with the size of
+/// the file.
+const DICTIONARY_MAGIC: u32 = 0xef71_8f77;
+const DICTIONARY_VERSION: u32 = 0x66;
+/// Costs are stored in 16 bits. mecab clamps them to this.
+const MAX_COST: i64 = 32767;
+/// The number of tokens with the same surface is stored in a byte.
+const MAX_HOMONYMS: usize = 0xFF;
+/// [`crate::unkchar::load_char_bin`] reads this many characters, starting
+/// from U+0000.
+const CHAR_COUNT: usize = 0xFFFF;
+/// Categories are stored as a bitfield this wide.
+const MAX_CATEGORIES: usize = 18;
+/// Category names are stored null-padded to this many bytes.
+const CATEGORY_NAME_LEN: usize = 0x20;
+
+/// What a dictionary file is for, as recorded in its header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DictKind {
+    System = 0,
+    User = 1,
+    Unknown = 2,
+}
+
+/// An entry of a dictionary source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DictEntry {
+    pub surface: String,
+    pub left_context: u16,
+    pub right_context: u16,
+    pub cost: i16,
+    pub feature: String,
+}
+
+/// Split off the first `n` comma-separated fields of `line`, unquoting them
+/// as mecab does, and return them along with the rest of the line.
+fn split_fields(line: &str, n: usize) -> Option<(Vec<String>, &str)> {
+    let mut fields = Vec::with_capacity(n);
+    let mut rest = line;
+    for _ in 0..n {
+        rest = rest.trim_start_matches([' ', '\t']);
+        let field = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut field = String::new();
+            let mut chars = quoted.char_indices();
+            let end = loop {
+                match chars.next()? {
+                    (i, '"') if quoted[i + 1..].starts_with('"') => {
+                        field.push('"');
+                        chars.next();
+                    }
+                    (i, '"') => break i + 1,
+                    (_, c) => field.push(c),
+                }
+            };
+            rest = quoted[end..].strip_prefix(',')?;
+            field
+        } else {
+            let (field, after) = rest.split_once(',')?;
+            rest = after;
+            field.to_owned()
+        };
+        fields.push(field);
+    }
+    Some((fields, rest))
+}
+
+/// The lines of a source, without their line endings. Sources have to be
+/// UTF-8, which not every dictionary comes in: IPADIC is EUC-JP, for one.
+fn source_lines<'a, R: BufRead + 'a>(
+    name: &'a str,
+    input: R,
+) -> impl Iterator<Item = Result<String>> + 'a {
+    input.split(b'\n').enumerate().map(move |(i, line)| {
+        let mut line = line.context(IoError)?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let Ok(line) = String::from_utf8(line) else {
+            return InvalidSourceError {
+                file: name,
+                line: i + 1,
+                message: "not UTF-8; convert the sources first, e.g. with iconv",
+            }
+            .fail();
+        };
+        Ok(line)
+    })
+}
+
+/// Read the entries of a dictionary source, `name` being what to call it in
+/// errors.
+pub fn read_entries<R: BufRead>(name: &str, input: R) -> Result<Vec<DictEntry>> {
+    let mut entries = Vec::new();
+    for (i, line) in source_lines(name, input).enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = |message: &'static str| InvalidSourceError {
+            file: name,
+            line: i + 1,
+            message,
+        };
+        let Some((fields, feature)) = split_fields(&line, 4) else {
+            return invalid("expected a surface, two context IDs, a cost and a feature").fail();
+        };
+        let [surface, left_context, right_context, cost] = &fields[..] else {
+            unreachable!();
+        };
+        ensure!(!surface.is_empty(), invalid("empty surface"));
+        let Ok(left_context) = left_context.trim().parse() else {
+            return invalid("invalid left context ID").fail();
+        };
+        let Ok(right_context) = right_context.trim().parse() else {
+            return invalid("invalid right context ID").fail();
+        };
+        let Ok(cost) = cost.trim().parse::<i64>() else {
+            return invalid("invalid cost").fail();
+        };
+        entries.push(DictEntry {
+            surface: surface.clone(),
+            left_context,
+            right_context,
+            cost: cost.clamp(-MAX_COST, MAX_COST) as i16,
+            feature: feature.to_owned(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Write a dictionary file of the given kind for `entries`, whose context IDs
+/// have to be within the connection matrix they are used with.
+///
+/// Entries with the same surface stay in the order they are given in.
+pub fn write_dictionary<W: Write>(
+    output: &mut W,
+    kind: DictKind,
+    entries: &[DictEntry],
+    (left_contexts, right_contexts): (u16, u16),
+) -> Result<()> {
+    for entry in entries {
+        ensure!(
+            entry.left_context < left_contexts && entry.right_context < right_contexts,
+            InvalidDictionaryError {
+                message: format!(
+                    "contexts of {:?} are outside a {left_contexts}x{right_contexts} matrix",
+                    entry.surface
+                ),
+            }
+        );
+    }
+
+    let mut order: Vec<_> = (0..entries.len()).collect();
+    order.sort_by(|&a, &b| entries[a].surface.cmp(&entries[b].surface));
+
+    let mut keys: Vec<&[u8]> = Vec::new();
+    let mut values = Vec::new();
+    for (first, group) in order
+        .chunk_by(|&a, &b| entries[a].surface == entries[b].surface)
+        .scan(0, |first, group| {
+            let this = *first;
+            *first += group.len();
+            Some((this, group))
+        })
+    {
+        let surface = &entries[group[0]].surface;
+        ensure!(
+            group.len() <= MAX_HOMONYMS,
+            InvalidDictionaryError {
+                message: format!("more than {MAX_HOMONYMS} entries for {surface:?}"),
+            }
+        );
+        ensure!(
+            first < 1 << 23,
+            InvalidDictionaryError {
+                message: "too many entries",
+            }
+        );
+        keys.push(surface.as_bytes());
+        values.push(((first as u32) << 8) | group.len() as u32);
+    }
+    let links = DoubleArrayTrieBuilder::new(&keys, &values).build();
+
+    let mut features = Vec::new();
+    let mut feature_offsets = HashMap::new();
+    let mut tokens = Vec::with_capacity(order.len() * 16);
+    for &index in &order {
+        let entry = &entries[index];
+        let offset = *feature_offsets
+            .entry(entry.feature.as_str())
+            .or_insert_with(|| {
+                let offset = features.len() as u32;
+                features.extend_from_slice(entry.feature.as_bytes());
+                features.push(0);
+                offset
+            });
+        tokens.extend_from_slice(&entry.left_context.to_le_bytes());
+        tokens.extend_from_slice(&entry.right_context.to_le_bytes());
+        // part-of-speech ID, which nothing here uses
+        tokens.extend_from_slice(&0u16.to_le_bytes());
+        tokens.extend_from_slice(&entry.cost.to_le_bytes());
+        tokens.extend_from_slice(&offset.to_le_bytes());
+        // compound word information, likewise
+        tokens.extend_from_slice(&0u32.to_le_bytes());
+    }
+
+    let link_bytes = links.len() * 8;
+    let size = 0x48 + link_bytes + tokens.len() + features.len();
+    ensure!(
+        u32::try_from(size).is_ok(),
+        InvalidDictionaryError {
+            message: "dictionary too large",
+        }
+    );
+
+    let mut header = Vec::with_capacity(0x48);
+    for field in [
+        DICTIONARY_MAGIC ^ size as u32,
+        DICTIONARY_VERSION,
+        kind as u32,
+        entries.len() as u32,
+        left_contexts as u32,
+        right_contexts as u32,
+        link_bytes as u32,
+        tokens.len() as u32,
+        features.len() as u32,
+        0,
+    ] {
+        header.extend_from_slice(&field.to_le_bytes());
+    }
+    let mut charset = [0u8; 0x20];
+    charset[..5].copy_from_slice(b"utf-8");
+    header.extend_from_slice(&charset);
+
+    output.write_all(&header).context(IoError)?;
+    for (base, check) in links {
+        output.write_all(&base.to_le_bytes()).context(IoError)?;
+        output.write_all(&check.to_le_bytes()).context(IoError)?;
+    }
+    output.write_all(&tokens).context(IoError)?;
+    output.write_all(&features).context(IoError)?;
+    Ok(())
+}
+
+/// Compile `matrix.def`, a line with the number of left and right contexts
+/// followed by a line with a left context ID, right context ID and cost for
+/// each connection. Connections it leaves out cost nothing. Returns the
+/// number of left and right contexts.
+pub fn compile_matrix<R: BufRead, W: Write>(
+    name: &str,
+    input: R,
+    output: &mut W,
+) -> Result<(u16, u16)> {
+    let mut size = None;
+    let mut costs = Vec::new();
+    for (i, line) in source_lines(name, input).enumerate() {
+        let line = line?;
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let invalid = |message: &'static str| InvalidSourceError {
+            file: name,
+            line: i + 1,
+            message,
+        };
+        let Some((left_contexts, _)) = size else {
+            let [left, right] = fields[..] else {
+                return invalid("expected the number of left and right contexts").fail();
+            };
+            let (Ok(left), Ok(right)) = (left.parse::<u16>(), right.parse::<u16>()) else {
+                return invalid("invalid number of contexts").fail();
+            };
+            size = Some((left, right));
+            costs = vec![0i16; left as usize * right as usize];
+            continue;
+        };
+        let [left, right, cost] = fields[..] else {
+            return invalid("expected two context IDs and a cost").fail();
+        };
+        let (Ok(left), Ok(right), Ok(cost)) = (
+            left.parse::<usize>(),
+            right.parse::<usize>(),
+            cost.parse::<i16>(),
+        ) else {
+            return invalid("invalid context ID or cost").fail();
+        };
+        let index = left + left_contexts as usize * right;
+        ensure!(
+            left < left_contexts as usize && index < costs.len(),
+            invalid("context ID outside the matrix")
+        );
+        costs[index] = cost;
+    }
+    let Some((left_contexts, right_contexts)) = size else {
+        return InvalidDictionaryError {
+            message: format!("{name} is empty"),
+        }
+        .fail();
+    };
+
+    output
+        .write_all(&left_contexts.to_le_bytes())
+        .context(IoError)?;
+    output
+        .write_all(&right_contexts.to_le_bytes())
+        .context(IoError)?;
+    for cost in costs {
+        output.write_all(&cost.to_le_bytes()).context(IoError)?;
+    }
+    Ok((left_contexts, right_contexts))
+}
+
+/// Compile `char.def`. Lines give either a category, as its name followed by
+/// whether to always make unknown tokens for it, whether to group characters
+/// of it together and how many characters to make prefixes of, or a range of
+/// characters and the categories they are in, the first one being the one
+/// that counts when a token starts with them. Anything after a `#` is a
+/// comment. Characters in no range are in `DEFAULT`, which has to exist.
+///
+/// Returns the names of the categories, for checking `unk.def` against.
+pub fn compile_char_def<R: BufRead, W: Write>(
+    name: &str,
+    input: R,
+    output: &mut W,
+) -> Result<Vec<String>> {
+    let mut categories: Vec<(String, u32)> = Vec::new();
+    let mut ranges = Vec::new();
+    for (i, line) in source_lines(name, input).enumerate() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default();
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+        let invalid = |message: &'static str| InvalidSourceError {
+            file: name,
+            line: i + 1,
+            message,
+        };
+        if fields[0].starts_with("0x") {
+            let parse = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+            let range = match fields[0].split_once("..") {
+                Some((start, end)) => parse(start).zip(parse(end)),
+                None => parse(fields[0]).map(|c| (c, c)),
+            };
+            let Some((start, end)) = range.filter(|(start, end)| start <= end) else {
+                return invalid("invalid character range").fail();
+            };
+            ensure!(
+                fields.len() > 1,
+                invalid("character range without a category")
+            );
+            let names: Vec<_> = fields[1..].iter().map(|&name| name.to_owned()).collect();
+            ranges.push((i + 1, start, end, names));
+        } else {
+            let [category, invoke, group, length] = fields[..] else {
+                return invalid("expected a category name, invoke, group and length").fail();
+            };
+            let (Ok(invoke), Ok(group), Ok(length)) = (
+                invoke.parse::<u32>(),
+                group.parse::<u32>(),
+                length.parse::<u32>(),
+            ) else {
+                return invalid("invalid invoke, group or length").fail();
+            };
+            ensure!(
+                invoke <= 1 && group <= 1 && length <= 0xF,
+                invalid("invoke and group have to be 0 or 1, and length at most 15")
+            );
+            ensure!(
+                category.len() < CATEGORY_NAME_LEN,
+                invalid("category name too long")
+            );
+            ensure!(
+                !categories.iter().any(|(name, _)| name == category),
+                invalid("category defined twice")
+            );
+            ensure!(
+                categories.len() < MAX_CATEGORIES,
+                invalid("too many categories")
+            );
+            let id = categories.len() as u32;
+            let info = (1 << id) | (id << 18) | (length << 26) | (group << 30) | (invoke << 31);
+            categories.push((category.to_owned(), info));
+        }
+    }
+
+    let category = |name: &str| categories.iter().find(|(n, _)| n == name).map(|c| c.1);
+    let Some(default) = category("DEFAULT") else {
+        return InvalidDictionaryError {
+            message: format!("{name} does not define category DEFAULT"),
+        }
+        .fail();
+    };
+    let mut chars = vec![default; CHAR_COUNT];
+    for (line, start, end, names) in ranges {
+        // The first category decides how to group, the rest only add to the
+        // categories the characters can be grouped into.
+        let mut info = 0;
+        for (j, category_name) in names.iter().enumerate() {
+            let Some(c) = category(category_name) else {
+                return InvalidSourceError {
+                    file: name,
+                    line,
+                    message: format!("category {category_name} is not defined"),
+                }
+                .fail();
+            };
+            info |= if j == 0 { c } else { c & 0x0003_FFFF };
+        }
+        // Characters past the ones stored are left out.
+        if let Some(chars) = chars.get_mut(start as usize..=(end as usize).min(CHAR_COUNT - 1)) {
+            chars.fill(info);
+        }
+    }
+
+    output
+        .write_all(&(categories.len() as u32).to_le_bytes())
+        .context(IoError)?;
+    for (category, _) in &categories {
+        let mut padded = [0u8; CATEGORY_NAME_LEN];
+        padded[..category.len()].copy_from_slice(category.as_bytes());
+        output.write_all(&padded).context(IoError)?;
+    }
+    for info in chars {
+        output.write_all(&info.to_le_bytes()).context(IoError)?;
+    }
+    Ok(categories.into_iter().map(|(name, _)| name).collect())
+}
+
+fn open_source(path: &Path) -> Result<BufReader<File>> {
+    File::open(path).context(IoError).map(BufReader::new)
+}
+
+fn create_output(path: &Path) -> Result<BufWriter<File>> {
+    File::create(path).context(IoError).map(BufWriter::new)
+}
+
+/// Compile the dictionary sources in `source` into `sys.dic`, `unk.dic`,
+/// `matrix.bin` and `char.bin` in `output`, replacing any that are there.
+#[instrument(skip_all, level = "debug")]
+pub fn compile_system_dictionary(source: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<()> {
+    let (source, output) = (source.as_ref(), output.as_ref());
+
+    let mut matrix = create_output(&output.join("matrix.bin"))?;
+    let contexts = compile_matrix(
+        "matrix.def",
+        open_source(&source.join("matrix.def"))?,
+        &mut matrix,
+    )?;
+    matrix.flush().context(IoError)?;
+
+    let mut char_bin = create_output(&output.join("char.bin"))?;
+    let categories = compile_char_def(
+        "char.def",
+        open_source(&source.join("char.def"))?,
+        &mut char_bin,
+    )?;
+    char_bin.flush().context(IoError)?;
+
+    let unknown = read_entries("unk.def", open_source(&source.join("unk.def"))?)?;
+    if let Some(entry) = unknown.iter().find(|e| !categories.contains(&e.surface)) {
+        return InvalidDictionaryError {
+            message: format!(
+                "unk.def has entries for undefined category {}",
+                entry.surface
+            ),
+        }
+        .fail();
+    }
+    let mut unk_dic = create_output(&output.join("unk.dic"))?;
+    write_dictionary(&mut unk_dic, DictKind::Unknown, &unknown, contexts)?;
+    unk_dic.flush().context(IoError)?;
+
+    let mut csv_paths = Vec::new();
+    for dir_entry in std::fs::read_dir(source).context(IoError)? {
+        let path = dir_entry.context(IoError)?.path();
+        if path.extension().is_some_and(|ext| ext == "csv") {
+            csv_paths.push(path);
+        }
+    }
+    csv_paths.sort();
+    let mut entries = Vec::new();
+    for path in &csv_paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        entries.extend(read_entries(&name, open_source(path)?)?);
+    }
+    let mut sys_dic = create_output(&output.join("sys.dic"))?;
+    write_dictionary(&mut sys_dic, DictKind::System, &entries, contexts)?;
+    sys_dic.flush().context(IoError)?;
+
+    info!(
+        "compiled {} entries from {} files and {} unknown word entries",
+        entries.len(),
+        csv_paths.len(),
+        unknown.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{blob::Blob, dart::load_mecab_dart_file, unkchar::load_char_bin, Dict, Error};
+
+    const LEX: &str = "これ,1,1,100,代名詞,コレ
+を,2,2,50,助詞,ヲ
+持つ,3,3,200,動詞,モツ
+持,4,4,900,名詞,モチ
+持,1,4,950,名詞,ジ
+\"1,000\",4,4,10,\"名詞,数詞\",センイチ
+";
+    const MATRIX: &str = "5 5\n0 1 -10\n1 2 20\n\n4 0 30\n";
+    const CHAR_DEF: &str = "# categories
+DEFAULT 0 1 0
+SPACE 0 1 0
+HIRAGANA 0 1 2
+KANJI 0 0 2
+
+0x0020 SPACE
+0x3041..0x3096 HIRAGANA  # ぁ..ゖ
+0x4E00..0x9FA5 KANJI HIRAGANA
+";
+    const UNK_DEF: &str = "DEFAULT,4,4,5000,記号
+SPACE,4,4,5000,空白
+HIRAGANA,4,4,5000,名詞
+KANJI,4,4,5000,名詞
+";
+
+    fn compile_entries(kind: DictKind, entries: &[DictEntry]) -> Blob {
+        let mut out = Vec::new();
+        write_dictionary(&mut out, kind, entries, (5, 5)).unwrap();
+        Blob::new(out)
+    }
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(
+            split_fields("a,1,2,3,x,y", 4),
+            Some((
+                vec!["a", "1", "2", "3"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                "x,y"
+            ))
+        );
+        assert_eq!(
+            split_fields("\"a,\"\"b\",1,2,3,\"x,y\"", 4),
+            Some((
+                vec!["a,\"b", "1", "2", "3"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                "\"x,y\""
+            ))
+        );
+        assert_eq!(split_fields("a,1,2", 4), None);
+        assert_eq!(split_fields("\"a,1,2,3,x", 4), None);
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let entries = read_entries("lex.csv", Cursor::new(LEX)).unwrap();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(
+            entries[5],
+            DictEntry {
+                surface: "1,000".to_owned(),
+                left_context: 4,
+                right_context: 4,
+                cost: 10,
+                feature: "\"名詞,数詞\",センイチ".to_owned(),
+            }
+        );
+
+        let err =
+            read_entries("lex.csv", Cursor::new("これ,1,1,100,x\nを,2,x,50,y\n")).unwrap_err();
+        assert_eq!(err.to_string(), "lex.csv, line 2: invalid right context ID");
+    }
+
+    #[test]
+    fn test_compiled_dictionary_lookup() {
+        // enough surfaces sharing prefixes to make the trie branch out
+        let mut entries = read_entries("lex.csv", Cursor::new(LEX)).unwrap();
+        for i in 0..2000 {
+            for (j, surface) in [format!("語{i}"), format!("{i}語"), format!("語{i}語")]
+                .into_iter()
+                .enumerate()
+            {
+                entries.push(DictEntry {
+                    surface,
+                    left_context: (i % 5) as u16,
+                    right_context: j as u16,
+                    cost: i as i16,
+                    feature: format!("{i},{j}"),
+                });
+            }
+        }
+
+        let dict = load_mecab_dart_file(compile_entries(DictKind::System, &entries)).unwrap();
+        assert_eq!(dict.tokens.len(), entries.len());
+        for entry in &entries {
+            let tokens = dict.dic_get(&entry.surface).unwrap();
+            assert!(tokens
+                .iter()
+                .any(|token| token.left_context == entry.left_context
+                    && token.right_context == entry.right_context
+                    && token.cost == entry.cost as i64
+                    && dict.feature_get(token.feature_offset) == entry.feature));
+        }
+        // homonyms keep their order
+        let tokens = dict.dic_get("持").unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(dict.feature_get(tokens[0].feature_offset), "名詞,モチ");
+        assert_eq!(dict.feature_get(tokens[1].feature_offset), "名詞,ジ");
+        assert!(dict.dic_get("語").is_none());
+        assert!(dict.dic_get("語2000").is_none());
+        // prefixes of keys can be walked through but hold no tokens
+        let node = dict.walk(dict.root(), "語".as_bytes()).unwrap();
+        assert!(dict.tokens_at(node).is_none());
+        let node = dict.walk(node, "19".as_bytes()).unwrap();
+        assert_eq!(dict.tokens_at(node).unwrap().len(), 1);
+        assert!(dict.walk(node, "語".as_bytes()).is_some());
+        assert!(dict.walk(node, "x".as_bytes()).is_none());
+
+        let empty = load_mecab_dart_file(compile_entries(DictKind::User, &[])).unwrap();
+        assert!(empty.dic_get("これ").is_none());
+        assert!(empty.walk(empty.root(), &[0]).is_none());
+    }
+
+    #[test]
+    fn test_compiled_dictionary_rejects_bad_contexts() {
+        let entries = vec![DictEntry {
+            surface: "これ".to_owned(),
+            left_context: 5,
+            right_context: 0,
+            cost: 0,
+            feature: String::new(),
+        }];
+        let mut out = Vec::new();
+        assert!(write_dictionary(&mut out, DictKind::System, &entries, (5, 5)).is_err());
+    }
+
+    #[test]
+    fn test_compiled_char_def() {
+        let mut out = Vec::new();
+        let categories = compile_char_def("char.def", Cursor::new(CHAR_DEF), &mut out).unwrap();
+        assert_eq!(categories, ["DEFAULT", "SPACE", "HIRAGANA", "KANJI"]);
+        assert_eq!(out.len(), 4 + 4 * 0x20 + 4 * 0xFFFF);
+
+        let chars = load_char_bin(&mut Cursor::new(out)).unwrap();
+        assert_eq!(chars.get_type('a').name, "DEFAULT");
+        assert_eq!(chars.get_type(' ').name, "SPACE");
+        let hiragana = chars.get_type('を');
+        assert_eq!(hiragana.name, "HIRAGANA");
+        assert!(hiragana.greedy_group);
+        assert_eq!(hiragana.prefix_group_len, 2);
+        let kanji = chars.get_type('持');
+        assert_eq!(kanji.name, "KANJI");
+        assert!(!kanji.greedy_group);
+        assert!(chars.has_type('持', 2));
+        assert!(!chars.has_type('を', 3));
+
+        let err = compile_char_def("char.def", Cursor::new("SPACE 0 1 0\n"), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "char.def does not define category DEFAULT");
+        let err = compile_char_def(
+            "char.def",
+            Cursor::new("DEFAULT 0 1 0\n0x0020 SPACE\n"),
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "char.def, line 2: category SPACE is not defined"
+        );
+    }
+
+    #[test]
+    fn test_compiled_matrix() {
+        let mut out = Vec::new();
+        let contexts = compile_matrix("matrix.def", Cursor::new(MATRIX), &mut out).unwrap();
+        assert_eq!(contexts, (5, 5));
+        assert_eq!(out.len(), 4 + 2 * 25);
+        let cost = |left: usize, right: usize| {
+            let at = 4 + 2 * (left + 5 * right);
+            i16::from_le_bytes([out[at], out[at + 1]])
+        };
+        assert_eq!(cost(0, 1), -10);
+        assert_eq!(cost(1, 2), 20);
+        assert_eq!(cost(4, 0), 30);
+        assert_eq!(cost(2, 1), 0);
+
+        assert!(
+            compile_matrix("matrix.def", Cursor::new("5 5\n5 0 1\n"), &mut Vec::new()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_tokenise_with_compiled_dictionary() {
+        let mut matrix = Vec::new();
+        let contexts = compile_matrix("matrix.def", Cursor::new(MATRIX), &mut matrix).unwrap();
+        let mut char_bin = Vec::new();
+        compile_char_def("char.def", Cursor::new(CHAR_DEF), &mut char_bin).unwrap();
+        let mut unk_dic = Vec::new();
+        let unknown = read_entries("unk.def", Cursor::new(UNK_DEF)).unwrap();
+        write_dictionary(&mut unk_dic, DictKind::Unknown, &unknown, contexts).unwrap();
+        let mut sys_dic = Vec::new();
+        let entries = read_entries("lex.csv", Cursor::new(LEX)).unwrap();
+        write_dictionary(&mut sys_dic, DictKind::System, &entries, contexts).unwrap();
+
+        let mut dict = Dict::load(
+            Blob::new(sys_dic),
+            Blob::new(unk_dic),
+            Blob::new(matrix),
+            Blob::new(char_bin),
+        )
+        .unwrap();
+        let text = "これを持つ";
+        let (tokens, _) = dict.tokenise(text).unwrap();
+        let surfaces: Vec<_> = tokens.iter().map(|t| t.get_text(text)).collect();
+        assert_eq!(surfaces, ["これ", "を", "持つ"]);
+        assert_eq!(tokens[2].get_feature(&dict), Some("動詞,モツ"));
+
+        let text = "ぬを";
+        let (tokens, _) = dict.tokenise(text).unwrap();
+        assert_eq!(tokens[0].kind, crate::TokenType::UNK);
+        assert_eq!(tokens[0].get_feature(&dict), Some("名詞"));
+
+        let mut user_dic = Vec::new();
+        let user_entries =
+            read_entries("user.csv", Cursor::new("ぬを,4,4,-100,固有名詞\n")).unwrap();
+        write_dictionary(&mut user_dic, DictKind::User, &user_entries, contexts).unwrap();
+        dict.load_user_dictionary(Blob::new(user_dic)).unwrap();
+        let (tokens, _) = dict.tokenise(text).unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].kind, crate::TokenType::User);
+        assert_eq!(tokens[0].get_feature(&dict), Some("固有名詞"));
+        // the system dictionary still applies alongside it
+        let (tokens, _) = dict.tokenise("これぬを").unwrap();
+        assert_eq!(tokens.len(), 2);
+
+        let mut mismatched = Vec::new();
+        write_dictionary(&mut mismatched, DictKind::User, &user_entries, (6, 6)).unwrap();
+        assert!(dict.load_user_dictionary(Blob::new(mismatched)).is_err());
+    }
+
+    #[test]
+    fn test_read_entries_rejects_other_encodings() {
+        // これ in EUC-JP, as IPADIC has it
+        let euc_jp = b"\xa4\xb3\xa4\xec,1,1,0,x\n";
+        let lines = [&b"\xe3\x81\x93,1,1,0,x\n"[..], euc_jp].concat();
+        let err = read_entries("Noun.csv", Cursor::new(lines)).unwrap_err();
+        assert!(matches!(err, Error::InvalidSource { line: 2, .. }), "{err}");
+        assert!(err.to_string().contains("not UTF-8"), "{err}");
+    }
+
+    #[test]
+    fn test_userdict_load() -> Result<()> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/userdict.csv");
+        let userdic = File::open(path).context(IoError)?;
+        let entries = read_entries("userdict.csv", BufReader::new(userdic))?;
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[2].surface, "1,000円");
+        let mut out = Vec::new();
+        write_dictionary(&mut out, DictKind::User, &entries, (u16::MAX, u16::MAX))?;
+        load_mecab_dart_file(Blob::new(out))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+//! Constraints on where a text may be split, for when the caller already knows
+//! better than the dictionary, such as when a user has corrected a split or
+//! the text came grouped in `<ruby>`.
+//!
+//! Constraints prune the lattice before the lowest-cost path through it is
+//! found, so the result is the best split among the ones that honour them.
+
+use std::ops::Range;
+
+use tracing::error;
+
+use crate::{Dict, Token, TokenType, TokeniseError};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    /// One token must end, and the next one start, at this byte offset.
+    Boundary(usize),
+    /// These bytes must be a single token. If `original_id` is given, it must
+    /// be that dictionary entry. Otherwise, if the dictionary has no entry
+    /// spelt like that, an unknown token is made for it.
+    Span {
+        range: Range<usize>,
+        original_id: Option<u32>,
+    },
+}
+
+impl Constraint {
+    fn allows(&self, token: &Token) -> bool {
+        let range = &token.range;
+        match self {
+            Constraint::Boundary(at) => !(range.start < *at && *at < range.end),
+            Constraint::Span {
+                range: span,
+                original_id,
+            } => {
+                if range == span {
+                    !original_id.is_some_and(|id| token.original_id != id)
+                } else {
+                    range.end <= span.start || span.end <= range.start
+                }
+            }
+        }
+    }
+
+    fn is_valid_for(&self, text: &str) -> bool {
+        let in_text = |at: usize| at <= text.len() && text.is_char_boundary(at);
+        match self {
+            Constraint::Boundary(at) => in_text(*at),
+            Constraint::Span { range, .. } => {
+                range.start < range.end && in_text(range.start) && in_text(range.end)
+            }
+        }
+    }
+}
+
+/// Drop every candidate token the constraints rule out, making unknown tokens
+/// for spans nothing in the dictionary covers.
+pub(crate) fn apply_constraints<'a>(
+    dict: &'a Dict,
+    text: &str,
+    constraints: &[Constraint],
+    tokens: &mut Vec<Token<'a>>,
+) -> Result<(), TokeniseError> {
+    for constraint in constraints {
+        if !constraint.is_valid_for(text) {
+            error!(
+                "constraint {constraint:?} does not fit text of length {}",
+                text.len()
+            );
+            return Err(TokeniseError { _dummy: () });
+        }
+        let Constraint::Span {
+            range,
+            original_id: None,
+        } = constraint
+        else {
+            continue;
+        };
+        if tokens.iter().any(|token| token.range == *range) {
+            continue;
+        }
+        let Some(first_char) = text[range.clone()].chars().next() else {
+            continue;
+        };
+        let char_type = dict.unk_data.get_type(first_char);
+        let Some(format_token) = [char_type.name.as_str(), "DEFAULT"]
+            .into_iter()
+            .find_map(|name| dict.unk_dic.dic_get(name)?.iter().next())
+        else {
+            error!("unknown chars dictionary has no token for {range:?}");
+            return Err(TokeniseError { _dummy: () });
+        };
+        let at = tokens.partition_point(|token| token.rank as usize <= range.start);
+        tokens.insert(
+            at,
+            Token::new(format_token, range.start, range.clone(), TokenType::UNK),
+        );
+    }
+
+    tokens.retain(|token| constraints.iter().all(|c| c.allows(token)));
+    Ok(())
+}
+
+/// Whether the path goes all the way from `start` to `end` without gaps. When
+/// constraints leave no such path, the search falls back to whatever it
+/// reached last.
+pub(crate) fn path_is_complete(tokens: &[Token], path: &[u32], start: u32, end: usize) -> bool {
+    let (Some(&first), Some(&last)) = (path.first(), path.last()) else {
+        return false;
+    };
+    tokens[first as usize].rank == start
+        && tokens[last as usize].range.end == end
+        && path.windows(2).all(|pair| {
+            tokens[pair[1] as usize].rank as usize == tokens[pair[0] as usize].range.end
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FormatToken;
+
+    const FORMAT_TOKEN: FormatToken = FormatToken {
+        left_context: 0,
+        right_context: 0,
+        pos: 0,
+        cost: 0,
+        original_id: 7,
+        feature_offset: 0,
+    };
+
+    fn token(range: Range<usize>) -> Token<'static> {
+        Token::new(&FORMAT_TOKEN, range.start, range, TokenType::Normal)
+    }
+
+    #[test]
+    fn test_constraint_allows() {
+        let boundary = Constraint::Boundary(3);
+        assert!(boundary.allows(&token(0..3)));
+        assert!(boundary.allows(&token(3..6)));
+        assert!(!boundary.allows(&token(0..6)));
+
+        let span = Constraint::Span {
+            range: 3..9,
+            original_id: None,
+        };
+        assert!(span.allows(&token(3..9)));
+        assert!(span.allows(&token(0..3)));
+        assert!(span.allows(&token(9..12)));
+        assert!(!span.allows(&token(3..6)));
+        assert!(!span.allows(&token(6..12)));
+        assert!(!span.allows(&token(0..12)));
+
+        let entry = |original_id| Constraint::Span {
+            range: 3..9,
+            original_id: Some(original_id),
+        };
+        assert!(entry(7).allows(&token(3..9)));
+        assert!(!entry(8).allows(&token(3..9)));
+    }
+
+    #[test]
+    fn test_constraint_is_valid_for() {
+        let text = "完ッ全";
+        assert!(Constraint::Boundary(0).is_valid_for(text));
+        assert!(Constraint::Boundary(9).is_valid_for(text));
+        assert!(!Constraint::Boundary(1).is_valid_for(text));
+        assert!(!Constraint::Boundary(10).is_valid_for(text));
+        let span = |range| Constraint::Span {
+            range,
+            original_id: None,
+        };
+        assert!(span(0..9).is_valid_for(text));
+        assert!(!span(3..3).is_valid_for(text));
+        assert!(!span(0..4).is_valid_for(text));
+    }
+
+    #[test]
+    fn test_path_is_complete() {
+        let tokens = [token(0..3), token(0..6), token(3..6), token(6..9)];
+        assert!(path_is_complete(&tokens, &[0, 2, 3], 0, 9));
+        assert!(path_is_complete(&tokens, &[1, 3], 0, 9));
+        assert!(!path_is_complete(&tokens, &[0, 3], 0, 9));
+        assert!(!path_is_complete(&tokens, &[2, 3], 0, 9));
+        assert!(!path_is_complete(&tokens, &[0, 2], 0, 9));
+        assert!(!path_is_complete(&tokens, &[], 0, 9));
+    }
+}
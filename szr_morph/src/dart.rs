@@ -1,6 +1,5 @@
 use std::{
-    hash::{BuildHasherDefault, Hasher},
-    io::{Cursor, Read, Seek},
+    io::{Cursor, Seek},
     ops::Range,
 };
 
@@ -8,135 +7,256 @@ use snafu::ResultExt;
 use tracing::trace;
 
 use super::{blob::*, io::*, FormatToken};
-use crate::{HashMap, HashSet, Result};
+use crate::Result;
 
-type BuildNoopHasher = BuildHasherDefault<NoopHasher>;
+struct Node {
+    code: usize,
+    depth: usize,
+    left: usize,
+    right: usize,
+}
 
-#[derive(Default)]
-struct NoopHasher(u64);
+/// Builds the double-array trie of a mecab dictionary, for [`DartDict`] to
+/// walk. Adapted from the builder of the darts crate to work on bytes, and
+/// with each key mapping to a value of its own instead of its index.
+///
+/// A node is the base its children are offset from. The child for byte `b`
+/// sits at `base + b + 1` and the end of a key at `base` itself, with a check
+/// equal to `base` to mark whose they are. The base of an end of key holds the
+/// complement of the value.
+pub(crate) struct DoubleArrayTrieBuilder<'a> {
+    keys: &'a [&'a [u8]],
+    values: &'a [u32],
+
+    check: Vec<i32>,
+    base: Vec<i32>,
+    used: Vec<bool>,
+
+    size: usize,
+    next_check_pos: usize,
+    progress: usize,
+}
 
-impl Hasher for NoopHasher {
-    fn finish(&self) -> u64 {
-        self.0
+impl<'a> DoubleArrayTrieBuilder<'a> {
+    /// The keys have to be sorted and unique, and every value has to fit in
+    /// 31 bits.
+    pub fn new(keys: &'a [&'a [u8]], values: &'a [u32]) -> Self {
+        debug_assert_eq!(keys.len(), values.len());
+        debug_assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        debug_assert!(values.iter().all(|&value| value < 0x8000_0000));
+        DoubleArrayTrieBuilder {
+            keys,
+            values,
+            check: Vec::new(),
+            base: Vec::new(),
+            used: Vec::new(),
+            size: 0,
+            next_check_pos: 0,
+            progress: 0,
+        }
     }
 
-    fn write(&mut self, bytes: &[u8]) {
-        for &byte in bytes {
-            self.0 = (self.0 << 8) ^ (byte as u64);
+    /// The links as (base, check) pairs, ready to be written out.
+    pub fn build(mut self) -> Vec<(u32, u32)> {
+        self.resize(0x2000);
+        let root = Node {
+            code: 0,
+            depth: 0,
+            left: 0,
+            right: self.keys.len(),
+        };
+        let mut siblings = Vec::new();
+        if self.fetch(&root, &mut siblings) > 0 {
+            self.base[0] = self.insert(&siblings) as i32;
+        } else {
+            self.base[0] = 1;
         }
+        self.size = self.size.max(2);
+
+        self.base.truncate(self.size);
+        self.check.truncate(self.size);
+        self.base
+            .into_iter()
+            .zip(self.check)
+            // Negative checks only link up free cells while building.
+            .map(|(base, check)| (base as u32, check.max(0) as u32))
+            .collect()
     }
 
-    fn write_u64(&mut self, value: u64) {
-        self.0 ^= value;
+    fn resize(&mut self, new_len: usize) {
+        self.check.resize(new_len, 0);
+        self.base.resize(new_len, 0);
+        self.used.resize(new_len, false);
     }
-}
 
-#[derive(Debug)]
-pub struct Link {
-    base: u32,
-    check: u32,
-}
-
-impl Link {
-    pub fn read<T: Read>(sysdic: &mut T) -> Result<Link> {
-        Ok(Link {
-            base: read_u32(sysdic)?,
-            check: read_u32(sysdic)?,
-        })
+    /// Collect the children of `parent`, one for each distinct byte the keys
+    /// under it have at its depth, or for the end of the key.
+    fn fetch(&self, parent: &Node, siblings: &mut Vec<Node>) -> usize {
+        let mut prev = 0;
+        for i in parent.left..parent.right {
+            let key = self.keys[i];
+            if parent.depth > key.len() {
+                continue;
+            }
+            let curr = key.get(parent.depth).map_or(0, |&byte| byte as usize + 1);
+            assert!(prev <= curr, "keys must be sorted");
+
+            if curr != prev || siblings.is_empty() {
+                if let Some(last) = siblings.last_mut() {
+                    last.right = i;
+                }
+                siblings.push(Node {
+                    code: curr,
+                    depth: parent.depth + 1,
+                    left: i,
+                    right: 0,
+                });
+            }
+            prev = curr;
+        }
+        if let Some(last) = siblings.last_mut() {
+            last.right = parent.right;
+        }
+        siblings.len()
     }
-}
 
-fn check_valid_link(links: &[Link], from: u32, to: u32) -> Result<(), i32> {
-    // check for overflow
-    if to as usize >= links.len() {
-        return Err(1);
-    }
-    // make sure we didn't follow a link from somewhere we weren't supposed to
-    else if links[to as usize].check != from {
-        return Err(2);
-    }
-    // make sure we don't follow a link back where we started
-    else if links[to as usize].base == from {
-        return Err(3);
-    }
-    Ok(())
-}
+    /// Find a base at which all of `siblings` fit, place them there and
+    /// recurse into their children. Returns the base.
+    fn insert(&mut self, siblings: &[Node]) -> usize {
+        let first_code = siblings[0].code;
+        let last_code = siblings[siblings.len() - 1].code;
+        let mut pos = (first_code + 1).max(self.next_check_pos) - 1;
+        let mut last_free = 0;
+        let mut nonzero_num = 0;
+        let mut first = true;
+
+        let begin = 'outer: loop {
+            pos += 1;
+            if self.check.len() <= pos {
+                self.resize(pos + 1);
+            }
 
-fn check_valid_out(links: &[Link], from: u32, to: u32) -> Result<(), i32> {
-    if let Err(err) = check_valid_link(links, from, to) {
-        return Err(err);
-    }
-    // don't follow links to bases that aren't outputs
-    else if links[to as usize].base < 0x8000_0000 {
-        return Err(-1);
-    }
-    Ok(())
-}
+            if self.check[pos] > 0 {
+                nonzero_num += 1;
+                continue;
+            } else if self.check[pos] < 0 {
+                pos = (-self.check[pos] - 1) as usize;
+                continue;
+            } else if first {
+                self.next_check_pos = pos;
+                last_free = pos;
+                first = false;
+            }
 
-fn collect_links(links: &[Link], base: u32, collection: &mut Vec<(String, u32)>, key: &[u8]) {
-    if check_valid_out(links, base, base).is_ok() {
-        if let Ok(key) = read_str_buffer(&key) {
-            collection.push((key, !links[base as usize].base));
-        }
-    }
-    for i in 0..0x100 {
-        if check_valid_link(links, base, base + 1 + i).is_ok() {
-            let mut newkey = key.to_owned();
-            newkey.push(i as u8);
-            collect_links(
-                links,
-                links[(base + 1 + i) as usize].base,
-                collection,
-                &newkey,
-            );
+            let begin = pos - first_code;
+            if self.check.len() <= begin + last_code {
+                let factor = 105.max(self.keys.len() * 100 / (self.progress + 1));
+                let len = (self.check.len() * factor / 100).max(begin + last_code + 1);
+                self.resize(len);
+            }
+
+            if self.used[begin]
+                || siblings
+                    .iter()
+                    .any(|sibling| self.check[begin + sibling.code] > 0)
+            {
+                if last_free < pos {
+                    self.check[last_free] = -(pos as i32);
+                }
+                continue 'outer;
+            }
+            break begin;
+        };
+
+        // If nearly everything between where the search started and where it
+        // ended up is taken, start the next search from here.
+        if nonzero_num as f32 / (pos as f32 - self.next_check_pos as f32 + 1.0) >= 0.95 {
+            self.next_check_pos = pos;
         }
-    }
-}
 
-#[derive(Debug, Clone, Copy)]
-pub struct DictInfo {
-    first: u32,
-    end: u32,
-}
+        self.used[begin] = true;
+        self.size = self.size.max(begin + last_code + 1);
+        for sibling in siblings {
+            self.check[begin + sibling.code] = begin as i32;
+        }
 
-fn entries_to_tokens(entries: Vec<(String, u32)>) -> HashMap<String, DictInfo> {
-    entries
-        .into_iter()
-        .map(|entry| {
-            let first: u32 = entry.1 / 0x100;
-            let end: u32 = (entry.1 % 0x100) + first;
-            (entry.0, DictInfo { first, end })
-        })
-        .collect()
-}
+        for sibling in siblings {
+            let mut children = Vec::new();
+            if self.fetch(sibling, &mut children) == 0 {
+                self.base[begin + sibling.code] = !(self.values[sibling.left] as i32);
+                self.progress += 1;
+            } else {
+                self.base[begin + sibling.code] = self.insert(&children) as i32;
+            }
+        }
 
-fn collect_links_into_map(links: Vec<Link>) -> HashMap<String, DictInfo> {
-    let mut collection: Vec<(String, u32)> = Vec::new();
-    collect_links(&links, links[0].base, &mut collection, &[]);
-    entries_to_tokens(collection)
+        begin
+    }
 }
 
+/// A mecab dictionary, looked up in place. Keys are found by walking its
+/// double-array trie a byte at a time, as laid out by
+/// [`DoubleArrayTrieBuilder`], straight from the blob, so nothing needs to be
+/// built when loading it.
 pub struct DartDict {
-    pub dict: HashMap<String, DictInfo>,
     pub tokens: Vec<FormatToken>,
-    contains_longer: HashSet<u64, BuildNoopHasher>,
     pub left_contexts: u32,
     pub right_contexts: u32,
+    links_range: Range<usize>,
     feature_bytes_range: Range<usize>,
     blob: Blob,
 }
 
 impl DartDict {
-    pub fn may_contain(&self, hash: u64) -> bool {
-        self.contains_longer.contains(&hash)
+    /// The node every walk starts from.
+    pub fn root(&self) -> u32 {
+        self.link(0).map_or(0, |(base, _)| base)
     }
 
-    pub fn dic_get<'a>(&'a self, find: &str) -> Option<&'a [FormatToken]> {
-        if let Some(info) = self.dict.get(find) {
-            Some(&self.tokens[info.first as usize..info.end as usize])
-        } else {
-            None
+    /// The base and check of the link at `index`.
+    fn link(&self, index: u32) -> Option<(u32, u32)> {
+        let start = self.links_range.start + index as usize * 8;
+        if start + 8 > self.links_range.end {
+            return None;
         }
+        let bytes = &self.blob[start..start + 8];
+        Some((
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        ))
+    }
+
+    /// Follow `bytes` from `node`, returning the node reached if there is a
+    /// key that goes on like that.
+    pub fn walk(&self, mut node: u32, bytes: &[u8]) -> Option<u32> {
+        for &byte in bytes {
+            let (base, check) = self.link(node.checked_add(1 + byte as u32)?)?;
+            // make sure we didn't follow a link from somewhere we weren't
+            // supposed to, or back to where we started
+            if check != node || base == node {
+                return None;
+            }
+            node = base;
+        }
+        Some(node)
+    }
+
+    /// The tokens of the key that ends at `node`, if one does.
+    pub fn tokens_at(&self, node: u32) -> Option<&[FormatToken]> {
+        let (base, check) = self.link(node)?;
+        // don't follow links to bases that aren't outputs
+        if check != node || base < 0x8000_0000 {
+            return None;
+        }
+        let value = !base;
+        let first = (value / 0x100) as usize;
+        let end = first + (value % 0x100) as usize;
+        self.tokens.get(first..end)
+    }
+
+    pub fn dic_get(&self, find: &str) -> Option<&[FormatToken]> {
+        self.walk(self.root(), find.as_bytes())
+            .and_then(|node| self.tokens_at(node))
     }
 
     pub fn feature_get(&self, offset: u32) -> &str {
@@ -206,11 +326,12 @@ pub fn load_mecab_dart_file(blob: Blob) -> Result<DartDict> {
         panic!("only UTF-8 dictionaries are supported. stop using legacy encodings for infrastructure!");
     }
 
-    let mut links: Vec<Link> = Vec::with_capacity((linkbytes / 8) as usize);
-    for _i in 0..(linkbytes / 8) {
-        links.push(Link::read(dic_file)?);
+    let links_location = dic_file.position() as usize;
+    let links_range = links_location..links_location + linkbytes as usize;
+    if blob.get(links_range.clone()).is_none() {
+        panic!("dictionary broken: invalid link table range");
     }
-    trace!("read {} links", links.len());
+    dic_file.set_position(links_range.end as u64);
 
     let mut tokens: Vec<FormatToken> = Vec::with_capacity((tokenbytes / 16) as usize);
     for _i in 0..(tokenbytes / 16) {
@@ -233,25 +354,11 @@ pub fn load_mecab_dart_file(blob: Blob) -> Result<DartDict> {
         panic!("dictionary broken: feature blob is not valid UTF-8");
     }
 
-    let dictionary = collect_links_into_map(links);
-
-    trace!("built dictionary of size {}", dictionary.len());
-
-    let mut contains_longer = HashSet::with_hasher(BuildNoopHasher::default());
-    for entry in dictionary.keys() {
-        let mut hasher = crate::hasher::Hasher::new();
-        for ch in entry.chars() {
-            hasher.write_u32(ch as u32);
-            contains_longer.insert(hasher.finish());
-        }
-    }
-
     Ok(DartDict {
-        dict: dictionary,
         tokens,
-        contains_longer,
         left_contexts,
         right_contexts,
+        links_range,
         feature_bytes_range,
         blob,
     })
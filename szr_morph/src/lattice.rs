@@ -0,0 +1,284 @@
+//! The whole lattice a text is tokenised over, for working out why a text was
+//! split the way it was.
+//!
+//! Every candidate token is a node, and an edge joins two nodes wherever the
+//! second one starts where the first one ends. The cost of a path is the word
+//! costs of its nodes plus the connection costs of its edges, including the
+//! ones from the beginning of the text to its first node and from its last
+//! node to the end. [`Dict::tokenise`] picks the cheapest one.
+
+use std::{fmt::Write, ops::Range};
+
+use serde::Serialize;
+
+use crate::{generate_potential_tokens, pathing, Dict, HashMap, TokenType, TokeniseError};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LatticeNode {
+    /// The range, in bytes, of the text the token covers.
+    pub range: Range<usize>,
+    pub surface: String,
+    pub kind: TokenType,
+    pub left_context: u16,
+    pub right_context: u16,
+    pub word_cost: i64,
+    pub original_id: u32,
+    pub feature: Option<String>,
+    /// Connection cost from the beginning of the text, if a path can start
+    /// with this node.
+    pub start_cost: Option<i64>,
+    /// Connection cost to the end of the text, if a path can end with this
+    /// node.
+    pub end_cost: Option<i64>,
+    /// Lowest cost of getting from the beginning of the text up to and
+    /// including this node, if any path gets there.
+    pub path_cost: Option<i64>,
+    /// Whether the node is on the path [`Dict::tokenise`] picks.
+    pub best: bool,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct LatticeEdge {
+    pub from: usize,
+    pub to: usize,
+    /// Cost of connecting the right context of `from` to the left context of
+    /// `to`, not counting the word cost of either.
+    pub connection_cost: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Lattice {
+    pub text: String,
+    /// In the order the tokens start in.
+    pub nodes: Vec<LatticeNode>,
+    pub edges: Vec<LatticeEdge>,
+    /// Indices of the nodes on the path [`Dict::tokenise`] picks.
+    pub best_path: Vec<usize>,
+    pub total_cost: i64,
+}
+
+impl Lattice {
+    pub(crate) fn build(dict: &Dict, text: &str) -> Result<Self, TokeniseError> {
+        let mut lattice = Lattice {
+            text: text.to_owned(),
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            best_path: Vec::new(),
+            total_cost: 0,
+        };
+        if text.is_empty() {
+            return Ok(lattice);
+        }
+
+        let mut tokens = Vec::new();
+        generate_potential_tokens(dict, text, &mut tokens);
+        let Some(min_rank) = tokens.first().map(|token| token.rank) else {
+            return Err(TokeniseError { _dummy: () });
+        };
+        let end_rank = tokens
+            .iter()
+            .map(|token| token.range.end)
+            .max()
+            .unwrap_or(0);
+
+        let mut by_rank: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (index, token) in tokens.iter().enumerate() {
+            by_rank.entry(token.rank as usize).or_default().push(index);
+        }
+
+        lattice.nodes = tokens
+            .iter()
+            .map(|token| LatticeNode {
+                range: token.range.clone(),
+                surface: text[token.range.clone()].to_owned(),
+                kind: token.kind,
+                left_context: token.left_context,
+                right_context: token.right_context,
+                word_cost: token.cost,
+                original_id: token.original_id,
+                feature: dict
+                    .read_feature_string_by_source(token.kind, token.feature_offset)
+                    .map(str::to_owned),
+                start_cost: (token.rank == min_rank)
+                    .then(|| dict.access_matrix(0, token.left_context) as i64),
+                end_cost: (token.range.end == end_rank)
+                    .then(|| dict.access_matrix(token.right_context, 0) as i64),
+                path_cost: None,
+                best: false,
+            })
+            .collect();
+
+        for (from, token) in tokens.iter().enumerate() {
+            for &to in by_rank.get(&token.range.end).into_iter().flatten() {
+                lattice.edges.push(LatticeEdge {
+                    from,
+                    to,
+                    connection_cost: dict
+                        .access_matrix(token.right_context, tokens[to].left_context)
+                        as i64,
+                });
+            }
+        }
+
+        let mut cache = pathing::Cache::new();
+        let (path, total_cost) = pathing::shortest_path(
+            &mut cache,
+            tokens.len(),
+            |index| tokens[index].rank,
+            |index| tokens[index].range.end as u32,
+            |left, right| dict.cost_between(&tokens[left], &tokens[right]),
+            |index| dict.cost_from_start(&tokens[index]),
+            |index| dict.cost_to_end(&tokens[index]),
+        );
+        if path.is_empty() {
+            return Err(TokeniseError { _dummy: () });
+        }
+        lattice.best_path = path.iter().map(|&index| index as usize).collect();
+        lattice.total_cost = total_cost;
+        for &index in &lattice.best_path {
+            lattice.nodes[index].best = true;
+        }
+        for (node, cost) in lattice.nodes.iter_mut().zip(cache.lowest_costs()) {
+            node.path_cost = cost;
+        }
+        Ok(lattice)
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    /// The lattice as a Graphviz graph, read from left to right, with the
+    /// beginning and end of the text as nodes of their own. The best path is
+    /// drawn in bold.
+    pub fn to_dot(&self) -> String {
+        fn escape(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+
+        let on_best_path = |from: usize, to: usize| {
+            self.best_path
+                .windows(2)
+                .any(|pair| pair[0] == from && pair[1] == to)
+        };
+        let style = |best: bool| if best { ", style=bold, color=red" } else { "" };
+
+        let mut out = String::new();
+        out.push_str("digraph lattice {\n");
+        out.push_str("  rankdir=LR;\n");
+        out.push_str("  node [shape=box];\n");
+        out.push_str("  bos [label=\"BOS\", shape=circle];\n");
+        out.push_str("  eos [label=\"EOS\", shape=circle];\n");
+        for (index, node) in self.nodes.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "  n{index} [label=\"{}\\n{}..{} {:?}\\n{}/{} cost {}\\nbest so far {}\"{}];",
+                escape(&node.surface),
+                node.range.start,
+                node.range.end,
+                node.kind,
+                node.left_context,
+                node.right_context,
+                node.word_cost,
+                node.path_cost
+                    .map_or_else(|| "-".to_owned(), |cost| cost.to_string()),
+                style(node.best),
+            );
+        }
+        let first = self.best_path.first().copied();
+        let last = self.best_path.last().copied();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if let Some(cost) = node.start_cost {
+                let _ = writeln!(
+                    out,
+                    "  bos -> n{index} [label=\"{cost}\"{}];",
+                    style(first == Some(index))
+                );
+            }
+        }
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "  n{} -> n{} [label=\"{}\"{}];",
+                edge.from,
+                edge.to,
+                edge.connection_cost,
+                style(on_best_path(edge.from, edge.to)),
+            );
+        }
+        for (index, node) in self.nodes.iter().enumerate() {
+            if let Some(cost) = node.end_cost {
+                let _ = writeln!(
+                    out,
+                    "  n{index} -> eos [label=\"{cost}\"{}];",
+                    style(last == Some(index))
+                );
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(
+        range: Range<usize>,
+        surface: &str,
+        start: Option<i64>,
+        end: Option<i64>,
+    ) -> LatticeNode {
+        LatticeNode {
+            range,
+            surface: surface.to_owned(),
+            kind: TokenType::Normal,
+            left_context: 1,
+            right_context: 2,
+            word_cost: 100,
+            original_id: 0,
+            feature: None,
+            start_cost: start,
+            end_cost: end,
+            path_cost: start.map(|cost| cost + 100),
+            best: false,
+        }
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let lattice = Lattice {
+            text: "a\"b".to_owned(),
+            nodes: vec![
+                LatticeNode {
+                    best: true,
+                    ..node(0..1, "a", Some(5), None)
+                },
+                node(0..3, "a\"b", Some(7), Some(3)),
+                LatticeNode {
+                    best: true,
+                    path_cost: Some(215),
+                    ..node(1..3, "\"b", None, Some(4))
+                },
+            ],
+            edges: vec![LatticeEdge {
+                from: 0,
+                to: 2,
+                connection_cost: 10,
+            }],
+            best_path: vec![0, 2],
+            total_cost: 219,
+        };
+        let dot = lattice.to_dot();
+        assert!(dot.starts_with("digraph lattice {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains(r#"  n1 [label="a\"b\n0..3 Normal\n1/2 cost 100\nbest so far 107"];"#));
+        assert!(dot.contains(r#"  bos -> n0 [label="5", style=bold, color=red];"#));
+        assert!(dot.contains(r#"  bos -> n1 [label="7"];"#));
+        assert!(dot.contains(r#"  n0 -> n2 [label="10", style=bold, color=red];"#));
+        assert!(dot.contains(r#"  n1 -> eos [label="3"];"#));
+        assert!(dot.contains(r#"  n2 -> eos [label="4", style=bold, color=red];"#));
+        assert!(!dot.contains("n0 -> eos"));
+    }
+}
@@ -1,27 +1,30 @@
 #![allow(dead_code)]
 mod blob;
+pub mod compile;
+mod constraint;
 mod dart;
-mod hasher;
 mod io;
+mod lattice;
 mod pathing;
 mod unkchar;
-mod userdict;
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     io::{Cursor, Read},
     ops::{Deref, Range},
     str,
 };
 
-use snafu::Snafu;
+use snafu::{ensure, Snafu};
 use tracing::{error, trace};
 
 pub use crate::{
     blob::Blob,
-    userdict::{RawUserDict, UserDict},
+    compile::{DictEntry, DictKind},
+    constraint::Constraint,
+    lattice::{Lattice, LatticeEdge, LatticeNode},
 };
-use crate::{dart::*, io::*, unkchar::*};
+use crate::{constraint::*, dart::*, io::*, unkchar::*};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -31,6 +34,19 @@ pub enum Error {
     IoError {
         source: std::io::Error,
     },
+    /// A line of a dictionary source that cannot be compiled.
+    #[snafu(display("{file}, line {line}: {message}"))]
+    InvalidSource {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    /// Dictionary sources that are fine line by line but cannot be compiled
+    /// together.
+    #[snafu(display("{message}"))]
+    InvalidDictionary {
+        message: String,
+    },
     /// FIXME remove this
     #[snafu(whatever, display("{message}: {source:?}"))]
     CatchallError {
@@ -71,7 +87,7 @@ impl FormatToken {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize)]
 pub enum TokenType {
     /// Token came from a mecab dictionary.
     Normal,
@@ -180,7 +196,7 @@ pub struct Dict {
     sys_dic: DartDict,
     unk_dic: DartDict,
     unk_data: UnkChar,
-    user_dic: Option<UserDict>,
+    user_dic: Option<DartDict>,
 
     use_space_stripping: bool,
     use_unk_forced_processing: bool,
@@ -237,27 +253,37 @@ impl Dict {
         })
     }
 
-    /// Load a user dictionary, comma-separated fields.
-    ///
-    /// The first four fields are the surface, left context ID, right context
-    /// ID, and cost of the token.
-    ///
-    /// Everything past the fourth comma is treated as pure text and is the
-    /// token's feature string. It is itself normally a list of comma-separated
-    /// fields with the same format as the feature strings of the main mecab
-    /// dictionary.
-    pub fn load_user_dictionary(
-        &mut self,
-        user_dict: Vec<(String, String, FormatToken)>,
-    ) -> Result<()> {
-        // let mut user_dic_file = Cursor::new(userdic);
-        let mut user_dic = UserDict::new();
-        // user_dic.load_from(&mut user_dic_file)?;
-        user_dic.load_data(user_dict)?;
+    /// The number of left and right contexts of the connection matrix. The
+    /// context IDs of user dictionary entries have to be below them.
+    pub fn contexts(&self) -> (u16, u16) {
+        (self.left_edges, self.right_edges)
+    }
+
+    /// Load a user dictionary compiled by [`compile::write_dictionary`] for
+    /// the same connection matrix, replacing any loaded before. Its entries
+    /// are looked up in place, so a dictionary opened with [`Blob::open`] is
+    /// only read as far as lookups need it.
+    pub fn load_user_dictionary(&mut self, userdic: Blob) -> Result<()> {
+        let user_dic = load_mecab_dart_file(userdic)?;
+        ensure!(
+            user_dic.left_contexts == self.left_edges as u32
+                && user_dic.right_contexts == self.right_edges as u32,
+            InvalidDictionaryError {
+                message: "user dictionary was compiled for a different connection matrix",
+            }
+        );
         self.user_dic = Some(user_dic);
         Ok(())
     }
 
+    /// Compile `entries` into a user dictionary in memory and load it, for
+    /// when there is no compiled one to hand.
+    pub fn load_user_dictionary_entries(&mut self, entries: &[DictEntry]) -> Result<()> {
+        let mut userdic = Vec::new();
+        compile::write_dictionary(&mut userdic, DictKind::User, entries, self.contexts())?;
+        self.load_user_dictionary(Blob::new(userdic))
+    }
+
     /// Returns the feature string belonging to a LexerToken.
     pub fn read_feature_string(&self, token: &LexerToken) -> Option<&str> {
         self.read_feature_string_by_source(token.kind, token.feature_offset)
@@ -269,7 +295,7 @@ impl Dict {
         match kind {
             TokenType::UNK => Some(self.unk_dic.feature_get(offset)),
             TokenType::Normal | TokenType::BOS => Some(self.sys_dic.feature_get(offset)),
-            TokenType::User => self.user_dic.as_ref().unwrap().feature_get(offset),
+            TokenType::User => self.user_dic.as_ref().map(|dic| dic.feature_get(offset)),
         }
     }
 
@@ -306,6 +332,36 @@ impl Dict {
         cache: &mut Cache,
         text: &str,
         output: &mut Vec<LexerToken>,
+    ) -> Result<i64, TokeniseError> {
+        self.analyse_constrained_with_cache(cache, text, &[], output)
+    }
+
+    /// Tokenises a string so that every one of `constraints` holds.
+    ///
+    /// See [`Dict::analyse_constrained_with_cache`] for more details.
+    pub fn tokenise_constrained(
+        &self,
+        text: &str,
+        constraints: &[Constraint],
+    ) -> Result<(Vec<LexerToken>, i64), TokeniseError> {
+        let mut cache = Cache::new();
+        let mut tokens = Vec::new();
+        self.analyse_constrained_with_cache(&mut cache, text, constraints, &mut tokens)
+            .map(|cost| (tokens, cost))
+    }
+
+    /// Like [`Dict::analyse_with_cache`], but only considers paths through
+    /// the lattice on which every one of `constraints` holds.
+    ///
+    /// Fails if a constraint does not fall on character boundaries within
+    /// `text`, if a span asks for an entry the dictionary does not have there,
+    /// or if the constraints contradict each other.
+    pub fn analyse_constrained_with_cache(
+        &self,
+        cache: &mut Cache,
+        text: &str,
+        constraints: &[Constraint],
+        output: &mut Vec<LexerToken>,
     ) -> Result<i64, TokeniseError> {
         if text.is_empty() {
             return Ok(0);
@@ -323,28 +379,125 @@ impl Dict {
 
         let mut tokens = take_memory(&mut cache.tokens);
         generate_potential_tokens(self, text, &mut tokens);
+        let bounds = tokens
+            .first()
+            .map(|token| token.rank)
+            .zip(tokens.iter().map(|token| token.range.end).max());
+        if !constraints.is_empty() {
+            if let Err(err) = apply_constraints(self, text, constraints, &mut tokens) {
+                output.clear();
+                cache.tokens = take_memory(&mut tokens);
+                return Err(err);
+            }
+        }
 
         let (path, total_cost) = crate::pathing::shortest_path(
             &mut cache.pathing_cache,
             tokens.len(),
-            |index| tokens[index].rank as u32,
+            |index| tokens[index].rank,
             |index| tokens[index].range.end as u32,
-            |left, right| {
-                let right_token = &tokens[right];
-                let left_token = &tokens[left];
-                right_token.cost as i64
-                    + self.access_matrix(left_token.right_context, right_token.left_context) as i64
-            },
-            |index| {
-                let right_token = &tokens[index];
-                right_token.cost as i64 + self.access_matrix(0, right_token.left_context) as i64
-            },
-            |index| self.access_matrix(tokens[index].right_context, 0) as i64,
+            |left, right| self.cost_between(&tokens[left], &tokens[right]),
+            |index| self.cost_from_start(&tokens[index]),
+            |index| self.cost_to_end(&tokens[index]),
         );
 
+        let complete = constraints.is_empty()
+            || bounds.is_some_and(|(start, end)| path_is_complete(&tokens, path, start, end));
+
         output.clear();
-        output.extend(path.iter().map(|&index| (&tokens[index as usize]).into()));
+        if complete {
+            output.extend(path.iter().map(|&index| (&tokens[index as usize]).into()));
+            self.fill_real_costs(output);
+        }
+
+        cache.tokens = take_memory(&mut tokens);
+        if output.is_empty() {
+            if complete {
+                error!("failed to tokenise text of length {}: {}", text.len(), text);
+            } else {
+                error!("constraints {constraints:?} leave no way to tokenise {text}");
+            }
+            return Err(TokeniseError { _dummy: () });
+        }
+
+        Ok(total_cost)
+    }
+
+    /// Tokenises a string into the `n` lowest-cost segmentations, cheapest
+    /// first.
+    ///
+    /// See [`Dict::analyse_n_best_with_cache`] for more details.
+    pub fn tokenise_n_best(
+        &self,
+        text: &str,
+        n: usize,
+    ) -> Result<Vec<(Vec<LexerToken>, i64)>, TokeniseError> {
+        let mut cache = Cache::new();
+        self.analyse_n_best_with_cache(&mut cache, text, n)
+    }
+
+    /// Like [`Dict::analyse_with_cache`], but returns up to `n` of the
+    /// lowest-cost paths through the lattice along with their total costs,
+    /// cheapest first. The first one is as good as the one
+    /// [`Dict::analyse_with_cache`] picks, and the others are the alternatives
+    /// the dictionary considers next most likely. How close their costs are
+    /// says how ambiguous the text is.
+    ///
+    /// There are fewer than `n` results if the lattice does not have that many
+    /// paths, and none for an empty `text`.
+    pub fn analyse_n_best_with_cache(
+        &self,
+        cache: &mut Cache,
+        text: &str,
+        n: usize,
+    ) -> Result<Vec<(Vec<LexerToken>, i64)>, TokeniseError> {
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut tokens = Vec::new();
+        generate_potential_tokens(self, text, &mut tokens);
 
+        let paths = crate::pathing::n_best_paths(
+            &mut cache.pathing_cache,
+            n,
+            tokens.len(),
+            |index| tokens[index].rank,
+            |index| tokens[index].range.end as u32,
+            |left, right| self.cost_between(&tokens[left], &tokens[right]),
+            |index| self.cost_from_start(&tokens[index]),
+            |index| self.cost_to_end(&tokens[index]),
+        );
+        if paths.is_empty() && n > 0 {
+            error!("failed to tokenise text of length {}: {}", text.len(), text);
+            return Err(TokeniseError { _dummy: () });
+        }
+
+        Ok(paths
+            .into_iter()
+            .map(|(path, total_cost)| {
+                let mut output: Vec<LexerToken> = path
+                    .iter()
+                    .map(|&index| (&tokens[index as usize]).into())
+                    .collect();
+                self.fill_real_costs(&mut output);
+                (output, total_cost)
+            })
+            .collect())
+    }
+
+    /// Every candidate token the text could be split into, how they connect
+    /// and what each connection costs, along with the path
+    /// [`Dict::tokenise`] picks through them. Meant for finding out why a text
+    /// was split the way it was; see [`Lattice::to_json`] and
+    /// [`Lattice::to_dot`].
+    pub fn lattice(&self, text: &str) -> Result<Lattice, TokeniseError> {
+        Lattice::build(self, text)
+    }
+
+    /// Add the cost of connecting to the previous token to the cost of each
+    /// token on a path.
+    fn fill_real_costs(&self, output: &mut [LexerToken]) {
         for i in 0..output.len() {
             let left_context = if i == 0 {
                 0
@@ -355,14 +508,22 @@ impl Dict {
             let edge_cost = self.access_matrix(left_context, right_context);
             output[i].real_cost = output[i].cost + edge_cost as i64;
         }
+    }
 
-        cache.tokens = take_memory(&mut tokens);
-        if path.is_empty() {
-            error!("failed to tokenise text of length {}: {}", text.len(), text);
-            return Err(TokeniseError { _dummy: () });
-        }
+    /// Cost of `right` coming right after `left` on a path, with the word
+    /// cost of `right`.
+    fn cost_between(&self, left: &Token, right: &Token) -> i64 {
+        right.cost + self.access_matrix(left.right_context, right.left_context) as i64
+    }
 
-        Ok(total_cost)
+    /// Cost of a path starting with `token`, with its word cost.
+    fn cost_from_start(&self, token: &Token) -> i64 {
+        token.cost + self.access_matrix(0, token.left_context) as i64
+    }
+
+    /// Cost of a path ending with `token`, not counting its word cost.
+    fn cost_to_end(&self, token: &Token) -> i64 {
+        self.access_matrix(token.right_context, 0) as i64
     }
 
     #[allow(clippy::cast_lossless)]
@@ -528,47 +689,37 @@ fn generate_potential_tokens_at<'a>(
         return space_count;
     };
 
-    // find all tokens starting at this point in the string
-    let mut hasher = crate::hasher::Hasher::new();
-    hasher.write_u32(first_char as u32);
+    // find all tokens starting at this point in the string, walking down the
+    // tries a character at a time for as long as any entry starts with the
+    // characters walked so far
+    let mut sys_node = Some(dict.sys_dic.root());
+    let mut user_node = dict.user_dic.as_ref().map(|user_dic| user_dic.root());
+    let mut walked = start;
     loop {
-        let substring: &str = &text[start..end];
-        let hash = hasher.finish();
-        let mut any = false;
-        if dict.sys_dic.may_contain(hash) {
-            any = true;
-            if let Some(matching_tokens) = dict.sys_dic.dic_get(&substring) {
-                let tokens = matching_tokens
-                    .into_iter()
-                    .map(|token| Token::new(token, rank, start..end, TokenType::Normal));
-                output.extend(tokens);
-            }
-        }
-        if dict
-            .user_dic
-            .as_ref()
-            .map(|x| x.may_contain(substring))
-            .unwrap_or(false)
-        {
-            any = true;
-            if let Some(matching_tokens) = dict
-                .user_dic
-                .as_ref()
-                .and_then(|user_dic| user_dic.dic_get(&substring))
-            {
-                let tokens = matching_tokens
-                    .into_iter()
-                    .map(|token| Token::new(token, rank, start..end, TokenType::User));
-                output.extend(tokens);
-            }
+        let step = &text.as_bytes()[walked..end];
+        walked = end;
+        sys_node = sys_node.and_then(|node| dict.sys_dic.walk(node, step));
+        user_node = user_node.and_then(|node| dict.user_dic.as_ref()?.walk(node, step));
+        if sys_node.is_none() && user_node.is_none() {
+            break;
         }
 
-        if !any {
-            break;
+        if let Some(matching_tokens) = sys_node.and_then(|node| dict.sys_dic.tokens_at(node)) {
+            let tokens = matching_tokens
+                .iter()
+                .map(|token| Token::new(token, rank, start..end, TokenType::Normal));
+            output.extend(tokens);
+        }
+        if let Some(matching_tokens) =
+            user_node.and_then(|node| dict.user_dic.as_ref()?.tokens_at(node))
+        {
+            let tokens = matching_tokens
+                .iter()
+                .map(|token| Token::new(token, rank, start..end, TokenType::User));
+            output.extend(tokens);
         }
 
         if let Some((_, c)) = index_iter.next() {
-            hasher.write_u32(c as u32);
             end += c.len_utf8();
         } else {
             break;
@@ -734,6 +885,60 @@ mod tests {
 
         assert_parse(&dict, "これを持っていけ", "これ|を|持っ|て|いけ");
 
+        // alternatives come after the best parse, in order of cost
+        let n_best = dict.tokenise_n_best("これを持っていけ", 5).unwrap();
+        assert_eq!(n_best.len(), 5);
+        assert_eq!(n_best[0].1, dict.tokenise("これを持っていけ").unwrap().1);
+        assert!(n_best.windows(2).all(|w| w[0].1 <= w[1].1));
+
+        // constraints override the dictionary
+        let (tokens, _) = dict
+            .tokenise_constrained("これを持っていけ", &[Constraint::Boundary(3)])
+            .unwrap();
+        assert_eq!(tokens[0].range, 0..3);
+        let span = Constraint::Span {
+            range: 0..9,
+            original_id: None,
+        };
+        let (tokens, _) = dict.tokenise_constrained("完ッ全に", &[span]).unwrap();
+        assert_eq!(tokenstream_to_string("完ッ全に", &tokens, "|"), "完ッ全|に");
+        assert!(dict
+            .tokenise_constrained(
+                "これを持っていけ",
+                &[
+                    Constraint::Boundary(3),
+                    Constraint::Span {
+                        range: 0..6,
+                        original_id: None
+                    }
+                ]
+            )
+            .is_err());
+
+        // the lattice agrees with the tokeniser
+        let lattice = dict.lattice("これを持っていけ").unwrap();
+        let (tokens, cost) = dict.tokenise("これを持っていけ").unwrap();
+        assert_eq!(lattice.total_cost, cost);
+        let best: Vec<_> = lattice
+            .best_path
+            .iter()
+            .map(|&index| lattice.nodes[index].range.clone())
+            .collect();
+        assert_eq!(
+            best,
+            tokens.iter().map(|t| t.range.clone()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            lattice.nodes[*lattice.best_path.last().unwrap()]
+                .path_cost
+                .unwrap()
+                + lattice.nodes[*lattice.best_path.last().unwrap()]
+                    .end_cost
+                    .unwrap(),
+            cost
+        );
+        assert!(lattice.to_dot().starts_with("digraph lattice {"));
+
         // lots of text
         assert_parse(&dict,
           "メタプログラミング (metaprogramming) とはプログラミング技法の一種で、ロジックを直接コーディングするのではなく、あるパターンをもったロジックを生成する高位ロジックによってプログラミングを行う方法、またその高位ロジックを定義する方法のこと。主に対象言語に埋め込まれたマクロ言語によって行われる。",
@@ -789,8 +994,15 @@ mod tests {
         assert_parse(&dict, "𛁁", "𛁁");
 
         // user dictionary
-        // assert_parse(&dict, "飛行機", "飛行|機");
-        // dict.load_user_dictionary().unwrap();
-        // assert_parse(&dict, "飛行機", "飛行機");
+        assert_parse(&dict, "飛行機", "飛行|機");
+        dict.load_user_dictionary_entries(&[DictEntry {
+            surface: "飛行機".to_owned(),
+            left_context: 0,
+            right_context: 0,
+            cost: -30000,
+            feature: "名詞".to_owned(),
+        }])
+        .unwrap();
+        assert_parse(&dict, "飛行機", "飛行機");
     }
 }
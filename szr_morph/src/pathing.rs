@@ -1,4 +1,4 @@
-use std::ops::Range;
+use std::{cmp::Reverse, collections::BinaryHeap, ops::Range};
 
 pub struct Cache {
     rank_to_range: Vec<Range<u32>>,
@@ -17,6 +17,15 @@ impl Cache {
         }
     }
 
+    /// Lowest cost of getting from the start of the graph up to and
+    /// including each node, as found by the last search, or `None` for nodes
+    /// no path reaches.
+    pub fn lowest_costs(&self) -> impl Iterator<Item = Option<Cost>> + '_ {
+        self.cost_for_node
+            .iter()
+            .map(|&cost| (cost != COST_MAX).then_some(cost))
+    }
+
     fn clear(&mut self) {
         self.rank_to_range.clear();
         self.cost_for_node.clear();
@@ -28,21 +37,14 @@ impl Cache {
 pub type Cost = i64;
 const COST_MAX: Cost = std::i64::MAX;
 
-pub fn shortest_path(
-    cache: &mut Cache,
+/// Index the nodes by rank, returning the lowest and highest rank any node
+/// starts at and the rank at which the graph ends.
+fn index_by_rank(
+    rank_to_range: &mut Vec<Range<u32>>,
     node_count: usize,
-    get_rank: impl Fn(usize) -> u32,
-    get_next_rank: impl Fn(usize) -> u32,
-    get_cost: impl Fn(usize, usize) -> Cost,
-    get_cost_for_start_node: impl Fn(usize) -> Cost,
-    get_cost_for_end_node: impl Fn(usize) -> Cost,
-) -> (&[u32], Cost) {
-    if node_count == 0 {
-        return (&[], 0);
-    }
-
-    cache.clear();
-
+    get_rank: &impl Fn(usize) -> u32,
+    get_next_rank: &impl Fn(usize) -> u32,
+) -> (u32, u32, u32) {
     debug_assert!((0..node_count)
         .zip(1..node_count)
         .all(|(index, next_index)| get_rank(next_index) >= get_rank(index)));
@@ -51,7 +53,6 @@ pub fn shortest_path(
     let min_rank = get_rank(0);
     let max_rank = get_rank(node_count - 1);
     let mut end_rank = 0;
-    let rank_to_range = &mut cache.rank_to_range;
     rank_to_range.resize((max_rank + 1) as usize, 0..0);
 
     {
@@ -96,6 +97,24 @@ pub fn shortest_path(
     debug_assert_eq!(rank_to_range[max_rank as usize].end, node_count as u32);
     debug_assert!(end_rank > max_rank);
 
+    (min_rank, max_rank, end_rank)
+}
+
+/// Fill in the lowest cost of getting from the start of the graph to each
+/// node, node included, and the node before it on that path. Returns the last
+/// node of the lowest-cost path through the whole graph.
+#[allow(clippy::too_many_arguments)]
+fn find_lowest_costs(
+    cache: &mut Cache,
+    node_count: usize,
+    (min_rank, max_rank, end_rank): (u32, u32, u32),
+    get_rank: &impl Fn(usize) -> u32,
+    get_next_rank: &impl Fn(usize) -> u32,
+    get_cost: &impl Fn(usize, usize) -> Cost,
+    get_cost_for_start_node: &impl Fn(usize) -> Cost,
+    get_cost_for_end_node: &impl Fn(usize) -> Cost,
+) -> u32 {
+    let rank_to_range = &cache.rank_to_range;
     let cost_for_node = &mut cache.cost_for_node;
     cost_for_node.resize(node_count, COST_MAX);
 
@@ -150,12 +169,47 @@ pub fn shortest_path(
         starting_index = range.end;
     }
 
+    lowest_cost_index
+}
+
+pub fn shortest_path(
+    cache: &mut Cache,
+    node_count: usize,
+    get_rank: impl Fn(usize) -> u32,
+    get_next_rank: impl Fn(usize) -> u32,
+    get_cost: impl Fn(usize, usize) -> Cost,
+    get_cost_for_start_node: impl Fn(usize) -> Cost,
+    get_cost_for_end_node: impl Fn(usize) -> Cost,
+) -> (&[u32], Cost) {
+    if node_count == 0 {
+        return (&[], 0);
+    }
+
+    cache.clear();
+
+    let ranks = index_by_rank(
+        &mut cache.rank_to_range,
+        node_count,
+        &get_rank,
+        &get_next_rank,
+    );
+    let lowest_cost_index = find_lowest_costs(
+        cache,
+        node_count,
+        ranks,
+        &get_rank,
+        &get_next_rank,
+        &get_cost,
+        &get_cost_for_start_node,
+        &get_cost_for_end_node,
+    );
+
     let path = &mut cache.path;
     let mut index = lowest_cost_index;
-    let total_cost = cost_for_node[index as usize] + get_cost_for_end_node(index as usize);
+    let total_cost = cache.cost_for_node[index as usize] + get_cost_for_end_node(index as usize);
     loop {
         path.push(index);
-        match source_node[index as usize] {
+        match cache.source_node[index as usize] {
             std::u32::MAX => break,
             source_index => index = source_index,
         }
@@ -165,6 +219,119 @@ pub fn shortest_path(
     (&cache.path, total_cost)
 }
 
+/// A path through the graph being extended backwards from its end, in the
+/// arena of [`n_best_paths`].
+struct PartialPath {
+    node: u32,
+    /// Cost from this node, excluded, to the end of the graph.
+    cost_to_end: Cost,
+    /// Index of the rest of the path, towards the end of the graph.
+    next: Option<usize>,
+}
+
+/// The `n` lowest-cost paths through the graph, cheapest first, along with
+/// their total costs. The first one costs as much as the one
+/// [`shortest_path`] finds, though with ties either may come first. There are
+/// fewer than `n` if the graph does not have that many paths.
+///
+/// The best cost from the start to each node is known after the same forward
+/// pass as [`shortest_path`], which makes it an exact estimate for a
+/// best-first search backwards from the end: paths come out complete and in
+/// order, without ever having to revisit one.
+#[allow(clippy::too_many_arguments)]
+pub fn n_best_paths(
+    cache: &mut Cache,
+    n: usize,
+    node_count: usize,
+    get_rank: impl Fn(usize) -> u32,
+    get_next_rank: impl Fn(usize) -> u32,
+    get_cost: impl Fn(usize, usize) -> Cost,
+    get_cost_for_start_node: impl Fn(usize) -> Cost,
+    get_cost_for_end_node: impl Fn(usize) -> Cost,
+) -> Vec<(Vec<u32>, Cost)> {
+    if node_count == 0 || n == 0 {
+        return Vec::new();
+    }
+
+    cache.clear();
+
+    let ranks = index_by_rank(
+        &mut cache.rank_to_range,
+        node_count,
+        &get_rank,
+        &get_next_rank,
+    );
+    let (min_rank, max_rank, end_rank) = ranks;
+    find_lowest_costs(
+        cache,
+        node_count,
+        ranks,
+        &get_rank,
+        &get_next_rank,
+        &get_cost,
+        &get_cost_for_start_node,
+        &get_cost_for_end_node,
+    );
+    let cost_for_node = &cache.cost_for_node;
+
+    // Nodes by the rank at which they end, for going backwards.
+    let mut ending_at = vec![Vec::new(); (max_rank + 1) as usize];
+    let mut arena = Vec::new();
+    let mut queue = BinaryHeap::new();
+    for (index, &cost) in cost_for_node.iter().enumerate() {
+        if cost == COST_MAX {
+            continue;
+        }
+        let next_rank = get_next_rank(index);
+        if next_rank <= max_rank {
+            ending_at[next_rank as usize].push(index as u32);
+        } else if next_rank == end_rank {
+            let cost_to_end = get_cost_for_end_node(index);
+            queue.push(Reverse((cost + cost_to_end, arena.len())));
+            arena.push(PartialPath {
+                node: index as u32,
+                cost_to_end,
+                next: None,
+            });
+        }
+    }
+
+    let mut paths = Vec::new();
+    while let Some(Reverse((total_cost, partial_index))) = queue.pop() {
+        let partial = &arena[partial_index];
+        let node = partial.node;
+        let rank = get_rank(node as usize);
+        if rank == min_rank {
+            let mut path = vec![node];
+            let mut next = partial.next;
+            while let Some(index) = next {
+                path.push(arena[index].node);
+                next = arena[index].next;
+            }
+            paths.push((path, total_cost));
+            if paths.len() == n {
+                break;
+            }
+            continue;
+        }
+
+        let cost_to_end = partial.cost_to_end;
+        for &previous in &ending_at[rank as usize] {
+            let cost_to_end = get_cost(previous as usize, node as usize) + cost_to_end;
+            queue.push(Reverse((
+                cost_for_node[previous as usize] + cost_to_end,
+                arena.len(),
+            )));
+            arena.push(PartialPath {
+                node: previous,
+                cost_to_end,
+                next: Some(partial_index),
+            });
+        }
+    }
+    paths
+}
+
 #[test]
 fn test_shortest_path() {
     let mut cache = Cache::new();
@@ -406,3 +573,154 @@ fn test_shortest_path() {
     assert_eq!(path, &[1u32]);
     assert_eq!(total_cost, 1i64);
 }
+
+#[test]
+fn test_n_best_paths() {
+    let mut cache = Cache::new();
+    let paths = n_best_paths(
+        &mut cache,
+        3,
+        0,
+        |_| unreachable!(),
+        |_| unreachable!(),
+        |_, _| unreachable!(),
+        |_| unreachable!(),
+        |_| unreachable!(),
+    );
+    assert!(paths.is_empty());
+
+    let get_rank = |index| match index {
+        0 | 1 => 0,
+        2 | 3 => 1,
+        4 => 2,
+        _ => unreachable!(),
+    };
+    let get_next_rank = |index| match index {
+        0 | 1 => 1,
+        2 | 3 => 2,
+        4 => 3,
+        _ => unreachable!(),
+    };
+    let get_cost = |a, b| match (a, b) {
+        (0, 2) => 100,
+        (0, 3) => 0,
+        (1, 2) => 100,
+        (1, 3) => 100,
+        (2, 4) => 0,
+        (3, 4) => 10000,
+        _ => unreachable!(),
+    };
+    let paths = n_best_paths(
+        &mut cache,
+        3,
+        5,
+        get_rank,
+        get_next_rank,
+        get_cost,
+        |_| 0,
+        |_| 0,
+    );
+    let mut tied: Vec<_> = paths[..2].iter().map(|(path, _)| path.clone()).collect();
+    tied.sort();
+    assert_eq!(tied, [vec![0, 2, 4], vec![1, 2, 4]]);
+    assert_eq!(
+        paths.iter().map(|(_, cost)| *cost).collect::<Vec<_>>(),
+        [100, 100, 10000]
+    );
+    assert_eq!(paths[2].0, [0, 3, 4]);
+
+    // Asking for more than there are gives all of them.
+    let paths = n_best_paths(
+        &mut cache,
+        10,
+        5,
+        get_rank,
+        get_next_rank,
+        get_cost,
+        |_| 0,
+        |_| 0,
+    );
+    assert_eq!(paths.len(), 4);
+    assert_eq!(paths[3], (vec![1, 3, 4], 10100));
+
+    let paths = n_best_paths(
+        &mut cache,
+        2,
+        5,
+        |index| match index {
+            0 => 0,
+            1 => 0,
+            2 => 1,
+            3 => 2,
+            4 => 3,
+            _ => unreachable!(),
+        },
+        |index| match index {
+            0 => 1,
+            1 => 4,
+            2 => 2,
+            3 => 3,
+            4 => 4,
+            _ => unreachable!(),
+        },
+        |a, b| match (a, b) {
+            (0, 2) => 0,
+            (2, 3) => 0,
+            (3, 4) => 0,
+            _ => unreachable!(),
+        },
+        |index| match index {
+            0 => 0,
+            1 => 1,
+            _ => unreachable!(),
+        },
+        |index| match index {
+            1 => 0,
+            4 => 2,
+            _ => unreachable!(),
+        },
+    );
+    assert_eq!(paths, [(vec![1], 1), (vec![0, 2, 3, 4], 2)]);
+}
+
+#[test]
+fn test_lowest_costs() {
+    let mut cache = Cache::new();
+    let (path, total_cost) = shortest_path(
+        &mut cache,
+        5,
+        |index| match index {
+            0 | 1 => 0,
+            2 | 3 => 1,
+            4 => 3,
+            _ => unreachable!(),
+        },
+        |index| match index {
+            0 | 1 => 1,
+            2 => 2,
+            3 => 4,
+            4 => 4,
+            _ => unreachable!(),
+        },
+        |a, b| match (a, b) {
+            (0, 2) => 10,
+            (0, 3) => 20,
+            (1, 2) => 1,
+            (1, 3) => 2,
+            _ => unreachable!(),
+        },
+        |index| match index {
+            0 => 0,
+            1 => 5,
+            _ => unreachable!(),
+        },
+        |_| 0,
+    );
+    assert_eq!(path, &[1, 3]);
+    assert_eq!(total_cost, 7);
+    // Node 2 leads nowhere and nothing ends where node 4 starts.
+    assert_eq!(
+        cache.lowest_costs().collect::<Vec<_>>(),
+        [Some(0), Some(5), Some(6), Some(7), None]
+    );
+}
@@ -0,0 +1,312 @@
+//! Importing review history from Anki.
+//!
+//! An Anki collection (`collection.anki2`) is an SQLite database. Notes hold
+//! the fields that were studied, each note has one or more cards, and every
+//! answer to a card is a row in the review log. Each note is matched to a
+//! variant by spelling and reading, and its log is replayed through the
+//! scheduler so that the mneme starts out where the learner actually is.
+
+use std::{collections::BTreeMap, path::Path};
+
+use chrono::{DateTime, Utc};
+use snafu::{OptionExt, ResultExt, Snafu};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    Connection, PgPool,
+};
+use szr_ja_utils::kata_to_hira_str;
+
+use crate::{
+    mneme::{self, MnemeWithHistory},
+    params::Params,
+    review_grade::ReviewGrade,
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    #[snafu(display("failed to read Anki collection {path}: {source}"))]
+    ReadCollection {
+        path: String,
+        source: sqlx::Error,
+    },
+    #[snafu(display("review {id} has an invalid timestamp"))]
+    InvalidTimestamp {
+        id: i64,
+    },
+    MatchVariants {
+        source: sqlx::Error,
+    },
+    PersistImport {
+        source: sqlx::Error,
+    },
+    PersistMneme {
+        source: mneme::Error,
+    },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Anki separates the fields of a note with the unit separator.
+const FIELD_SEPARATOR: char = '\x1f';
+
+/// Which fields of a note hold what, counting from zero.
+#[derive(Debug, Clone, Copy)]
+pub struct Fields {
+    pub spelling: usize,
+    /// Without a reading, notes are matched by spelling alone.
+    pub reading: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnkiReview {
+    pub reviewed_at: DateTime<Utc>,
+    pub grade: ReviewGrade,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnkiNote {
+    pub id: i64,
+    /// With markup removed.
+    pub fields: Vec<String>,
+    /// The log of the card of this note that was reviewed the most, in
+    /// order. Other cards, e.g. for the reverse direction, are left out.
+    pub reviews: Vec<AnkiReview>,
+}
+
+impl AnkiNote {
+    pub fn spelling(&self, fields: Fields) -> Option<&str> {
+        self.fields
+            .get(fields.spelling)
+            .map(String::as_str)
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Readings are stored as hiragana.
+    pub fn reading(&self, fields: Fields) -> Option<String> {
+        fields
+            .reading
+            .and_then(|i| self.fields.get(i))
+            .filter(|s| !s.is_empty())
+            .map(|s| kata_to_hira_str(s))
+    }
+
+    /// The mneme as it would be had its reviews happened here, or `None` if
+    /// the note was never reviewed.
+    pub fn to_history(&self, params: &Params) -> Option<MnemeWithHistory> {
+        let (first, rest) = self.reviews.split_first()?;
+        let init = MnemeWithHistory::init_at(params, first.grade, first.reviewed_at);
+        Some(rest.iter().fold(init, |item, review| {
+            item.add_review(params, review.grade, review.reviewed_at)
+        }))
+    }
+}
+
+/// Remove HTML tags and the entities Anki's editor likes to insert.
+fn clean_field(field: &str) -> String {
+    let mut r = String::with_capacity(field.len());
+    let mut in_tag = false;
+    for c in field.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => r.push(c),
+            _ => {}
+        }
+    }
+    r.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+        .trim()
+        .to_string()
+}
+
+/// Kinds of entries in Anki's review log that are answers to a card.
+/// Entries of other kinds, such as cramming in a filtered deck or
+/// rescheduling by hand, leave the learner where they were.
+const REVLOG_LEARN: i64 = 0;
+const REVLOG_REVIEW: i64 = 1;
+const REVLOG_RELEARN: i64 = 2;
+
+/// Answer buttons in the order Anki numbers them. The first version of Anki's
+/// scheduler only had three of them, Again, Good and Easy, for cards that were
+/// being learnt or relearnt. Zero marks a card that was rescheduled by hand
+/// rather than answered.
+fn grade_for_answer(kind: i64, ease: i64, scheduler_version: i64) -> Option<ReviewGrade> {
+    match kind {
+        REVLOG_LEARN | REVLOG_RELEARN if scheduler_version < 2 => match ease {
+            1 => Some(ReviewGrade::Fail),
+            2 => Some(ReviewGrade::Okay),
+            3 => Some(ReviewGrade::Easy),
+            _ => None,
+        },
+        REVLOG_LEARN | REVLOG_REVIEW | REVLOG_RELEARN => match ease {
+            1 => Some(ReviewGrade::Fail),
+            2 => Some(ReviewGrade::Hard),
+            3 => Some(ReviewGrade::Okay),
+            4 => Some(ReviewGrade::Easy),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The version of Anki's scheduler the collection is used with, which is the
+/// first one unless it says otherwise. Newer collections keep it in a table of
+/// their own, older ones in the configuration of the collection.
+async fn scheduler_version(conn: &mut SqliteConnection) -> sqlx::Result<i64> {
+    let has_config_table: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'config')",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if has_config_table {
+        let version: Option<i64> = sqlx::query_scalar(
+            "SELECT CAST(CAST(val AS TEXT) AS INTEGER) FROM config WHERE key = 'schedVer'",
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some(version) = version {
+            return Ok(version);
+        }
+    }
+    let version: Option<Option<i64>> = sqlx::query_scalar(
+        "SELECT CASE WHEN json_valid(conf) THEN json_extract(conf, '$.schedVer') END FROM col",
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(version.flatten().unwrap_or(1))
+}
+
+/// Read every note that has been reviewed at least once.
+pub async fn read_collection(path: &Path) -> Result<Vec<AnkiNote>> {
+    let path_str = path.display().to_string();
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let mut conn = SqliteConnection::connect_with(&options)
+        .await
+        .context(ReadCollectionCtx { path: &path_str })?;
+    let scheduler_version = scheduler_version(&mut conn)
+        .await
+        .context(ReadCollectionCtx { path: &path_str })?;
+    let rows: Vec<(i64, String, i64, i64, i64, i64)> = sqlx::query_as(
+        r#"SELECT notes.id, notes.flds, revlog.cid, revlog.id, revlog.type, revlog.ease
+FROM revlog
+JOIN cards ON cards.id = revlog.cid
+JOIN notes ON notes.id = cards.nid
+ORDER BY notes.id, revlog.cid, revlog.id"#,
+    )
+    .fetch_all(&mut conn)
+    .await
+    .context(ReadCollectionCtx { path: &path_str })?;
+
+    // Note -> fields and card -> log
+    let mut notes: BTreeMap<i64, (String, BTreeMap<i64, Vec<AnkiReview>>)> = BTreeMap::new();
+    for (note_id, fields, card_id, id, kind, ease) in rows {
+        let Some(grade) = grade_for_answer(kind, ease, scheduler_version) else {
+            continue;
+        };
+        // Review ids are the time of the review in milliseconds.
+        let reviewed_at =
+            DateTime::from_timestamp_millis(id).context(InvalidTimestampCtx { id })?;
+        notes
+            .entry(note_id)
+            .or_insert_with(|| (fields, BTreeMap::new()))
+            .1
+            .entry(card_id)
+            .or_default()
+            .push(AnkiReview { reviewed_at, grade });
+    }
+
+    Ok(notes
+        .into_iter()
+        .filter_map(|(id, (fields, cards))| {
+            // Ties go to the card that was created first.
+            let reviews = cards
+                .into_values()
+                .rev()
+                .max_by_key(|reviews| reviews.len())?;
+            Some(AnkiNote {
+                id,
+                fields: fields.split(FIELD_SEPARATOR).map(clean_field).collect(),
+                reviews,
+            })
+        })
+        .collect())
+}
+
+/// What happened to each note in an import, identified by its spelling.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: Vec<String>,
+    /// No variant has this spelling and reading.
+    pub unmatched: Vec<String>,
+    /// Several variants match, so it is unclear which one was studied.
+    pub ambiguous: Vec<String>,
+    /// The variant already has a mneme, which is left alone.
+    pub already_known: Vec<String>,
+}
+
+/// Create a mneme with its reconstructed history for every note that matches
+/// exactly one variant without a mneme. Either all of them are stored, or none
+/// are.
+pub async fn import(
+    pool: &PgPool,
+    params: &Params,
+    notes: &[AnkiNote],
+    fields: Fields,
+) -> Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut tx = pool.begin().await.context(PersistImportCtx)?;
+    for note in notes {
+        let (Some(spelling), Some(mut item)) = (note.spelling(fields), note.to_history(params))
+        else {
+            continue;
+        };
+        let reading = note.reading(fields);
+        let matches = sqlx::query!(
+            r#"SELECT id, mneme_id FROM variants
+WHERE spelling = $1 AND ($2::text IS NULL OR reading = $2)"#,
+            spelling,
+            reading
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .context(MatchVariantsCtx)?;
+        let label = spelling.to_string();
+        let variant_id = match matches.as_slice() {
+            [] => {
+                report.unmatched.push(label);
+                continue;
+            }
+            [m] if m.mneme_id.is_some() => {
+                report.already_known.push(label);
+                continue;
+            }
+            [m] => m.id,
+            _ => {
+                report.ambiguous.push(label);
+                continue;
+            }
+        };
+
+        // Lapses in Anki count as much as any others.
+        if item.lapses() >= params.leech_threshold() as usize {
+            item.mneme.leech = true;
+            item.mneme.suspended = params.suspend_leeches();
+        }
+        let mneme_id = item.mneme.id;
+        item.persist(&mut tx).await.context(PersistMnemeCtx)?;
+        sqlx::query!(
+            "UPDATE variants SET mneme_id = $2 WHERE id = $1",
+            variant_id,
+            mneme_id
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistImportCtx)?;
+        report.imported.push(label);
+    }
+    tx.commit().await.context(PersistImportCtx)?;
+    Ok(report)
+}
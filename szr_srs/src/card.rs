@@ -0,0 +1,139 @@
+//! The different things a variant can be studied for.
+//!
+//! Each card of a variant is a mneme of its own, scheduled independently of
+//! the others. Recognition cards live in `variants.mneme_id`, the other kinds
+//! in `variant_cards`, and the `variant_mnemes` view has all of them.
+
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgConnection, PgExecutor};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetCards { source: sqlx::Error },
+    PersistCard { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[sqlx(type_name = "card_kind")]
+pub enum CardKind {
+    /// Shown the spelling, recall the reading and the meaning.
+    #[default]
+    Recognition,
+    /// Shown the spelling with its reading, recall the meaning.
+    Meaning,
+    /// Shown the reading and the meaning, recall how it is written.
+    Production,
+    /// Hear the word, recall what it is and how it is pronounced.
+    Listening,
+}
+
+impl CardKind {
+    pub const ALL: [Self; 4] = [
+        Self::Recognition,
+        Self::Meaning,
+        Self::Production,
+        Self::Listening,
+    ];
+
+    /// What to recall when the card is shown.
+    pub fn instructions(self) -> &'static str {
+        match self {
+            Self::Recognition => "Recall the reading and meaning",
+            Self::Meaning => "Recall the meaning",
+            Self::Production => "Recall how it is written",
+            Self::Listening => "Recall the word and its pitch",
+        }
+    }
+}
+
+/// The mneme for the card of `kind` of a variant, if there is one.
+pub async fn mneme_for(
+    executor: impl PgExecutor<'_>,
+    variant_id: Uuid,
+    kind: CardKind,
+) -> Result<Option<Uuid>> {
+    sqlx::query_scalar!(
+        r#"SELECT mneme_id AS "mneme_id!" FROM variant_mnemes
+WHERE variant_id = $1 AND kind = $2"#,
+        variant_id,
+        kind as _
+    )
+    .fetch_optional(executor)
+    .await
+    .context(GetCardsCtx)
+}
+
+/// The kind of card a mneme is, or `None` if it does not belong to a variant.
+pub async fn kind_of(executor: impl PgExecutor<'_>, mneme_id: Uuid) -> Result<Option<CardKind>> {
+    sqlx::query_scalar!(
+        r#"SELECT kind AS "kind!: CardKind" FROM variant_mnemes WHERE mneme_id = $1"#,
+        mneme_id
+    )
+    .fetch_optional(executor)
+    .await
+    .context(GetCardsCtx)
+}
+
+/// Every card of a variant, in the order of [`CardKind::ALL`].
+pub async fn cards_for_variant(
+    executor: impl PgExecutor<'_>,
+    variant_id: Uuid,
+) -> Result<Vec<(CardKind, Uuid)>> {
+    let rows = sqlx::query!(
+        r#"SELECT kind AS "kind!: CardKind", mneme_id AS "mneme_id!" FROM variant_mnemes
+WHERE variant_id = $1
+ORDER BY kind"#,
+        variant_id
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetCardsCtx)?;
+    Ok(rows.into_iter().map(|r| (r.kind, r.mneme_id)).collect())
+}
+
+/// Make `mneme_id` the card of `kind` for a variant, replacing any it already
+/// had.
+pub async fn attach(
+    conn: &mut PgConnection,
+    variant_id: Uuid,
+    kind: CardKind,
+    mneme_id: Uuid,
+) -> Result<()> {
+    match kind {
+        CardKind::Recognition => sqlx::query!(
+            "UPDATE variants SET mneme_id = $2 WHERE id = $1",
+            variant_id,
+            mneme_id
+        )
+        .execute(conn)
+        .await
+        .context(PersistCardCtx)?,
+        _ => sqlx::query!(
+            r#"INSERT INTO variant_cards (variant_id, kind, mneme_id) VALUES ($1, $2, $3)
+ON CONFLICT (variant_id, kind) DO UPDATE SET mneme_id = excluded.mneme_id"#,
+            variant_id,
+            kind as _,
+            mneme_id
+        )
+        .execute(conn)
+        .await
+        .context(PersistCardCtx)?,
+    };
+    Ok(())
+}
@@ -0,0 +1,240 @@
+//! Getting mnemes back out of the database.
+//!
+//! Mnemes are exported along with the variant they belong to, either as CSV
+//! (one file for the current state of each mneme, one for every review in the
+//! log) or as an Anki text import file that can be studied elsewhere.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use chrono::{DateTime, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgPool};
+
+use crate::{
+    card::CardKind,
+    memory_status::MemoryStatus,
+    mneme::{self, MnemeWithHistory},
+    review_grade::ReviewGrade,
+    scheduler::SchedulerKind,
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetMnemes { source: mneme::Error },
+    GetVariants { source: sqlx::Error },
+    WriteRecord { source: csv::Error },
+    WriteHeader { source: std::io::Error },
+    Flush { source: std::io::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A mneme along with the variant it is a card of.
+#[derive(Debug, Clone)]
+pub struct ExportedMneme {
+    pub variant_id: Uuid,
+    pub kind: CardKind,
+    pub spelling: String,
+    pub reading: Option<String>,
+    pub item: MnemeWithHistory,
+}
+
+impl ExportedMneme {
+    /// Every mneme that belongs to a variant, ordered by creation.
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        let variants = sqlx::query!(
+            r#"SELECT
+variants.id,
+kind AS "kind!: CardKind",
+spelling,
+reading,
+variant_mnemes.mneme_id AS "mneme_id!"
+FROM variant_mnemes
+JOIN variants ON variants.id = variant_mnemes.variant_id"#
+        )
+        .fetch_all(pool)
+        .await
+        .context(GetVariantsCtx)?;
+        let mut variants: HashMap<_, _> = variants
+            .into_iter()
+            .map(|v| (v.mneme_id, (v.id, v.kind, v.spelling, v.reading)))
+            .collect();
+        let items = MnemeWithHistory::get_all(pool)
+            .await
+            .context(GetMnemesCtx)?;
+        Ok(items
+            .into_iter()
+            .filter_map(|item| {
+                let (variant_id, kind, spelling, reading) = variants.remove(&item.mneme.id)?;
+                Some(Self {
+                    variant_id,
+                    kind,
+                    spelling,
+                    reading,
+                    item,
+                })
+            })
+            .collect())
+    }
+}
+
+#[derive(serde::Serialize)]
+struct MnemeRecord<'a> {
+    mneme_id: Uuid,
+    variant_id: Uuid,
+    kind: CardKind,
+    spelling: &'a str,
+    reading: Option<&'a str>,
+    created_at: DateTime<Utc>,
+    next_due: DateTime<Utc>,
+    status: MemoryStatus,
+    /// Answers given, leaving out the review that created the mneme and any
+    /// resets.
+    num_reviews: usize,
+    difficulty: f64,
+    stability: f64,
+    scheduler: SchedulerKind,
+    suspended: bool,
+    buried_until: Option<DateTime<Utc>>,
+    leech: bool,
+}
+
+#[derive(serde::Serialize)]
+struct ReviewRecord<'a> {
+    mneme_id: Uuid,
+    kind: CardKind,
+    spelling: &'a str,
+    reading: Option<&'a str>,
+    index: i32,
+    grade: ReviewGrade,
+    status: MemoryStatus,
+    due_at: DateTime<Utc>,
+    reviewed_at: DateTime<Utc>,
+    elapsed_days: f64,
+    difficulty: f64,
+    stability: f64,
+    scheduler: SchedulerKind,
+    params_version: i32,
+    latency_ms: Option<i32>,
+    reset: bool,
+}
+
+/// One row per mneme, with its current state.
+pub fn write_mnemes_csv(w: impl Write, mnemes: &[ExportedMneme]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(w);
+    for m in mnemes {
+        let mneme = &m.item.mneme;
+        writer
+            .serialize(MnemeRecord {
+                mneme_id: mneme.id,
+                variant_id: m.variant_id,
+                kind: m.kind,
+                spelling: &m.spelling,
+                reading: m.reading.as_deref(),
+                created_at: mneme.created_at,
+                next_due: mneme.next_due,
+                status: mneme.state.status,
+                num_reviews: m.item.states().filter(|s| !s.is_start()).count(),
+                difficulty: mneme.state.difficulty,
+                stability: mneme.state.stability,
+                scheduler: mneme.state.scheduler,
+                suspended: mneme.suspended,
+                buried_until: mneme.buried_until,
+                leech: mneme.leech,
+            })
+            .context(WriteRecordCtx)?;
+    }
+    writer.flush().context(FlushCtx)
+}
+
+/// One row per review, including the one that created each mneme and any
+/// resets, in review order.
+pub fn write_reviews_csv(w: impl Write, mnemes: &[ExportedMneme]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(w);
+    for m in mnemes {
+        for s in m.item.states() {
+            writer
+                .serialize(ReviewRecord {
+                    mneme_id: m.item.mneme.id,
+                    kind: m.kind,
+                    spelling: &m.spelling,
+                    reading: m.reading.as_deref(),
+                    index: s.index,
+                    grade: s.grade,
+                    status: s.status,
+                    due_at: s.due_at,
+                    reviewed_at: s.reviewed_at,
+                    elapsed_days: s.elapsed_days,
+                    difficulty: s.difficulty,
+                    stability: s.stability,
+                    scheduler: s.scheduler,
+                    params_version: s.params_version,
+                    latency_ms: s.latency_ms,
+                    reset: s.reset,
+                })
+                .context(WriteRecordCtx)?;
+        }
+    }
+    writer.flush().context(FlushCtx)
+}
+
+/// Tag given to every exported note.
+const ANKI_TAG: &str = "suzuri";
+
+/// Text as HTML that Anki shows as is.
+pub fn escape_html(s: &str) -> String {
+    let mut r = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => r.push_str("&amp;"),
+            '<' => r.push_str("&lt;"),
+            '>' => r.push_str("&gt;"),
+            '"' => r.push_str("&quot;"),
+            '\'' => r.push_str("&#39;"),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+/// Notes in Anki's text import format, with fields for the spelling, the
+/// reading and the example sentences given for each mneme. The fields are
+/// HTML, so the spelling and reading are escaped, while the examples are
+/// written as given and must escape any text in them with [`escape_html`].
+/// There is one note per variant, however many cards it has here, and
+/// suspended mnemes are left out.
+pub fn write_anki_notes(
+    mut w: impl Write,
+    mnemes: &[ExportedMneme],
+    mut examples: impl FnMut(&ExportedMneme) -> Vec<String>,
+) -> Result<()> {
+    // https://docs.ankiweb.net/importing/text-files.html#file-headers
+    writeln!(
+        w,
+        "#separator:tab\n#html:true\n#columns:Spelling\tReading\tExamples\tTags\n#tags column:4"
+    )
+    .context(WriteHeaderCtx)?;
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b'\t')
+        .has_headers(false)
+        .from_writer(w);
+    let mut seen = HashSet::new();
+    for m in mnemes
+        .iter()
+        .filter(|m| !m.item.mneme.suspended && seen.insert(m.variant_id))
+    {
+        writer
+            .write_record([
+                &escape_html(&m.spelling),
+                &escape_html(m.reading.as_deref().unwrap_or_default()),
+                &examples(m).join("<br>"),
+                ANKI_TAG,
+            ])
+            .context(WriteRecordCtx)?;
+    }
+    writer.flush().context(FlushCtx)
+}
@@ -0,0 +1,65 @@
+//! What the scheduler expects to happen next.
+//!
+//! Per-mneme predictions live on [`Mneme`](crate::Mneme) itself: see
+//! [`Mneme::retrievability_at`](crate::Mneme::retrievability_at) and
+//! [`Mneme::predicted_interval`](crate::Mneme::predicted_interval). This module
+//! covers the deck as a whole, straight from the database. Unlike the
+//! [`Simulator`](crate::Simulator), it does not guess at future reviews: it
+//! only counts the due dates that are already set.
+
+use chrono::{DateTime, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::PgExecutor;
+
+use crate::queue::Usage;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetDueHistogram { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DueCount {
+    /// Start of the day.
+    pub date: DateTime<Utc>,
+    /// Number of mnemes coming due over the day. The first day also counts
+    /// the ones that are already overdue.
+    pub due: usize,
+}
+
+/// How many mnemes come due on each of the `days` days starting with the one
+/// `now` falls in. Like the review queue, this leaves out suspended mnemes and
+/// ones that are not the card of any variant. Buried ones are counted on the
+/// day they come back.
+pub async fn due_histogram(
+    executor: impl PgExecutor<'_>,
+    now: DateTime<Utc>,
+    days: u32,
+) -> Result<Vec<DueCount>> {
+    let rows = sqlx::query!(
+        r#"SELECT days.day AS "day!", COUNT(due.at) AS "due!"
+FROM generate_series($1::timestamptz, $1::timestamptz + ($2::int - 1) * interval '1 day', interval '1 day') AS days(day)
+LEFT JOIN (
+  SELECT GREATEST(next_due, buried_until) AS at FROM mnemes
+  WHERE NOT suspended
+  AND EXISTS (SELECT FROM variant_mnemes WHERE variant_mnemes.mneme_id = mnemes.id)
+) due ON due.at < days.day + interval '1 day' AND (due.at >= days.day OR days.day = $1)
+GROUP BY days.day
+ORDER BY days.day"#,
+        Usage::day_start(now),
+        days as i32
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetDueHistogramCtx)?;
+    Ok(rows
+        .into_iter()
+        .map(|r| DueCount {
+            date: r.day,
+            due: r.due as usize,
+        })
+        .collect())
+}
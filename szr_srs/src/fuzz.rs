@@ -0,0 +1,180 @@
+//! Spreading out due dates.
+//!
+//! Mnemes created or reviewed together would otherwise keep coming due
+//! together. Fuzzing moves each interval to a pseudorandom point in a range
+//! around it, and load balancing picks the day in that range with the fewest
+//! mnemes already due. The randomness is seeded by the mneme and the position
+//! of the review in its log, so replaying a log gives the same due dates.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgExecutor};
+
+use crate::params::Params;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetDueLoad { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Intervals shorter than this are learning steps, which are only ever
+/// delayed, never brought forward.
+const MAX_LEARNING_STEP: f64 = 1.0;
+/// Intervals between the learning steps and this are left alone.
+const MIN_FUZZED_INTERVAL: f64 = 2.5;
+/// How much a learning step can be delayed by, relative to its length. Like
+/// the fuzz range of longer intervals, this grows with the step, so that a
+/// batch created together is spread over more time the longer the step is.
+const LEARNING_STEP_FUZZ: f64 = 0.25;
+/// The fuzz range grows by a decreasing fraction of the interval, as in FSRS.
+static FUZZ_RANGES: [(f64, f64, f64); 3] = [
+    (2.5, 7.0, 0.15),
+    (7.0, 20.0, 0.1),
+    (20.0, f64::INFINITY, 0.05),
+];
+
+fn as_days(d: Duration) -> f64 {
+    d.num_seconds() as f64 / 86400.0
+}
+
+fn from_days(d: f64) -> Duration {
+    Duration::seconds((d * 86400.0).round() as i64)
+}
+
+/// Number of mnemes due on each day, keyed by days since the Unix epoch.
+#[derive(Debug, Clone, Default)]
+pub struct DueLoad(HashMap<i64, usize>);
+
+impl DueLoad {
+    fn day(t: DateTime<Utc>) -> i64 {
+        t.timestamp().div_euclid(86400)
+    }
+
+    pub fn get(&self, t: DateTime<Utc>) -> usize {
+        self.0.get(&Self::day(t)).copied().unwrap_or_default()
+    }
+
+    pub fn add(&mut self, t: DateTime<Utc>) {
+        *self.0.entry(Self::day(t)).or_default() += 1;
+    }
+
+    /// Count the mnemes coming due between `from` and `to`.
+    pub async fn get_for_range(
+        executor: impl PgExecutor<'_>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Self> {
+        let due = sqlx::query_scalar!(
+            "SELECT next_due FROM mnemes WHERE next_due >= $1 AND next_due < $2",
+            from,
+            to
+        )
+        .fetch_all(executor)
+        .await
+        .context(GetDueLoadCtx)?;
+        let mut r = Self::default();
+        for t in due {
+            r.add(t);
+        }
+        Ok(r)
+    }
+}
+
+/// Uniformly distributed in `[0, 1)`, determined by a mneme and the index of
+/// a review in its log.
+fn uniform(id: Uuid, index: i32) -> f64 {
+    let (hi, lo) = id.as_u64_pair();
+    // SplitMix64 finaliser
+    let mut z = hi ^ lo.rotate_left(32) ^ (index as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// How far a review interval of the given length can be moved either way, in
+/// days.
+fn fuzz_delta(days: f64) -> f64 {
+    if days < MIN_FUZZED_INTERVAL {
+        return 0.0;
+    }
+    let mut delta = 1.0;
+    for (start, end, factor) in FUZZ_RANGES {
+        delta += factor * (days.min(end) - start).max(0.0);
+    }
+    delta
+}
+
+/// The interval to actually use for the review at `index` in the log of mneme
+/// `id`, given the one computed by the scheduler. `load` is only looked at if
+/// the parameters ask for load balancing.
+pub(crate) fn adjust_interval(
+    params: &Params,
+    interval: Duration,
+    now: DateTime<Utc>,
+    id: Uuid,
+    index: i32,
+    load: Option<&DueLoad>,
+) -> Duration {
+    if !params.fuzz && !params.load_balance {
+        return interval;
+    }
+    let days = as_days(interval);
+    if days < MAX_LEARNING_STEP {
+        if !params.fuzz {
+            return interval;
+        }
+        return interval + from_days(LEARNING_STEP_FUZZ * days * uniform(id, index));
+    }
+
+    let delta = fuzz_delta(days);
+    if delta == 0.0 {
+        return interval;
+    }
+    let max = params.max_interval as f64;
+    let lo = (days - delta).max(MIN_FUZZED_INTERVAL).min(max);
+    let hi = (days + delta).min(max);
+    let target = if params.fuzz {
+        lo + (hi - lo) * uniform(id, index)
+    } else {
+        days
+    };
+
+    match load.filter(|_| params.load_balance) {
+        // Move by whole days so that the time of day stays the same.
+        Some(load) => {
+            let first = (lo - days).ceil() as i64;
+            let last = (hi - days).floor() as i64;
+            (first..=last)
+                .map(|k| interval + Duration::days(k))
+                .min_by(|a, b| {
+                    let key = |d: &Duration| (load.get(now + *d), (as_days(*d) - target).abs());
+                    let (la, da) = key(a);
+                    let (lb, db) = key(b);
+                    la.cmp(&lb).then(da.total_cmp(&db))
+                })
+                .unwrap_or(interval)
+        }
+        None if params.round_to_days => Duration::days(target.round() as i64),
+        None => from_days(target),
+    }
+}
+
+/// A range of due dates that covers every choice [`adjust_interval`] can make
+/// when load balancing an interval close to `interval`, e.g. a fuzzed one.
+pub(crate) fn balancing_window(
+    now: DateTime<Utc>,
+    interval: Duration,
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let days = as_days(interval);
+    let delta = 2.0 * fuzz_delta(days).ceil() + 1.0;
+    (
+        now + from_days((days - delta).max(0.0)),
+        now + from_days(days + delta),
+    )
+}
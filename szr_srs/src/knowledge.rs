@@ -0,0 +1,139 @@
+//! How well things other than variants are known.
+//!
+//! Only variants have mnemes, but knowing 分かる says something about 分る,
+//! another variant of the same lemma, and about the kanji 分 read as わ. These
+//! estimates are derived from the cards of every variant a lemma or morpheme
+//! appears in, going by whichever card is best remembered. They are computed
+//! in the database for just the lemma or kanji at hand. Only cards scheduled
+//! by FSRS count, since SM-2 does not estimate the probability of recall.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgExecutor};
+use szr_ja_utils::is_kanji;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetCards { source: sqlx::Error },
+    GetVariants { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Probability of recall above which a lemma counts as known well enough for
+/// its other variants to be familiar.
+pub const FAMILIAR_RECALL: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The highest probability of recall among the cards.
+    pub retrievability: f64,
+    /// How many cards the estimate is based on.
+    pub cards: usize,
+}
+
+/// How well the lemma is known through any of its variants, as of `now`, or
+/// `None` if none of them has a card.
+pub async fn lemma(
+    executor: impl PgExecutor<'_>,
+    lemma_id: Uuid,
+    now: DateTime<Utc>,
+) -> Result<Option<Estimate>> {
+    let r = sqlx::query!(
+        r#"SELECT
+MAX(retrievability(s.stability, s.reviewed_at, $2)) AS retrievability,
+COUNT(*) AS "cards!"
+FROM variants
+JOIN variant_mnemes ON variant_mnemes.variant_id = variants.id
+JOIN mnemes ON mnemes.id = variant_mnemes.mneme_id
+JOIN mneme_states s ON s.id = mnemes.state_id
+WHERE variants.lemma_id = $1 AND s.scheduler = 'Fsrs'"#,
+        lemma_id,
+        now
+    )
+    .fetch_one(executor)
+    .await
+    .context(GetCardsCtx)?;
+    Ok(r.retrievability.map(|retrievability| Estimate {
+        retrievability,
+        cards: r.cards as usize,
+    }))
+}
+
+/// How well each kanji in `spelling` is known, under any of its readings,
+/// through the cards of every variant with a morpheme that has it, as of
+/// `now`. Kanji without any such card are left out, and the rest come in the
+/// order they first appear in.
+pub async fn kanji_in(
+    executor: impl PgExecutor<'_>,
+    spelling: &str,
+    now: DateTime<Utc>,
+) -> Result<Vec<(char, Estimate)>> {
+    let mut kanji: Vec<char> = Vec::new();
+    for c in spelling.chars().filter(|c| is_kanji(*c)) {
+        if !kanji.contains(&c) {
+            kanji.push(c);
+        }
+    }
+    let kanji_strings: Vec<String> = kanji.iter().map(char::to_string).collect();
+    let rows = sqlx::query!(
+        r#"SELECT
+k.kanji AS "kanji!",
+MAX(retrievability(s.stability, s.reviewed_at, $2)) AS "retrievability!",
+COUNT(*) AS "cards!"
+FROM unnest($1::text[]) AS k(kanji)
+JOIN morpheme_occs m ON strpos(m.spelling, k.kanji) > 0
+JOIN variant_mnemes ON variant_mnemes.variant_id = m.variant_id
+JOIN mnemes ON mnemes.id = variant_mnemes.mneme_id
+JOIN mneme_states s ON s.id = mnemes.state_id
+WHERE s.scheduler = 'Fsrs'
+GROUP BY k.kanji"#,
+        &kanji_strings,
+        now
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetCardsCtx)?;
+    let mut estimates: HashMap<String, Estimate> = rows
+        .into_iter()
+        .map(|r| {
+            let estimate = Estimate {
+                retrievability: r.retrievability,
+                cards: r.cards as usize,
+            };
+            (r.kanji, estimate)
+        })
+        .collect();
+    Ok(kanji
+        .into_iter()
+        .filter_map(|c| Some((c, estimates.remove(&c.to_string())?)))
+        .collect())
+}
+
+/// Variants without a recognition card of their own whose lemma is known at
+/// least as well as `threshold` through another variant, as of `now`.
+pub async fn familiar_variants(
+    executor: impl PgExecutor<'_>,
+    threshold: f64,
+    now: DateTime<Utc>,
+) -> Result<Vec<Uuid>> {
+    sqlx::query_scalar!(
+        r#"SELECT id AS "id!" FROM variants
+WHERE mneme_id IS NULL AND lemma_id IN (
+  SELECT known.lemma_id
+  FROM variants known
+  JOIN variant_mnemes ON variant_mnemes.variant_id = known.id
+  JOIN mnemes ON mnemes.id = variant_mnemes.mneme_id
+  JOIN mneme_states s ON s.id = mnemes.state_id
+  WHERE s.scheduler = 'Fsrs' AND retrievability(s.stability, s.reviewed_at, $2) >= $1
+)"#,
+        threshold,
+        now
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetVariantsCtx)
+}
@@ -0,0 +1,74 @@
+//! Cards that keep being forgotten.
+//!
+//! A lapse is a fail on a mneme that had made it out of learning. Once a mneme
+//! has lapsed [`Params::leech_threshold`](crate::Params::leech_threshold)
+//! times it is tagged as a leech when reviewed, and suspended too if the
+//! parameters say so. Reviewing it again is unlikely to help much more than
+//! finding a better way to remember it.
+
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgExecutor};
+
+use crate::card::CardKind;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetLeeches { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A mneme tagged as a leech, along with the variant it is a card of.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Leech {
+    pub mneme_id: Uuid,
+    pub variant_id: Uuid,
+    pub kind: CardKind,
+    pub spelling: String,
+    pub reading: Option<String>,
+    pub lapses: usize,
+    pub suspended: bool,
+}
+
+/// Every leech that belongs to a variant, the ones with the most lapses
+/// first.
+pub async fn get_all(executor: impl PgExecutor<'_>) -> Result<Vec<Leech>> {
+    let rows = sqlx::query!(
+        r#"SELECT
+mnemes.id,
+variants.id AS variant_id,
+variant_mnemes.kind AS "kind!: CardKind",
+variants.spelling,
+variants.reading,
+mnemes.suspended,
+(SELECT COUNT(*)
+  FROM mneme_states s
+  JOIN mneme_states prev ON prev.mneme_id = s.mneme_id AND prev.index = s.index - 1
+  WHERE s.mneme_id = mnemes.id
+    AND s.undone_at IS NULL AND prev.undone_at IS NULL
+    AND prev.status = 'Reviewing' AND s.grade = 'Fail'
+    AND s.index > (SELECT COALESCE(MAX(r.index), 0) FROM mneme_states r
+      WHERE r.mneme_id = s.mneme_id AND r.reset AND r.undone_at IS NULL)) AS "lapses!"
+FROM mnemes
+JOIN variant_mnemes ON variant_mnemes.mneme_id = mnemes.id
+JOIN variants ON variants.id = variant_mnemes.variant_id
+WHERE mnemes.leech
+ORDER BY 7 DESC, variants.spelling, 3"#
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetLeechesCtx)?;
+    Ok(rows
+        .into_iter()
+        .map(|r| Leech {
+            mneme_id: r.id,
+            variant_id: r.variant_id,
+            kind: r.kind,
+            spelling: r.spelling,
+            reading: r.reading,
+            lapses: r.lapses as usize,
+            suspended: r.suspended,
+        })
+        .collect())
+}
@@ -1,14 +1,32 @@
+pub mod anki;
+pub mod card;
+pub mod export;
+pub mod forecast;
+pub mod fuzz;
+pub mod knowledge;
+pub mod leech;
 mod memory_status;
 pub mod mneme;
 mod mneme_state;
-mod params;
+pub mod note;
+pub mod optimiser;
+pub mod params;
+pub mod queue;
+pub mod replay;
 mod review_grade;
+pub mod scheduler;
+pub mod simulator;
+pub mod stats;
 #[cfg(test)]
 mod tests;
 mod weights;
 
+pub use card::CardKind;
 pub use memory_status::MemoryStatus;
 pub use mneme::{Mneme, MnemeWithHistory};
 pub use mneme_state::MnemeState;
+pub use optimiser::Optimiser;
 pub use params::Params;
 pub use review_grade::ReviewGrade;
+pub use scheduler::{Scheduler, SchedulerKind};
+pub use simulator::Simulator;
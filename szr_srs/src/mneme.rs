@@ -1,23 +1,55 @@
 use chrono::{DateTime, Duration, SubsecRound, Utc};
-use snafu::{ResultExt, Snafu};
-use sqlx::{types::Uuid, PgPool};
+use snafu::{ensure, ResultExt, Snafu};
+use sqlx::{types::Uuid, Connection, PgConnection, PgExecutor, PgPool};
 
 use crate::{
-    memory_status::MemoryStatus, mneme_state::MnemeState, params::Params, review_grade::ReviewGrade,
+    fuzz::{self, DueLoad},
+    memory_status::MemoryStatus,
+    mneme_state::{self, MnemeState},
+    params::Params,
+    replay,
+    review_grade::ReviewGrade,
+    scheduler::{Fsrs, Schedule, Scheduler, SchedulerKind},
 };
 
 #[derive(Debug, Snafu)]
 #[snafu(context(suffix(Ctx)))]
 pub enum Error {
-    #[snafu(whatever, display("{message}: {source:?}"))]
-    CatchallError {
-        message: String,
-        #[snafu(source(from(Box<dyn std::error::Error>, Some)))]
-        source: Option<Box<dyn std::error::Error>>,
+    #[snafu(display("mneme {id} has no state {state_id}"))]
+    MissingState {
+        id: Uuid,
+        state_id: Uuid,
+    },
+    States {
+        source: mneme_state::Error,
     },
     PersistMneme {
         source: sqlx::Error,
     },
+    GetMnemes {
+        source: sqlx::Error,
+    },
+    GetDueLoad {
+        source: fuzz::Error,
+    },
+    #[snafu(display("mneme {id} has not been reviewed since it was created"))]
+    NothingToUndo {
+        id: Uuid,
+    },
+    #[snafu(display("state {state_id} is no longer the current state of mneme {id}"))]
+    NotCurrentState {
+        id: Uuid,
+        state_id: Uuid,
+    },
+    UndoReview {
+        source: sqlx::Error,
+    },
+    UpdateFlags {
+        source: sqlx::Error,
+    },
+    ResetMneme {
+        source: sqlx::Error,
+    },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -28,6 +60,11 @@ fn pg_compatible_now() -> DateTime<Utc> {
     Utc::now().trunc_subsecs(6)
 }
 
+/// Length of the interval between two points in time, in fractional days.
+pub(crate) fn elapsed_days(from: DateTime<Utc>, to: DateTime<Utc>) -> f64 {
+    (to - from).num_seconds() as f64 / 86400.0
+}
+
 /// (State for) a unit of memory.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize, PartialEq, PartialOrd)]
 pub struct Mneme {
@@ -35,6 +72,12 @@ pub struct Mneme {
     pub created_at: DateTime<Utc>,
     pub next_due: DateTime<Utc>,
     pub state: MnemeState,
+    /// Suspended mnemes are never queued for review.
+    pub suspended: bool,
+    /// Buried mnemes are not queued for review until this point in time.
+    pub buried_until: Option<DateTime<Utc>>,
+    /// Whether the mneme has lapsed often enough to be tagged as a leech.
+    pub leech: bool,
 }
 
 struct MnemeUpdate {
@@ -45,60 +88,44 @@ struct MnemeUpdate {
 }
 
 impl Mneme {
-    pub(crate) fn stability_pass_update_base(&self, params: &Params, retrievability: f64) -> f64 {
-        let w = &params.weights;
-        w.stab_upd_pass_scale
-            * self.state.stability.powf(-w.stab_upd_pass_stab)
-            * (w.stab_upd_pass_retr * (1.0 - retrievability)).exp_m1()
-            * (1.0 + params.max_difficulty - self.state.difficulty)
+    /// Probability of recall after the given number of days have passed since
+    /// the last review, if the state is one of FSRS. SM-2 does not model
+    /// memory, so it has no such estimate.
+    pub(crate) fn retrievability_after(&self, days: f64) -> Option<f64> {
+        (self.state.scheduler == SchedulerKind::Fsrs)
+            .then(|| Fsrs::retrievability(self.state.stability, days))
     }
 
-    pub(crate) fn stability_fail_update(&self, params: &Params, retrievability: f64) -> f64 {
-        let w = &params.weights;
-        w.stab_upd_fail_scale
-            * self.state.difficulty.powf(-w.stab_upd_fail_diff)
-            * (w.stab_upd_fail_retr * (1.0 - retrievability)).exp()
-            * ((1.0 + self.state.stability).powf(w.stab_upd_fail_stab) - 1.0)
+    /// Probability of recall at `now`, as FSRS sees it when the mneme is
+    /// reviewed then: only whole days since the last review count.
+    pub fn retrievability_at(&self, now: DateTime<Utc>) -> Option<f64> {
+        self.retrievability_after((now - self.state.reviewed_at).num_days() as f64)
     }
 
-    pub(crate) fn stability_for_grade(
+    /// Time until the mneme would next be due if it were reviewed at `now`
+    /// with the given grade, fuzz included. Load balancing depends on the
+    /// rest of the deck at the time of the review, so it is not taken into
+    /// account. The mneme has to be in the terms of the scheduler of
+    /// `params`, see [`Mneme::for_scheduler`].
+    pub fn predicted_interval(
         &self,
         params: &Params,
         grade: ReviewGrade,
-        retrievability: f64,
-    ) -> f64 {
-        match grade {
-            ReviewGrade::Fail => self.stability_fail_update(params, retrievability),
-            _ => {
-                let change_factor = 1.0
-                    + self.stability_pass_update_base(params, retrievability)
-                        * params.stability_pass_update_bonus(grade);
-                self.state.stability * change_factor
-            }
-        }
+        now: DateTime<Utc>,
+    ) -> Duration {
+        self.review_with_id(params, grade, now, Uuid::nil(), None)
+            .next_due
+            - now
     }
 
-    pub(crate) fn interval_for_grade(
-        &self,
-        params: &Params,
-        grade: ReviewGrade,
-        retrievability: f64,
-    ) -> Duration {
-        Self::theoretical_interval(
-            params,
-            self.stability_for_grade(params, grade, retrievability),
-        )
+    pub fn is_buried(&self, now: DateTime<Utc>) -> bool {
+        self.buried_until.is_some_and(|t| now < t)
     }
 
-    /// Point in time at which retrievability equals the target retention value.
-    pub(crate) fn theoretical_interval(p: &Params, stab: f64) -> Duration {
-        let d = 9.0 * stab * (-1.0 + 1.0 / p.target_retention);
-        if p.round_to_days {
-            Duration::days((d.round() as i64).clamp(1, p.max_interval as i64))
-        } else {
-            let d = 86400.0 * d.clamp(1.0, p.max_interval as f64);
-            Duration::seconds(d.round() as i64)
-        }
+    /// Whether or not the mneme can be queued for review, regardless of
+    /// whether it is due.
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        !self.suspended && !self.is_buried(now)
     }
 
     /// We cleanly separate the creation of the initial card from reviews.
@@ -120,39 +147,39 @@ impl Mneme {
         id: Uuid,
         new_review_id: Uuid,
     ) -> Self {
-        let difficulty = grade
-            .as_factor()
-            .mul_add(-params.weights.init_diff_scale, params.weights.diff_base)
-            .clamp(params.min_difficulty, params.max_difficulty);
-        let stability = params
-            .initial_stability(grade)
-            .max(params.min_initial_stability);
-        let theoretical_interval = Self::theoretical_interval(params, stability);
-        let interval = match grade {
-            ReviewGrade::Fail => params.first_interval,
-            ReviewGrade::Hard => params.second_interval,
-            ReviewGrade::Okay => params.third_interval,
-            ReviewGrade::Easy => theoretical_interval,
-        };
-        let next_due = now + interval;
+        let Schedule {
+            difficulty,
+            stability,
+            interval,
+        } = params.scheduler.init(params, grade);
+        let next_due = now + fuzz::adjust_interval(params, interval, now, id, 0, None);
         let state = match grade {
             ReviewGrade::Easy => MemoryStatus::Reviewing,
             _ => MemoryStatus::Learning,
         };
         let review = MnemeState {
             id: new_review_id,
+            index: 0,
             grade,
             status: state,
             due_at: now,
             reviewed_at: now,
+            elapsed_days: 0.0,
             difficulty,
             stability,
+            params_version: params.version,
+            latency_ms: None,
+            reset: false,
+            scheduler: params.scheduler,
         };
         Self {
             id,
             created_at: now,
             next_due,
             state: review,
+            suspended: false,
+            buried_until: None,
+            leech: false,
         }
     }
 
@@ -162,10 +189,8 @@ impl Mneme {
         grade: ReviewGrade,
         now: DateTime<Utc>,
         new_review_id: Uuid,
+        load: Option<&DueLoad>,
     ) -> MnemeUpdate {
-        let w = &params.weights;
-        let days_since = (now - self.state.reviewed_at).num_days() as f64;
-
         // Perform a transition on the state in case something unexpected happened.
         let state = match (self.state.status, grade) {
             (
@@ -176,148 +201,372 @@ impl Mneme {
             (s, _) => s,
         };
 
-        let retrievability = (1.0 + days_since / (9.0 * self.state.stability)).powi(-1);
-        // These parameters will only be updated if we are on a review streak.
-        let mut stability = self.state.stability;
-        let mut difficulty = self.state.difficulty;
-
-        let interval_for_grade =
-            |grade| Self::interval_for_grade(self, params, grade, retrievability);
-        // Note that we use the "current" state to choose our behaviour here.
-        // TODO: deduplicate the nodes below where we recompute [`stability_update_base`] a few times?
-        let interval = match self.state.status {
-            MemoryStatus::Learning | MemoryStatus::Relearning => {
-                let okay_interval = interval_for_grade(ReviewGrade::Okay);
-                let min_easy_interval = params.interval_step + okay_interval;
-                let easy_interval = min_easy_interval.max(interval_for_grade(ReviewGrade::Easy));
-                match grade {
-                    ReviewGrade::Fail => params.second_interval,
-                    ReviewGrade::Hard => params.third_interval,
-                    ReviewGrade::Okay => okay_interval,
-                    ReviewGrade::Easy => easy_interval,
-                }
-            }
-            MemoryStatus::Reviewing => {
-                stability = Self::stability_for_grade(self, params, grade, retrievability);
-                difficulty = w
-                    .diff_upd_mean_rev
-                    .mul_add(
-                        w.diff_base,
-                        (1.0 - w.diff_upd_mean_rev)
-                            * grade
-                                .as_factor()
-                                .mul_add(-w.diff_upd_scale, self.state.difficulty),
-                    )
-                    .clamp(params.min_difficulty, params.max_difficulty);
-                let theo_hard_interval = interval_for_grade(ReviewGrade::Hard);
-                let theo_okay_interval = interval_for_grade(ReviewGrade::Okay);
-                let theo_easy_interval = interval_for_grade(ReviewGrade::Easy);
-                let hard_interval = theo_hard_interval.min(theo_okay_interval);
-                let okay_interval = theo_okay_interval.max(params.interval_step + hard_interval);
-                let easy_interval = theo_easy_interval.max(params.interval_step + okay_interval);
-                match grade {
-                    ReviewGrade::Fail => params.second_interval,
-                    ReviewGrade::Hard => hard_interval,
-                    ReviewGrade::Okay => okay_interval,
-                    ReviewGrade::Easy => easy_interval,
-                }
-            }
-        };
+        let Schedule {
+            difficulty,
+            stability,
+            interval,
+        } = params.scheduler.review(params, self, grade, now);
 
         let review = MnemeState {
             id: new_review_id,
+            index: self.state.index + 1,
             stability,
             difficulty,
             status: state,
             due_at: self.next_due,
             reviewed_at: now,
+            elapsed_days: elapsed_days(self.state.reviewed_at, now),
             grade,
+            params_version: params.version,
+            latency_ms: None,
+            reset: false,
+            scheduler: params.scheduler,
         };
 
         MnemeUpdate {
+            next_due: now
+                + fuzz::adjust_interval(params, interval, now, self.id, review.index, load),
             new_state: review,
-            next_due: now + interval,
+        }
+    }
+
+    /// The mneme as it would be after starting it over at `now`, in the state
+    /// a failed creation would leave it in, but due right away.
+    pub(crate) fn reset_at_with_id(
+        &self,
+        params: &Params,
+        now: DateTime<Utc>,
+        new_review_id: Uuid,
+    ) -> Self {
+        let fresh = Self::init_at_with_id(params, ReviewGrade::Fail, now, self.id, new_review_id);
+        Self {
+            next_due: now,
+            state: MnemeState {
+                index: self.state.index + 1,
+                reset: true,
+                ..fresh.state
+            },
+            ..self.clone()
+        }
+    }
+
+    /// The mneme as it would be after the given review.
+    pub(crate) fn reviewed_with_id(
+        &self,
+        params: &Params,
+        grade: ReviewGrade,
+        now: DateTime<Utc>,
+        new_review_id: Uuid,
+    ) -> Self {
+        let MnemeUpdate {
+            next_due,
+            new_state,
+        } = self.review_with_id(params, grade, now, new_review_id, None);
+        Self {
+            next_due,
+            state: new_state,
+            ..self.clone()
+        }
+    }
+}
+
+/// A mneme joined with its current state.
+struct MnemeRow {
+    id: Uuid,
+    created_at: DateTime<Utc>,
+    next_due: DateTime<Utc>,
+    suspended: bool,
+    buried_until: Option<DateTime<Utc>>,
+    leech: bool,
+    state_id: Uuid,
+    index: i32,
+    grade: ReviewGrade,
+    status: MemoryStatus,
+    due_at: DateTime<Utc>,
+    reviewed_at: DateTime<Utc>,
+    elapsed_days: f64,
+    difficulty: f64,
+    stability: f64,
+    params_version: i32,
+    latency_ms: Option<i32>,
+    reset: bool,
+    scheduler: SchedulerKind,
+}
+
+impl From<MnemeRow> for Mneme {
+    fn from(row: MnemeRow) -> Self {
+        Self {
+            id: row.id,
+            created_at: row.created_at,
+            next_due: row.next_due,
+            state: MnemeState {
+                id: row.state_id,
+                index: row.index,
+                grade: row.grade,
+                status: row.status,
+                due_at: row.due_at,
+                reviewed_at: row.reviewed_at,
+                elapsed_days: row.elapsed_days,
+                difficulty: row.difficulty,
+                stability: row.stability,
+                params_version: row.params_version,
+                latency_ms: row.latency_ms,
+                reset: row.reset,
+                scheduler: row.scheduler,
+            },
+            suspended: row.suspended,
+            buried_until: row.buried_until,
+            leech: row.leech,
         }
     }
 }
 
 impl Mneme {
-    pub async fn create(pool: &PgPool, params: &Params, grade: ReviewGrade) -> Result<Uuid> {
-        Self::init(params, grade).persist(pool).await
+    pub async fn create(
+        conn: &mut PgConnection,
+        params: &Params,
+        grade: ReviewGrade,
+    ) -> Result<Uuid> {
+        Self::init(params, grade).persist(conn).await
     }
 
-    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Self> {
-        struct RawMneme {
-            id: Uuid,
-            created_at: DateTime<Utc>,
-            next_due: DateTime<Utc>,
-            state_id: Uuid,
-        }
-        let RawMneme {
-            id,
-            created_at,
-            next_due,
-            state_id,
-        } = sqlx::query_as!(
-            RawMneme,
-            "SELECT id, created_at, next_due, state_id FROM mnemes WHERE id = $1",
+    pub async fn get_by_id(executor: impl PgExecutor<'_>, id: Uuid) -> Result<Self> {
+        let row = sqlx::query_as!(
+            MnemeRow,
+            r#"SELECT
+mnemes.id,
+mnemes.created_at,
+mnemes.next_due,
+mnemes.suspended,
+mnemes.buried_until,
+mnemes.leech,
+mneme_states.id AS state_id,
+mneme_states.index,
+mneme_states.grade AS "grade: _",
+mneme_states.status AS "status: _",
+mneme_states.due_at,
+mneme_states.reviewed_at,
+mneme_states.elapsed_days,
+mneme_states.difficulty,
+mneme_states.stability,
+mneme_states.params_version,
+mneme_states.latency_ms,
+mneme_states.reset,
+mneme_states.scheduler AS "scheduler: _"
+FROM mnemes
+JOIN mneme_states ON mneme_states.id = mnemes.state_id
+WHERE mnemes.id = $1"#,
             id
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
+        .await
+        .context(GetMnemesCtx)?;
+        Ok(row.into())
+    }
+
+    /// Load every mneme in its current state.
+    pub async fn get_all(executor: impl PgExecutor<'_>) -> Result<Vec<Self>> {
+        let rows = sqlx::query_as!(
+            MnemeRow,
+            r#"SELECT
+mnemes.id,
+mnemes.created_at,
+mnemes.next_due,
+mnemes.suspended,
+mnemes.buried_until,
+mnemes.leech,
+mneme_states.id AS state_id,
+mneme_states.index,
+mneme_states.grade AS "grade: _",
+mneme_states.status AS "status: _",
+mneme_states.due_at,
+mneme_states.reviewed_at,
+mneme_states.elapsed_days,
+mneme_states.difficulty,
+mneme_states.stability,
+mneme_states.params_version,
+mneme_states.latency_ms,
+mneme_states.reset,
+mneme_states.scheduler AS "scheduler: _"
+FROM mnemes
+JOIN mneme_states ON mneme_states.id = mnemes.state_id
+ORDER BY mnemes.created_at, mnemes.id"#
+        )
+        .fetch_all(executor)
         .await
-        .unwrap();
-        let state = MnemeState::get_by_id(pool, state_id).await.unwrap();
+        .context(GetMnemesCtx)?;
+        Ok(rows.into_iter().map(Self::from).collect())
+    }
 
-        Ok(Mneme {
-            id,
-            created_at,
-            next_due,
-            state,
-        })
+    /// Load every mneme that is due at `now` and can be queued for review,
+    /// which means it is active and is the card of some variant.
+    pub async fn get_due(executor: impl PgExecutor<'_>, now: DateTime<Utc>) -> Result<Vec<Self>> {
+        let rows = sqlx::query_as!(
+            MnemeRow,
+            r#"SELECT
+mnemes.id,
+mnemes.created_at,
+mnemes.next_due,
+mnemes.suspended,
+mnemes.buried_until,
+mnemes.leech,
+mneme_states.id AS state_id,
+mneme_states.index,
+mneme_states.grade AS "grade: _",
+mneme_states.status AS "status: _",
+mneme_states.due_at,
+mneme_states.reviewed_at,
+mneme_states.elapsed_days,
+mneme_states.difficulty,
+mneme_states.stability,
+mneme_states.params_version,
+mneme_states.latency_ms,
+mneme_states.reset,
+mneme_states.scheduler AS "scheduler: _"
+FROM mnemes
+JOIN mneme_states ON mneme_states.id = mnemes.state_id
+WHERE mnemes.next_due <= $1
+AND NOT mnemes.suspended
+AND (mnemes.buried_until IS NULL OR mnemes.buried_until <= $1)
+AND EXISTS (SELECT FROM variant_mnemes WHERE variant_mnemes.mneme_id = mnemes.id)
+ORDER BY mnemes.next_due, mnemes.id"#,
+            now
+        )
+        .fetch_all(executor)
+        .await
+        .context(GetMnemesCtx)?;
+        Ok(rows.into_iter().map(Self::from).collect())
+    }
+
+    pub(crate) async fn persist(self, conn: &mut PgConnection) -> Result<Uuid> {
+        let mut tx = conn.begin().await.context(PersistMnemeCtx)?;
+        let new_id = self.insert(&mut tx).await?;
+        tx.commit().await.context(PersistMnemeCtx)?;
+        Ok(new_id)
     }
 
-    pub(crate) async fn persist(self, pool: &PgPool) -> Result<Uuid> {
+    /// Insert the mneme along with its current state. The state references
+    /// the mneme through a deferred constraint, so this has to happen inside
+    /// a transaction.
+    async fn insert(self, conn: &mut PgConnection) -> Result<Uuid> {
         let Self {
             id,
             created_at,
             next_due,
             state,
+            suspended,
+            buried_until,
+            leech,
         } = self;
 
         let state_id = state.id;
-        state.persist(pool).await.unwrap();
+        state.persist(&mut *conn, id).await.context(StatesCtx)?;
 
         let new_id = sqlx::query_scalar!(
-            "INSERT INTO mnemes (id, created_at, next_due, state_id) VALUES ($1, $2, $3, $4) RETURNING id",
+            r#"INSERT INTO mnemes (id, created_at, next_due, state_id, suspended, buried_until, leech)
+VALUES ($1, $2, $3, $4, $5, $6, $7)
+RETURNING id"#,
             id,
             created_at,
             next_due,
-            state_id
+            state_id,
+            suspended,
+            buried_until,
+            leech
         )
-        .fetch_one(pool)
-        .await.context(PersistMnemeCtx)?;
+        .fetch_one(&mut *conn)
+        .await
+        .context(PersistMnemeCtx)?;
 
         Ok(new_id)
     }
 
     // TODO provide the new state with RETURNING and a join
     pub async fn review_by_id(
-        pool: &PgPool,
+        conn: &mut PgConnection,
         id: Uuid,
         params: &Params,
         grade: ReviewGrade,
-    ) -> Result<()> {
-        let mneme = Self::get_by_id(pool, id).await.unwrap();
-        mneme.review(pool, params, grade).await
+        latency: Option<Duration>,
+    ) -> Result<Uuid> {
+        let mut tx = conn.begin().await.context(PersistMnemeCtx)?;
+        let mneme = Self::get_by_id(&mut *tx, id).await?;
+        let state_id = mneme.review(&mut tx, params, grade, latency).await?;
+        tx.commit().await.context(PersistMnemeCtx)?;
+        Ok(state_id)
+    }
+
+    /// The mneme in the terms of the scheduler of `params`. Difficulty and
+    /// stability mean something else to each scheduler, so if the current
+    /// state comes from another one, the log is replayed with `params` first.
+    pub async fn for_scheduler(self, conn: &mut PgConnection, params: &Params) -> Result<Self> {
+        if self.state.scheduler == params.scheduler {
+            return Ok(self);
+        }
+        let history = MnemeState::get_all_for_mneme(conn, self.id)
+            .await
+            .context(StatesCtx)?
+            .into_iter()
+            .filter(|s| s.index < self.state.index)
+            .collect();
+        let item = MnemeWithHistory {
+            mneme: self,
+            history,
+        };
+        Ok(replay::replay(params, &item).mneme)
     }
 
-    pub async fn review(&self, pool: &PgPool, params: &Params, grade: ReviewGrade) -> Result<()> {
+    /// Append a review to the log of this mneme and make it the current state,
+    /// returning the ID of that state. When `conn` is already a transaction,
+    /// the review is only applied if that transaction is committed. `latency`
+    /// is how long the answer took, if it was timed, which may make the review
+    /// count as a harder one.
+    ///
+    /// The mneme is locked while the review is applied, and if it was reviewed
+    /// since `self` was read, the review follows on from that one instead.
+    pub async fn review(
+        &self,
+        conn: &mut PgConnection,
+        params: &Params,
+        grade: ReviewGrade,
+        latency: Option<Duration>,
+    ) -> Result<Uuid> {
+        let grade = params.grade_for_latency(grade, latency);
+        let mut tx = conn.begin().await.context(PersistMnemeCtx)?;
+        let now = pg_compatible_now();
+        let new_review_id = Uuid::new_v4();
+        let current_state_id = sqlx::query_scalar!(
+            "SELECT state_id FROM mnemes WHERE id = $1 FOR UPDATE",
+            self.id
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context(PersistMnemeCtx)?;
+        let mneme = if current_state_id == self.state.id {
+            self.clone()
+        } else {
+            Self::get_by_id(&mut *tx, self.id).await?
+        };
+        let mneme = mneme.for_scheduler(&mut tx, params).await?;
+        let mut update = mneme.review_with_id(params, grade, now, new_review_id, None);
+        if params.load_balance {
+            let (from, to) = fuzz::balancing_window(now, update.next_due - now);
+            let load = DueLoad::get_for_range(&mut *tx, from, to)
+                .await
+                .context(GetDueLoadCtx)?;
+            update = mneme.review_with_id(params, grade, now, new_review_id, Some(&load));
+        }
         let MnemeUpdate {
             next_due,
-            new_state,
-        } = self.review_with_id(params, grade, pg_compatible_now(), Uuid::new_v4());
+            mut new_state,
+        } = update;
+        new_state.latency_ms =
+            latency.map(|l| l.num_milliseconds().clamp(0, i32::MAX as i64) as i32);
         let new_state_id = new_state.id;
-        new_state.persist(pool).await.unwrap();
+        new_state
+            .persist(&mut tx, self.id)
+            .await
+            .context(StatesCtx)?;
         sqlx::query!(
             r#"
 UPDATE mnemes
@@ -328,9 +577,221 @@ WHERE id = $1
             new_state_id,
             next_due
         )
-        .execute(pool)
+        .execute(&mut *tx)
+        .await
+        .context(PersistMnemeCtx)?;
+        // Wherever the review happens, it counts for any queued session.
+        sqlx::query!(
+            "UPDATE review_session_items SET done_at = $2 WHERE mneme_id = $1 AND done_at IS NULL",
+            self.id,
+            new_state.reviewed_at
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistMnemeCtx)?;
+        // Every lapse from the threshold on tags the mneme again, even if the
+        // tag was cleared in the meantime.
+        if mneme.state.status == MemoryStatus::Reviewing && grade == ReviewGrade::Fail {
+            let lapses = Self::count_lapses(&mut *tx, self.id).await?;
+            if lapses >= params.leech_threshold as usize {
+                // Noted on the state first, while the flags are as they were.
+                sqlx::query!(
+                    r#"UPDATE mneme_states
+SET tagged_leech = NOT mnemes.leech, suspended_leech = $3 AND NOT mnemes.suspended
+FROM mnemes
+WHERE mneme_states.id = $2 AND mnemes.id = $1"#,
+                    self.id,
+                    new_state_id,
+                    params.suspend_leeches
+                )
+                .execute(&mut *tx)
+                .await
+                .context(PersistMnemeCtx)?;
+                sqlx::query!(
+                    "UPDATE mnemes SET leech = true, suspended = suspended OR $2 WHERE id = $1",
+                    self.id,
+                    params.suspend_leeches
+                )
+                .execute(&mut *tx)
+                .await
+                .context(PersistMnemeCtx)?;
+            }
+        }
+        tx.commit().await.context(PersistMnemeCtx)?;
+        Ok(new_state_id)
+    }
+
+    /// Number of times the mneme was forgotten while under review, i.e. failed
+    /// after having made it out of learning, not counting undone reviews or
+    /// the ones before the mneme was last reset.
+    pub async fn count_lapses(executor: impl PgExecutor<'_>, id: Uuid) -> Result<usize> {
+        let lapses = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!"
+FROM mneme_states s
+JOIN mneme_states prev ON prev.mneme_id = s.mneme_id AND prev.index = s.index - 1
+WHERE s.mneme_id = $1
+  AND s.undone_at IS NULL AND prev.undone_at IS NULL
+  AND prev.status = 'Reviewing' AND s.grade = 'Fail'
+  AND s.index > (SELECT COALESCE(MAX(r.index), 0) FROM mneme_states r
+    WHERE r.mneme_id = s.mneme_id AND r.reset AND r.undone_at IS NULL)"#,
+            id
+        )
+        .fetch_one(executor)
+        .await
+        .context(GetMnemesCtx)?;
+        Ok(lapses as usize)
+    }
+
+    /// Undo the review that left the mneme in the state `state_id`, as long as
+    /// that is still its current state, so that undoing the same review twice
+    /// does not undo the one before it as well.
+    pub async fn undo_review_by_id(
+        conn: &mut PgConnection,
+        id: Uuid,
+        state_id: Uuid,
+    ) -> Result<()> {
+        let mut tx = conn.begin().await.context(UndoReviewCtx)?;
+        sqlx::query!("SELECT id FROM mnemes WHERE id = $1 FOR UPDATE", id)
+            .fetch_one(&mut *tx)
+            .await
+            .context(UndoReviewCtx)?;
+        let mneme = Self::get_by_id(&mut *tx, id).await?;
+        ensure!(
+            mneme.state.id == state_id,
+            NotCurrentStateCtx { id, state_id }
+        );
+        mneme.undo_last_review(&mut tx).await?;
+        tx.commit().await.context(UndoReviewCtx)
+    }
+
+    /// Make the state before the last review current again, as if the review
+    /// had never happened. The undone state stays in the database, marked as
+    /// such, and any session that queued this mneme gets it back. If the review
+    /// made the mneme a leech, the tag comes off again, as does the suspension
+    /// that came with it. A reset is not a review and cannot be undone.
+    pub async fn undo_last_review(&self, conn: &mut PgConnection) -> Result<()> {
+        let state = self.state;
+        if state.is_start() {
+            return NothingToUndoCtx { id: self.id }.fail();
+        }
+        let mut tx = conn.begin().await.context(UndoReviewCtx)?;
+        let previous_id = sqlx::query_scalar!(
+            "SELECT id FROM mneme_states WHERE mneme_id = $1 AND index = $2 AND undone_at IS NULL",
+            self.id,
+            state.index - 1
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context(UndoReviewCtx)?;
+        let undone = sqlx::query!(
+            r#"UPDATE mneme_states SET undone_at = $2 WHERE id = $1
+RETURNING tagged_leech, suspended_leech"#,
+            state.id,
+            pg_compatible_now()
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context(UndoReviewCtx)?;
+        // The state records when the mneme was due before the review.
+        sqlx::query!(
+            r#"UPDATE mnemes
+SET state_id = $2, next_due = $3, leech = leech AND NOT $4, suspended = suspended AND NOT $5
+WHERE id = $1"#,
+            self.id,
+            previous_id,
+            state.due_at,
+            undone.tagged_leech,
+            undone.suspended_leech
+        )
+        .execute(&mut *tx)
+        .await
+        .context(UndoReviewCtx)?;
+        sqlx::query!(
+            "UPDATE review_session_items SET done_at = NULL WHERE mneme_id = $1 AND done_at = $2",
+            self.id,
+            state.reviewed_at
+        )
+        .execute(&mut *tx)
+        .await
+        .context(UndoReviewCtx)?;
+        tx.commit().await.context(UndoReviewCtx)?;
+        Ok(())
+    }
+
+    pub async fn set_suspended(
+        executor: impl PgExecutor<'_>,
+        id: Uuid,
+        suspended: bool,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE mnemes SET suspended = $2 WHERE id = $1",
+            id,
+            suspended
+        )
+        .execute(executor)
+        .await
+        .context(UpdateFlagsCtx)?;
+        Ok(())
+    }
+
+    /// Keep the mneme out of the queue until the given point in time, or put
+    /// it back right away if `None`.
+    pub async fn set_buried_until(
+        executor: impl PgExecutor<'_>,
+        id: Uuid,
+        buried_until: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "UPDATE mnemes SET buried_until = $2 WHERE id = $1",
+            id,
+            buried_until
+        )
+        .execute(executor)
+        .await
+        .context(UpdateFlagsCtx)?;
+        Ok(())
+    }
+
+    /// Tag the mneme as a leech or clear the tag. This does not suspend or
+    /// unsuspend it.
+    pub async fn set_leech(executor: impl PgExecutor<'_>, id: Uuid, leech: bool) -> Result<()> {
+        sqlx::query!("UPDATE mnemes SET leech = $2 WHERE id = $1", id, leech)
+            .execute(executor)
+            .await
+            .context(UpdateFlagsCtx)?;
+        Ok(())
+    }
+
+    pub async fn reset_by_id(conn: &mut PgConnection, id: Uuid, params: &Params) -> Result<()> {
+        let mut tx = conn.begin().await.context(ResetMnemeCtx)?;
+        let mneme = Self::get_by_id(&mut *tx, id).await?;
+        mneme.reset(&mut tx, params).await?;
+        tx.commit().await.context(ResetMnemeCtx)
+    }
+
+    /// Start the mneme over as a new one that is due right away. This adds a
+    /// state to its log that marks the reset, and the reviews before it still
+    /// count for statistics and fitting weights, but not as lapses, which is
+    /// why the leech tag goes too.
+    pub async fn reset(&self, conn: &mut PgConnection, params: &Params) -> Result<()> {
+        let fresh = self.reset_at_with_id(params, pg_compatible_now(), Uuid::new_v4());
+        let mut tx = conn.begin().await.context(ResetMnemeCtx)?;
+        let state_id = fresh.state.id;
+        fresh
+            .state
+            .persist(&mut tx, self.id)
+            .await
+            .context(StatesCtx)?;
+        sqlx::query!(
+            "UPDATE mnemes SET state_id = $2, next_due = $3, leech = false WHERE id = $1",
+            self.id,
+            state_id,
+            fresh.next_due
+        )
+        .execute(&mut *tx)
         .await
-        .unwrap();
+        .context(ResetMnemeCtx)?;
+        tx.commit().await.context(ResetMnemeCtx)?;
         Ok(())
     }
 }
@@ -342,6 +803,21 @@ pub struct MnemeWithHistory {
 }
 
 impl MnemeWithHistory {
+    pub fn init(params: &Params, grade: ReviewGrade) -> Self {
+        Self {
+            mneme: Mneme::init(params, grade),
+            history: Vec::new(),
+        }
+    }
+
+    /// A mneme created by a review at the given point in time.
+    pub fn init_at(params: &Params, grade: ReviewGrade, now: DateTime<Utc>) -> Self {
+        Self {
+            mneme: Mneme::init_at(params, grade, now),
+            history: Vec::new(),
+        }
+    }
+
     #[cfg(test)]
     pub(crate) fn init_at_with_id(
         params: &Params,
@@ -356,15 +832,14 @@ impl MnemeWithHistory {
         }
     }
 
-    // fn add_review_now(&self, params: &Params, grade: ReviewGrade) -> Self {
-    //     self.add_review(params, grade, Utc::now())
-    // }
+    pub fn add_review_now(&self, params: &Params, grade: ReviewGrade) -> Self {
+        self.add_review(params, grade, pg_compatible_now())
+    }
 
-    // fn add_review(&self, params: &Params, grade: ReviewGrade, now: DateTime<Utc>) -> Self {
-    //     self.add_review_with_id(params, grade, now, Uuid::new_v4())
-    // }
+    pub fn add_review(&self, params: &Params, grade: ReviewGrade, now: DateTime<Utc>) -> Self {
+        self.add_review_with_id(params, grade, now, Uuid::new_v4())
+    }
 
-    #[cfg(test)]
     pub(crate) fn add_review_with_id(
         &self,
         params: &Params,
@@ -374,28 +849,131 @@ impl MnemeWithHistory {
     ) -> Self {
         // It's an append-only log!
         let mut history = self.history.clone();
-        history.push(self.mneme.state.clone());
-        let MnemeUpdate {
-            next_due,
-            new_state,
-        } = self.mneme.review_with_id(params, grade, now, new_review_id);
+        history.push(self.mneme.state);
         Self {
+            mneme: self
+                .mneme
+                .reviewed_with_id(params, grade, now, new_review_id),
+            history,
+        }
+    }
+
+    /// The mneme as it was before its last review, or `None` if it has not
+    /// been reviewed since it was created or reset.
+    pub fn undo_last_review(&self) -> Option<Self> {
+        if self.mneme.state.is_start() {
+            return None;
+        }
+        let mut history = self.history.clone();
+        let state = history.pop()?;
+        Some(Self {
             mneme: Mneme {
-                id: self.mneme.id,
-                state: new_state,
-                next_due,
-                created_at: self.mneme.created_at.clone(),
+                next_due: self.mneme.state.due_at,
+                state,
+                ..self.mneme.clone()
             },
             history,
+        })
+    }
+
+    /// Load a mneme along with all of its past states.
+    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Self> {
+        let mneme = Mneme::get_by_id(pool, id).await?;
+        let history = MnemeState::get_all_for_mneme(pool, id)
+            .await
+            .context(StatesCtx)?
+            .into_iter()
+            .filter(|s| s.index < mneme.state.index)
+            .collect();
+        Ok(Self { mneme, history })
+    }
+
+    /// Load every mneme along with all of its past states.
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>> {
+        struct RawMneme {
+            id: Uuid,
+            created_at: DateTime<Utc>,
+            next_due: DateTime<Utc>,
+            state_id: Uuid,
+            suspended: bool,
+            buried_until: Option<DateTime<Utc>>,
+            leech: bool,
         }
+        let raw_mnemes = sqlx::query_as!(
+            RawMneme,
+            r#"SELECT id, created_at, next_due, state_id, suspended, buried_until, leech
+FROM mnemes
+ORDER BY created_at, id"#
+        )
+        .fetch_all(pool)
+        .await
+        .context(GetMnemesCtx)?;
+        let mut states = MnemeState::get_all_by_mneme(pool)
+            .await
+            .context(StatesCtx)?;
+
+        let mut r = Vec::with_capacity(raw_mnemes.len());
+        for RawMneme {
+            id,
+            created_at,
+            next_due,
+            state_id,
+            suspended,
+            buried_until,
+            leech,
+        } in raw_mnemes
+        {
+            let mut history = states.remove(&id).unwrap_or_default();
+            let Some(current) = history.iter().position(|s| s.id == state_id) else {
+                return MissingStateCtx { id, state_id }.fail();
+            };
+            let state = history.remove(current);
+            history.retain(|s| s.index < state.index);
+            r.push(Self {
+                mneme: Mneme {
+                    id,
+                    created_at,
+                    next_due,
+                    state,
+                    suspended,
+                    buried_until,
+                    leech,
+                },
+                history,
+            });
+        }
+        Ok(r)
+    }
+
+    /// Number of times the mneme was forgotten while under review, as in
+    /// [`Mneme::count_lapses`].
+    pub fn lapses(&self) -> usize {
+        let states: Vec<_> = self.states().collect();
+        let last_reset = states.iter().rposition(|s| s.reset).unwrap_or(0);
+        states[last_reset..]
+            .windows(2)
+            .filter(|pair| {
+                pair[0].status == MemoryStatus::Reviewing && pair[1].grade == ReviewGrade::Fail
+            })
+            .count()
     }
 
-    pub async fn persist(self, pool: &PgPool) -> Result<(), ()> {
+    /// All states of the mneme in review order, ending with the current one.
+    pub fn states(&self) -> impl Iterator<Item = &MnemeState> {
+        self.history
+            .iter()
+            .chain(std::iter::once(&self.mneme.state))
+    }
+
+    /// Insert the mneme along with its whole log, all or nothing.
+    pub async fn persist(self, conn: &mut PgConnection) -> Result<()> {
         let Self { mneme, history } = self;
-        mneme.persist(pool).await.unwrap();
+        let id = mneme.id;
+        let mut tx = conn.begin().await.context(PersistMnemeCtx)?;
+        mneme.insert(&mut tx).await?;
         for s in history {
-            s.persist(pool).await.unwrap();
+            s.persist(&mut tx, id).await.context(StatesCtx)?;
         }
-        Ok(())
+        tx.commit().await.context(PersistMnemeCtx)
     }
 }
@@ -1,69 +1,202 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use sqlx::{types::Uuid, PgPool};
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgConnection, PgExecutor};
+
+use crate::{memory_status::MemoryStatus, review_grade::ReviewGrade, scheduler::SchedulerKind};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetStates { source: sqlx::Error },
+    PersistState { source: sqlx::Error },
+}
 
-use crate::{memory_status::MemoryStatus, review_grade::ReviewGrade};
+type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, Copy, Clone, serde::Serialize, serde::Deserialize, PartialEq, PartialOrd)]
 pub struct MnemeState {
     pub id: Uuid,
+    /// Position of this state in the review log of its mneme, starting from
+    /// zero for the initial review.
+    pub index: i32,
     pub grade: ReviewGrade,
     pub status: MemoryStatus,
     pub due_at: DateTime<Utc>,
     pub reviewed_at: DateTime<Utc>,
+    /// Time between the previous review and this one, in days.
+    pub elapsed_days: f64,
     pub difficulty: f64,
     pub stability: f64,
-    // TODO fk to params used to perform this review?
-    // or do we decide to actually remove as much data as possible
-    // and literally regenerate intermediate due dates and ... no, probably not
+    /// Version of the parameters used to perform this review, which also
+    /// identifies the profile it was performed under.
+    pub params_version: i32,
+    /// Time taken to answer, in milliseconds, if it was measured.
+    pub latency_ms: Option<i32>,
+    /// Whether this state comes from resetting the mneme rather than from a
+    /// review, in which case the mneme starts over from it.
+    #[serde(default)]
+    pub reset: bool,
+    /// Scheduler that computed the difficulty and stability, which each one
+    /// means something else by.
+    #[serde(default)]
+    pub scheduler: SchedulerKind,
 }
 
 impl MnemeState {
-    pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Self, ()> {
+    /// Whether the mneme has not been reviewed since this state, because it
+    /// was created or reset with it.
+    pub fn is_start(&self) -> bool {
+        self.index == 0 || self.reset
+    }
+
+    pub async fn get_by_id(executor: impl PgExecutor<'_>, id: Uuid) -> Result<Self> {
         let r = sqlx::query_as!(
             Self,
             r#"SELECT
 id,
+index,
 grade AS "grade: _",
 status AS "status: _",
 due_at,
 reviewed_at,
- difficulty,
-stability
+elapsed_days,
+difficulty,
+stability,
+params_version,
+latency_ms,
+reset,
+scheduler AS "scheduler: _"
 FROM mneme_states WHERE id = $1"#,
             id
         )
-        .fetch_one(pool)
+        .fetch_one(executor)
         .await
-        .unwrap();
+        .context(GetStatesCtx)?;
         Ok(r)
     }
 
-    pub(crate) async fn persist(self, pool: &PgPool) -> Result<(), ()> {
+    /// All the states recorded for a mneme, in review order. Undone reviews
+    /// are left out.
+    pub async fn get_all_for_mneme(
+        executor: impl PgExecutor<'_>,
+        mneme_id: Uuid,
+    ) -> Result<Vec<Self>> {
+        let r = sqlx::query_as!(
+            Self,
+            r#"SELECT
+id,
+index,
+grade AS "grade: _",
+status AS "status: _",
+due_at,
+reviewed_at,
+elapsed_days,
+difficulty,
+stability,
+params_version,
+latency_ms,
+reset,
+scheduler AS "scheduler: _"
+FROM mneme_states WHERE mneme_id = $1 AND undone_at IS NULL
+ORDER BY index ASC"#,
+            mneme_id
+        )
+        .fetch_all(executor)
+        .await
+        .context(GetStatesCtx)?;
+        Ok(r)
+    }
+
+    /// The states of every mneme, grouped by mneme and in review order.
+    pub async fn get_all_by_mneme(
+        executor: impl PgExecutor<'_>,
+    ) -> Result<HashMap<Uuid, Vec<Self>>> {
+        let rows = sqlx::query!(
+            r#"SELECT
+mneme_id,
+id,
+index,
+grade AS "grade: ReviewGrade",
+status AS "status: MemoryStatus",
+due_at,
+reviewed_at,
+elapsed_days,
+difficulty,
+stability,
+params_version,
+latency_ms,
+reset,
+scheduler AS "scheduler: SchedulerKind"
+FROM mneme_states
+WHERE undone_at IS NULL
+ORDER BY mneme_id, index ASC"#
+        )
+        .fetch_all(executor)
+        .await
+        .context(GetStatesCtx)?;
+        let mut r: HashMap<Uuid, Vec<Self>> = HashMap::new();
+        for row in rows {
+            r.entry(row.mneme_id).or_default().push(Self {
+                id: row.id,
+                index: row.index,
+                grade: row.grade,
+                status: row.status,
+                due_at: row.due_at,
+                reviewed_at: row.reviewed_at,
+                elapsed_days: row.elapsed_days,
+                difficulty: row.difficulty,
+                stability: row.stability,
+                params_version: row.params_version,
+                latency_ms: row.latency_ms,
+                reset: row.reset,
+                scheduler: row.scheduler,
+            });
+        }
+        Ok(r)
+    }
+
+    pub(crate) async fn persist(self, conn: &mut PgConnection, mneme_id: Uuid) -> Result<()> {
         let Self {
             id,
+            index,
             grade,
             status,
             due_at,
             reviewed_at,
+            elapsed_days,
             difficulty,
             stability,
+            params_version,
+            latency_ms,
+            reset,
+            scheduler,
         } = self;
         sqlx::query!(
-            r#"INSERT INTO mneme_states (id, grade, status, due_at, reviewed_at, difficulty, stability)
-VALUES ($1, $2, $3, $4, $5, $6, $7)"#,
+            r#"INSERT INTO mneme_states
+(id, mneme_id, index, grade, status, due_at, reviewed_at, elapsed_days, difficulty, stability, params_version, latency_ms, reset, scheduler)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)"#,
             id,
+            mneme_id,
+            index,
             // disable compile-time checking for the enum mappings
             // https://github.com/launchbadge/sqlx/issues/1004
             grade as _,
             status as _,
             due_at,
             reviewed_at,
+            elapsed_days,
             difficulty,
-            stability
+            stability,
+            params_version,
+            latency_ms,
+            reset,
+            scheduler as _
         )
-        .execute(pool)
+        .execute(conn)
         .await
-        .unwrap();
+        .context(PersistStateCtx)?;
         Ok(())
     }
 }
@@ -0,0 +1,81 @@
+//! What the learner writes down about a variant.
+//!
+//! Notes belong to the variant rather than to one of its mnemes, so every card
+//! of the variant shows the same ones. The mnemonic and the notes are
+//! markdown, while the gloss is a line of plain text to go with the
+//! dictionary definitions.
+
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgExecutor};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetNote { source: sqlx::Error },
+    PersistNote { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Note {
+    /// A meaning in the learner's own words.
+    pub gloss: String,
+    /// A story or other device to remember the variant by.
+    pub mnemonic: String,
+    /// Anything else.
+    pub notes: String,
+}
+
+impl Note {
+    pub fn is_empty(&self) -> bool {
+        self.gloss.is_empty() && self.mnemonic.is_empty() && self.notes.is_empty()
+    }
+
+    /// The note of a variant, which is empty if nothing was written yet.
+    pub async fn get(executor: impl PgExecutor<'_>, variant_id: Uuid) -> Result<Self> {
+        let note = sqlx::query_as!(
+            Self,
+            "SELECT gloss, mnemonic, notes FROM variant_notes WHERE variant_id = $1",
+            variant_id
+        )
+        .fetch_optional(executor)
+        .await
+        .context(GetNoteCtx)?;
+        Ok(note.unwrap_or_default())
+    }
+
+    /// Replace the note of a variant. Surrounding whitespace is dropped, and
+    /// an empty note is removed altogether.
+    pub async fn save(&self, executor: impl PgExecutor<'_>, variant_id: Uuid) -> Result<()> {
+        let note = Self {
+            gloss: self.gloss.trim().to_owned(),
+            mnemonic: self.mnemonic.trim().to_owned(),
+            notes: self.notes.trim().to_owned(),
+        };
+        if note.is_empty() {
+            sqlx::query!(
+                "DELETE FROM variant_notes WHERE variant_id = $1",
+                variant_id
+            )
+            .execute(executor)
+            .await
+            .context(PersistNoteCtx)?;
+            return Ok(());
+        }
+        sqlx::query!(
+            r#"INSERT INTO variant_notes (variant_id, gloss, mnemonic, notes)
+VALUES ($1, $2, $3, $4)
+ON CONFLICT (variant_id) DO UPDATE
+SET gloss = EXCLUDED.gloss, mnemonic = EXCLUDED.mnemonic, notes = EXCLUDED.notes, updated_at = now()"#,
+            variant_id,
+            note.gloss,
+            note.mnemonic,
+            note.notes
+        )
+        .execute(executor)
+        .await
+        .context(PersistNoteCtx)?;
+        Ok(())
+    }
+}
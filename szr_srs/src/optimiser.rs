@@ -0,0 +1,247 @@
+//! Fitting the FSRS weights to a review log.
+//!
+//! Each mneme's log is replayed through the scheduler with a candidate set of
+//! weights, and every review that happened at least a day after the previous
+//! one is scored by the log-loss of the retrievability predicted for it. The
+//! loss is minimised with Adam, using central differences for the gradient:
+//! it costs a few more replays per step than differentiating the scheduler by
+//! hand, but it stays correct whenever the scheduler changes.
+//!
+//! The same loss, computed by [`evaluate`], says how well a set of parameters
+//! predicts a log. Only FSRS predicts recall at all, so there is nothing to
+//! score for SM-2.
+
+use snafu::{ResultExt, Snafu};
+use sqlx::PgPool;
+
+use crate::{
+    mneme::{self, Mneme, MnemeWithHistory},
+    params::{self, Params},
+    review_grade::ReviewGrade,
+    scheduler::{Fsrs, SchedulerKind},
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    LoadReviewLog { source: mneme::Error },
+    PersistWeights { source: params::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Bounds on each of the weights, loosely following the ones used by the
+/// reference FSRS optimiser. These keep the scheduler well-behaved while the
+/// optimiser explores.
+static WEIGHT_BOUNDS: [(f64, f64); 17] = [
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (0.1, 100.0),
+    (1.0, 10.0),
+    (0.1, 5.0),
+    (0.1, 5.0),
+    (0.0, 0.75),
+    (0.0, 4.5),
+    (0.1, 0.8),
+    (0.01, 3.5),
+    (0.1, 5.0),
+    (0.01, 0.25),
+    (0.01, 0.9),
+    (0.01, 4.0),
+    (0.0, 1.0),
+    (1.0, 6.0),
+];
+
+/// Keeps predicted retrievabilities away from 0 and 1 so that the loss stays
+/// finite.
+const RETRIEVABILITY_EPSILON: f64 = 1e-4;
+
+/// Relative size of the perturbation used to estimate each partial derivative.
+const GRADIENT_STEP: f64 = 1e-5;
+
+const ADAM_BETA_1: f64 = 0.9;
+const ADAM_BETA_2: f64 = 0.999;
+const ADAM_EPSILON: f64 = 1e-8;
+
+pub struct Optimiser {
+    /// Step size for Adam.
+    pub learning_rate: f64,
+    /// Number of gradient steps to take.
+    pub iterations: usize,
+}
+
+impl Default for Optimiser {
+    fn default() -> Self {
+        Self {
+            learning_rate: 4e-2,
+            iterations: 500,
+        }
+    }
+}
+
+/// The result of fitting the weights to a review log.
+#[derive(Debug, Clone)]
+pub struct Fitted {
+    pub weights: [f64; 17],
+    /// Mean log-loss of the weights we started from.
+    pub initial_loss: f64,
+    /// Mean log-loss of [`Self::weights`].
+    pub loss: f64,
+    /// Number of reviews the loss is computed over.
+    pub num_reviews: usize,
+}
+
+impl Fitted {
+    /// Store the weights as a new version of the profile of `params`, keeping
+    /// its other settings, and return that version.
+    pub async fn persist(&self, pool: &PgPool, params: &Params) -> Result<i32> {
+        params
+            .with_weight_vector(self.weights)
+            .insert(pool, Some(self.loss), Some(self.num_reviews as i32))
+            .await
+            .context(PersistWeightsCtx)
+    }
+}
+
+impl Optimiser {
+    /// Fit the weights to every review log in the database, starting from the
+    /// weights in `params`, and store the result as a new version of its
+    /// profile.
+    pub async fn optimise(&self, pool: &PgPool, params: &Params) -> Result<(i32, Fitted)> {
+        let items = MnemeWithHistory::get_all(pool)
+            .await
+            .context(LoadReviewLogCtx)?;
+        let fitted = self.fit(params, &items);
+        let version = fitted.persist(pool, params).await?;
+        Ok((version, fitted))
+    }
+
+    /// Fit the weights to the given review logs, starting from the weights in
+    /// `params`. The weights are left alone if none of the reviews can be
+    /// scored.
+    pub fn fit(&self, params: &Params, items: &[MnemeWithHistory]) -> Fitted {
+        let mut w = params.weights.vector;
+        for (w, (lo, hi)) in w.iter_mut().zip(WEIGHT_BOUNDS) {
+            *w = w.clamp(lo, hi);
+        }
+
+        let (initial_loss, num_reviews) = log_loss(params, w, items);
+        let mut best = (initial_loss, w);
+
+        if num_reviews > 0 {
+            let mut m = [0.0; 17];
+            let mut v = [0.0; 17];
+            for t in 1..=self.iterations {
+                let g = gradient(params, w, items);
+                for i in 0..17 {
+                    m[i] = ADAM_BETA_1 * m[i] + (1.0 - ADAM_BETA_1) * g[i];
+                    v[i] = ADAM_BETA_2 * v[i] + (1.0 - ADAM_BETA_2) * g[i] * g[i];
+                    let m_hat = m[i] / (1.0 - ADAM_BETA_1.powi(t as i32));
+                    let v_hat = v[i] / (1.0 - ADAM_BETA_2.powi(t as i32));
+                    let (lo, hi) = WEIGHT_BOUNDS[i];
+                    w[i] = (w[i] - self.learning_rate * m_hat / (v_hat.sqrt() + ADAM_EPSILON))
+                        .clamp(lo, hi);
+                }
+                let (loss, _) = log_loss(params, w, items);
+                // Adam does not decrease the loss monotonically.
+                if loss < best.0 {
+                    best = (loss, w);
+                }
+            }
+        }
+
+        let (loss, weights) = best;
+        Fitted {
+            weights,
+            initial_loss,
+            loss,
+            num_reviews,
+        }
+    }
+}
+
+/// Mean log-loss of the retrievability predicted by FSRS with the weights `w`
+/// over all reviews that can be scored, along with the number of such reviews.
+/// Only the weights are fitted, whichever scheduler `params` use.
+fn log_loss(params: &Params, w: [f64; 17], items: &[MnemeWithHistory]) -> (f64, usize) {
+    let params = Params {
+        scheduler: SchedulerKind::Fsrs,
+        ..params.with_weight_vector(w)
+    };
+    fsrs_log_loss(&params, items)
+}
+
+/// Mean log-loss of the retrievability predicted when replaying the given
+/// review logs with `params`, over every review that happened at least a day
+/// after the previous one, along with the number of such reviews. Resets start
+/// the mneme over without being scored. Lower is better. `None` unless
+/// `params` use FSRS, the only scheduler that predicts recall.
+pub fn evaluate(params: &Params, items: &[MnemeWithHistory]) -> Option<(f64, usize)> {
+    (params.scheduler == SchedulerKind::Fsrs).then(|| fsrs_log_loss(params, items))
+}
+
+/// [`evaluate`] for parameters known to use FSRS.
+fn fsrs_log_loss(params: &Params, items: &[MnemeWithHistory]) -> (f64, usize) {
+    let mut total = 0.0;
+    let mut n = 0;
+
+    for item in items {
+        let mut states = item.states();
+        let Some(first) = states.next() else {
+            continue;
+        };
+        let mut mneme = Mneme::init_at_with_id(
+            params,
+            first.grade,
+            first.reviewed_at,
+            item.mneme.id,
+            first.id,
+        );
+        for state in states {
+            if state.reset {
+                mneme = mneme.reset_at_with_id(params, state.reviewed_at, state.id);
+                continue;
+            }
+            // Same-day reviews say more about the learning steps than about
+            // the memory model.
+            if state.elapsed_days >= 1.0 {
+                // As the scheduler sees it when the review happens, so that the
+                // weights are fitted to the same curve they are used with.
+                let days = (state.reviewed_at - mneme.state.reviewed_at).num_days() as f64;
+                let r = Fsrs::retrievability(mneme.state.stability, days)
+                    .clamp(RETRIEVABILITY_EPSILON, 1.0 - RETRIEVABILITY_EPSILON);
+                total -= match state.grade {
+                    ReviewGrade::Fail => (1.0 - r).ln(),
+                    _ => r.ln(),
+                };
+                n += 1;
+            }
+            mneme = mneme.reviewed_with_id(params, state.grade, state.reviewed_at, state.id);
+        }
+    }
+
+    if n == 0 {
+        (0.0, 0)
+    } else {
+        (total / n as f64, n)
+    }
+}
+
+fn gradient(params: &Params, w: [f64; 17], items: &[MnemeWithHistory]) -> [f64; 17] {
+    let mut g = [0.0; 17];
+    for i in 0..17 {
+        let h = GRADIENT_STEP * w[i].abs().max(1.0);
+        let mut above = w;
+        above[i] += h;
+        let mut below = w;
+        below[i] -= h;
+        let (loss_above, _) = log_loss(params, above, items);
+        let (loss_below, _) = log_loss(params, below, items);
+        g[i] = (loss_above - loss_below) / (2.0 * h);
+        if !g[i].is_finite() {
+            g[i] = 0.0;
+        }
+    }
+    g
+}
@@ -1,10 +1,88 @@
 use chrono::Duration;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use sqlx::{PgExecutor, PgPool};
 
-use crate::{review_grade::ReviewGrade, weights::Weights};
+use crate::{queue::Limits, review_grade::ReviewGrade, scheduler::SchedulerKind, weights::Weights};
 
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    #[snafu(display("target retention must be strictly between 0 and 1, got {value}"))]
+    InvalidTargetRetention {
+        value: f64,
+    },
+    #[snafu(display("learning steps must be positive and nondecreasing"))]
+    InvalidLearningSteps,
+    #[snafu(display("answers can only be slow after a positive duration"))]
+    InvalidSlowOkayAfter,
+    #[snafu(display("leech threshold must be at least 1, got {value}"))]
+    InvalidLeechThreshold {
+        value: i32,
+    },
+    #[snafu(display("there is no profile named {profile:?}"))]
+    UnknownProfile {
+        profile: String,
+    },
+    #[snafu(display("profile names can only have letters, digits, '-' and '_', got {profile:?}"))]
+    InvalidProfileName {
+        profile: String,
+    },
+    #[snafu(display("daily limits can be at most {}", i32::MAX))]
+    InvalidDailyLimits,
+    #[snafu(display("expected 17 weights, got {len}"))]
+    InvalidWeights {
+        len: usize,
+    },
+    GetParams {
+        source: sqlx::Error,
+    },
+    PersistParams {
+        source: sqlx::Error,
+    },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The profile used unless the user picks another one.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Make sure `profile` can name a profile. Profile names go into paths as they
+/// are, so they are kept to characters that need no escaping.
+pub fn check_profile_name(profile: &str) -> Result<()> {
+    ensure!(
+        !profile.is_empty()
+            && profile
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        InvalidProfileNameCtx { profile }
+    );
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct Params {
+    /// Recorded on every review performed with these parameters.
+    pub(crate) version: i32,
+    /// Name of the profile these parameters are a version of.
+    pub(crate) profile: String,
+    /// How reviews update the memory state of a mneme.
+    pub(crate) scheduler: SchedulerKind,
     /// Whether or not theoretical intervals should be rounded to days.
     pub(crate) round_to_days: bool,
+    /// Whether or not to move due dates around a little so that mnemes
+    /// reviewed together do not keep coming due together.
+    pub(crate) fuzz: bool,
+    /// Whether or not to move due dates towards days with fewer mnemes due.
+    pub(crate) load_balance: bool,
+    /// Number of lapses after which a mneme is tagged as a leech.
+    pub(crate) leech_threshold: i32,
+    /// Whether or not leeches are suspended when they are tagged.
+    pub(crate) suspend_leeches: bool,
+    /// How much a review session takes on per day.
+    pub(crate) limits: Limits,
+    /// Answers graded as [`ReviewGrade::Okay`] that take longer than this
+    /// count as [`ReviewGrade::Hard`].
+    pub(crate) slow_okay_after: Option<Duration>,
     /// TODO poor naming
     pub(crate) first_interval: Duration,
     /// TODO poor naming
@@ -29,9 +107,17 @@ pub struct Params {
 
 impl Params {
     pub fn from_weight_vector(w: [f64; 17]) -> Self {
-        // TODO builder
         Self {
+            version: 0,
+            profile: DEFAULT_PROFILE.to_owned(),
+            scheduler: SchedulerKind::Fsrs,
             round_to_days: false,
+            fuzz: false,
+            load_balance: false,
+            leech_threshold: 8,
+            suspend_leeches: false,
+            limits: Limits::default(),
+            slow_okay_after: None,
             target_retention: 0.9,
             first_interval: Duration::minutes(1),
             second_interval: Duration::minutes(5),
@@ -45,6 +131,204 @@ impl Params {
         }
     }
 
+    /// Start from the defaults, with the given weights.
+    pub fn builder(w: [f64; 17]) -> ParamsBuilder {
+        ParamsBuilder {
+            params: Self::from_weight_vector(w),
+        }
+    }
+
+    /// Start from these parameters, e.g. to change a setting of their profile.
+    pub fn to_builder(&self) -> ParamsBuilder {
+        ParamsBuilder {
+            params: self.clone(),
+        }
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn scheduler(&self) -> SchedulerKind {
+        self.scheduler
+    }
+
+    pub fn target_retention(&self) -> f64 {
+        self.target_retention
+    }
+
+    pub fn round_to_days(&self) -> bool {
+        self.round_to_days
+    }
+
+    pub fn fuzz(&self) -> bool {
+        self.fuzz
+    }
+
+    pub fn load_balance(&self) -> bool {
+        self.load_balance
+    }
+
+    pub fn leech_threshold(&self) -> i32 {
+        self.leech_threshold
+    }
+
+    pub fn suspend_leeches(&self) -> bool {
+        self.suspend_leeches
+    }
+
+    pub fn slow_okay_after(&self) -> Option<Duration> {
+        self.slow_okay_after
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// The grade a review counts as, given how long the answer took.
+    pub fn grade_for_latency(&self, grade: ReviewGrade, latency: Option<Duration>) -> ReviewGrade {
+        match (grade, latency, self.slow_okay_after) {
+            (ReviewGrade::Okay, Some(latency), Some(max)) if latency > max => ReviewGrade::Hard,
+            _ => grade,
+        }
+    }
+
+    /// The intervals after the first review of a mneme, for
+    /// [`ReviewGrade::Fail`], [`ReviewGrade::Hard`] and [`ReviewGrade::Okay`]
+    /// respectively.
+    pub fn learning_steps(&self) -> (Duration, Duration, Duration) {
+        (
+            self.first_interval,
+            self.second_interval,
+            self.third_interval,
+        )
+    }
+
+    pub fn weight_vector(&self) -> [f64; 17] {
+        self.weights.vector
+    }
+
+    /// A copy of these parameters with the weights replaced.
+    pub(crate) fn with_weight_vector(&self, w: [f64; 17]) -> Self {
+        Self {
+            weights: Weights::from_weight_vector(w),
+            ..self.clone()
+        }
+    }
+
+    /// The newest version of every profile, or only of `profile` if given. If
+    /// `version` is given, only that version is loaded, whether or not it is
+    /// the newest.
+    async fn load(pool: &PgPool, version: Option<i32>, profile: Option<&str>) -> Result<Vec<Self>> {
+        let rows = sqlx::query_as!(
+            ParamsRow,
+            r#"SELECT DISTINCT ON (profile)
+version,
+profile,
+scheduler AS "scheduler: _",
+weights,
+target_retention,
+round_to_days,
+fuzz,
+load_balance,
+leech_threshold,
+suspend_leeches,
+new_per_day,
+reviews_per_day,
+EXTRACT(EPOCH FROM first_interval)::float8 AS "first_interval!",
+EXTRACT(EPOCH FROM second_interval)::float8 AS "second_interval!",
+EXTRACT(EPOCH FROM third_interval)::float8 AS "third_interval!",
+EXTRACT(EPOCH FROM slow_okay_after)::float8 AS slow_okay_after
+FROM params
+WHERE ($1::int IS NULL OR version = $1) AND ($2::text IS NULL OR profile = $2)
+ORDER BY profile, version DESC"#,
+            version,
+            profile
+        )
+        .fetch_all(pool)
+        .await
+        .context(GetParamsCtx)?;
+        rows.into_iter().map(TryInto::try_into).collect()
+    }
+
+    /// The only parameters [`Self::load`] finds, as if fetched with
+    /// `fetch_one`.
+    async fn load_one(pool: &PgPool, version: Option<i32>, profile: Option<&str>) -> Result<Self> {
+        Self::load(pool, version, profile)
+            .await?
+            .pop()
+            .ok_or(sqlx::Error::RowNotFound)
+            .context(GetParamsCtx)
+    }
+
+    /// A specific version of some profile.
+    pub async fn get_by_version(pool: &PgPool, version: i32) -> Result<Self> {
+        Self::load_one(pool, Some(version), None).await
+    }
+
+    /// The current version of a profile.
+    pub async fn get_latest(pool: &PgPool, profile: &str) -> Result<Self> {
+        Self::load_one(pool, None, Some(profile)).await
+    }
+
+    /// The current version of the profile reviews are performed with.
+    pub async fn get_active(pool: &PgPool) -> Result<Self> {
+        let profile = active_profile(pool).await?;
+        Self::get_latest(pool, &profile).await
+    }
+
+    /// The current version of every profile, by name.
+    pub async fn get_all_latest(pool: &PgPool) -> Result<Vec<Self>> {
+        Self::load(pool, None, None).await
+    }
+
+    /// Store these parameters as the newest version of their profile.
+    pub async fn persist(self, pool: &PgPool) -> Result<Self> {
+        let version = self.insert(pool, None, None).await?;
+        Ok(Self { version, ..self })
+    }
+
+    /// Insert a new version, along with how well its weights fit the review
+    /// log they were trained on, if they were.
+    pub(crate) async fn insert(
+        &self,
+        pool: &PgPool,
+        log_loss: Option<f64>,
+        num_reviews: Option<i32>,
+    ) -> Result<i32> {
+        let version = sqlx::query_scalar!(
+            r#"INSERT INTO params
+(profile, scheduler, weights, target_retention, round_to_days, fuzz, load_balance, leech_threshold, suspend_leeches, new_per_day, reviews_per_day, first_interval, second_interval, third_interval, slow_okay_after, log_loss, num_reviews)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, make_interval(secs => $12), make_interval(secs => $13), make_interval(secs => $14), make_interval(secs => $15), $16, $17)
+RETURNING version"#,
+            self.profile,
+            self.scheduler as SchedulerKind,
+            &self.weights.vector[..],
+            self.target_retention,
+            self.round_to_days,
+            self.fuzz,
+            self.load_balance,
+            self.leech_threshold,
+            self.suspend_leeches,
+            self.limits.new_per_day as i32,
+            self.limits.reviews_per_day as i32,
+            seconds(self.first_interval),
+            seconds(self.second_interval),
+            seconds(self.third_interval),
+            self.slow_okay_after.map(seconds),
+            log_loss,
+            num_reviews
+        )
+        .fetch_one(pool)
+        .await
+        .context(PersistParamsCtx)?;
+        Ok(version)
+    }
+
     pub(crate) fn initial_stability(&self, grade: ReviewGrade) -> f64 {
         match grade {
             ReviewGrade::Fail => self.weights.init_stab_fail,
@@ -62,3 +346,224 @@ impl Params {
         }
     }
 }
+
+/// Name of the profile reviews are performed with.
+pub async fn active_profile(executor: impl PgExecutor<'_>) -> Result<String> {
+    sqlx::query_scalar!("SELECT profile FROM active_profile")
+        .fetch_one(executor)
+        .await
+        .context(GetParamsCtx)
+}
+
+/// Perform reviews with the given profile from now on. It has to have been
+/// stored already.
+pub async fn set_active_profile(executor: impl PgExecutor<'_>, profile: &str) -> Result<()> {
+    check_profile_name(profile)?;
+    let r = sqlx::query!(
+        "UPDATE active_profile SET profile = $1 WHERE EXISTS (SELECT FROM params WHERE profile = $1)",
+        profile
+    )
+    .execute(executor)
+    .await
+    .context(PersistParamsCtx)?;
+    ensure!(
+        r.rows_affected() > 0,
+        UnknownProfileCtx {
+            profile: profile.to_owned()
+        }
+    );
+    Ok(())
+}
+
+/// Adjusts the user-facing settings of a [`Params`].
+pub struct ParamsBuilder {
+    params: Params,
+}
+
+impl ParamsBuilder {
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.params.profile = profile.into();
+        self
+    }
+
+    pub fn scheduler(mut self, scheduler: SchedulerKind) -> Self {
+        self.params.scheduler = scheduler;
+        self
+    }
+
+    pub fn target_retention(mut self, target_retention: f64) -> Self {
+        self.params.target_retention = target_retention;
+        self
+    }
+
+    pub fn round_to_days(mut self, round_to_days: bool) -> Self {
+        self.params.round_to_days = round_to_days;
+        self
+    }
+
+    pub fn fuzz(mut self, fuzz: bool) -> Self {
+        self.params.fuzz = fuzz;
+        self
+    }
+
+    pub fn load_balance(mut self, load_balance: bool) -> Self {
+        self.params.load_balance = load_balance;
+        self
+    }
+
+    pub fn leech_threshold(mut self, leech_threshold: i32) -> Self {
+        self.params.leech_threshold = leech_threshold;
+        self
+    }
+
+    pub fn suspend_leeches(mut self, suspend_leeches: bool) -> Self {
+        self.params.suspend_leeches = suspend_leeches;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.params.limits = limits;
+        self
+    }
+
+    /// See [`Params::grade_for_latency`]. Slow answers are never downgraded
+    /// if `None`.
+    pub fn slow_okay_after(mut self, slow_okay_after: Option<Duration>) -> Self {
+        self.params.slow_okay_after = slow_okay_after;
+        self
+    }
+
+    /// See [`Params::learning_steps`].
+    pub fn learning_steps(mut self, first: Duration, second: Duration, third: Duration) -> Self {
+        self.params.first_interval = first;
+        self.params.second_interval = second;
+        self.params.third_interval = third;
+        self
+    }
+
+    pub fn build(self) -> Result<Params> {
+        let p = self.params;
+        check_profile_name(&p.profile)?;
+        ensure!(
+            p.target_retention > 0.0 && p.target_retention < 1.0,
+            InvalidTargetRetentionCtx {
+                value: p.target_retention
+            }
+        );
+        ensure!(
+            Duration::zero() < p.first_interval
+                && p.first_interval <= p.second_interval
+                && p.second_interval <= p.third_interval,
+            InvalidLearningStepsCtx
+        );
+        ensure!(
+            !p.slow_okay_after.is_some_and(|d| d <= Duration::zero()),
+            InvalidSlowOkayAfterCtx
+        );
+        ensure!(
+            p.limits.new_per_day <= i32::MAX as usize
+                && p.limits.reviews_per_day <= i32::MAX as usize,
+            InvalidDailyLimitsCtx
+        );
+        ensure!(
+            p.leech_threshold >= 1,
+            InvalidLeechThresholdCtx {
+                value: p.leech_threshold
+            }
+        );
+        Ok(p)
+    }
+}
+
+/// Units a [`Duration`] setting can be written in, largest first.
+const UNITS: [(&str, i64); 4] = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+
+/// Read a duration written as a number and a unit, such as `30s`, `10m`,
+/// `1.5h` or `2d`. A number on its own is taken as seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (number, unit_seconds) = UNITS
+        .iter()
+        .find_map(|&(unit, seconds)| Some((s.strip_suffix(unit)?, seconds)))
+        .unwrap_or((s, 1));
+    let number: f64 = number.trim().parse().ok()?;
+    let micros = number * unit_seconds as f64 * 1e6;
+    (micros.is_finite() && micros.abs() < i64::MAX as f64)
+        .then(|| Duration::microseconds(micros.round() as i64))
+}
+
+/// Write a duration the way [`parse_duration`] reads it, in the largest unit
+/// it is a whole number of.
+pub fn format_duration(d: Duration) -> String {
+    let micros = d.num_microseconds().unwrap_or(i64::MAX);
+    for (unit, seconds) in UNITS {
+        if micros % (seconds * 1_000_000) == 0 {
+            return format!("{}{unit}", micros / (seconds * 1_000_000));
+        }
+    }
+    format!("{}s", micros as f64 / 1e6)
+}
+
+// Intervals go through the database as (fractional) seconds, since sqlx only
+// decodes them into its own interval type.
+
+fn seconds(d: Duration) -> f64 {
+    d.num_microseconds().unwrap_or(i64::MAX) as f64 / 1e6
+}
+
+fn from_seconds(s: f64) -> Duration {
+    Duration::microseconds((s * 1e6).round() as i64)
+}
+
+struct ParamsRow {
+    version: i32,
+    profile: String,
+    scheduler: SchedulerKind,
+    weights: Vec<f64>,
+    target_retention: f64,
+    round_to_days: bool,
+    fuzz: bool,
+    load_balance: bool,
+    leech_threshold: i32,
+    suspend_leeches: bool,
+    new_per_day: i32,
+    reviews_per_day: i32,
+    /// In seconds, as are the other intervals.
+    first_interval: f64,
+    second_interval: f64,
+    third_interval: f64,
+    slow_okay_after: Option<f64>,
+}
+
+impl TryFrom<ParamsRow> for Params {
+    type Error = Error;
+
+    fn try_from(row: ParamsRow) -> Result<Self> {
+        let len = row.weights.len();
+        let w = row
+            .weights
+            .try_into()
+            .ok()
+            .context(InvalidWeightsCtx { len })?;
+        Ok(Self {
+            version: row.version,
+            profile: row.profile,
+            scheduler: row.scheduler,
+            round_to_days: row.round_to_days,
+            fuzz: row.fuzz,
+            load_balance: row.load_balance,
+            leech_threshold: row.leech_threshold,
+            suspend_leeches: row.suspend_leeches,
+            limits: Limits {
+                new_per_day: row.new_per_day.max(0) as usize,
+                reviews_per_day: row.reviews_per_day.max(0) as usize,
+            },
+            target_retention: row.target_retention,
+            first_interval: from_seconds(row.first_interval),
+            second_interval: from_seconds(row.second_interval),
+            third_interval: from_seconds(row.third_interval),
+            slow_okay_after: row.slow_okay_after.map(from_seconds),
+            ..Self::from_weight_vector(w)
+        })
+    }
+}
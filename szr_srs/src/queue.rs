@@ -0,0 +1,249 @@
+//! Deciding what to review next.
+//!
+//! Mnemes that are due are split into new ones (never reviewed since they
+//! were created), learning and relearning ones, and ones under review. New
+//! mnemes and reviews are capped per day, counting the reviews already done
+//! that day from anywhere. What is left is interleaved into a single ordered
+//! batch, which is stored as a [`Session`] and worked through in order. Only
+//! mnemes that are the card of some variant are queued, since there is
+//! nothing to show for the others.
+
+use chrono::{DateTime, Duration, DurationRound, SubsecRound, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::{types::Uuid, PgPool};
+
+use crate::{
+    memory_status::MemoryStatus,
+    mneme::{self, Mneme},
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetDueMnemes { source: mneme::Error },
+    GetUsage { source: sqlx::Error },
+    GetSession { source: sqlx::Error },
+    PersistSession { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// How many new mnemes can be introduced per day.
+    pub new_per_day: usize,
+    /// How many mnemes under review can be reviewed per day. Learning and
+    /// relearning mnemes are never held back.
+    pub reviews_per_day: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            new_per_day: 20,
+            reviews_per_day: 200,
+        }
+    }
+}
+
+/// What has already been reviewed on a given day.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub new: usize,
+    pub reviews: usize,
+}
+
+impl Usage {
+    /// Days start at midnight UTC.
+    pub fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+        now.duration_trunc(Duration::days(1)).unwrap_or(now)
+    }
+
+    /// Count the reviews performed since the start of the day `now` falls in.
+    pub async fn get_for_day(pool: &PgPool, now: DateTime<Utc>) -> Result<Self> {
+        let r = sqlx::query!(
+            r#"SELECT
+COUNT(*) FILTER (WHERE prev.index = 0 OR prev.reset) AS "new!",
+COUNT(*) FILTER (WHERE prev.index > 0 AND NOT prev.reset AND prev.status = 'Reviewing') AS "reviews!"
+FROM mneme_states s
+JOIN mneme_states prev ON prev.mneme_id = s.mneme_id AND prev.index = s.index - 1
+WHERE s.reviewed_at >= $1 AND s.undone_at IS NULL AND prev.undone_at IS NULL
+  AND NOT s.reset"#,
+            Self::day_start(now)
+        )
+        .fetch_one(pool)
+        .await
+        .context(GetUsageCtx)?;
+        Ok(Self {
+            new: r.new as usize,
+            reviews: r.reviews as usize,
+        })
+    }
+}
+
+/// The order in which the mnemes in `due` should be reviewed, leaving out
+/// those over the limits for the day.
+///
+/// Each kind of mneme is sorted by due date and spread evenly over the batch,
+/// so that e.g. new mnemes do not all end up at the start or at the end.
+pub fn build(due: &[Mneme], limits: Limits, usage: Usage) -> Vec<Uuid> {
+    let mut new = Vec::new();
+    let mut learning = Vec::new();
+    let mut relearning = Vec::new();
+    let mut reviewing = Vec::new();
+    for m in due {
+        let kind = if m.state.is_start() {
+            &mut new
+        } else {
+            match m.state.status {
+                MemoryStatus::Learning => &mut learning,
+                MemoryStatus::Relearning => &mut relearning,
+                MemoryStatus::Reviewing => &mut reviewing,
+            }
+        };
+        kind.push(m);
+    }
+
+    new.truncate(limits.new_per_day.saturating_sub(usage.new));
+    reviewing.truncate(limits.reviews_per_day.saturating_sub(usage.reviews));
+
+    // Earlier kinds win ties.
+    let mut r = Vec::new();
+    for (priority, kind) in [learning, relearning, reviewing, new]
+        .into_iter()
+        .enumerate()
+    {
+        let mut kind = kind;
+        kind.sort_by_key(|m| (m.next_due, m.id));
+        let n = kind.len() as f64;
+        r.extend(
+            kind.into_iter()
+                .enumerate()
+                .map(|(i, m)| ((i as f64 + 0.5) / n, priority, m.id)),
+        );
+    }
+    r.sort_by(|(a, pa, _), (b, pb, _)| a.total_cmp(b).then(pa.cmp(pb)));
+    r.into_iter().map(|(_, _, id)| id).collect()
+}
+
+/// A batch of mnemes to be reviewed in order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// In review order.
+    pub items: Vec<Uuid>,
+}
+
+impl Session {
+    /// Pick the mnemes that are due at `now` and store them as a new session,
+    /// or return `None` if there is nothing to review.
+    pub async fn start(pool: &PgPool, limits: Limits, now: DateTime<Utc>) -> Result<Option<Self>> {
+        let due = Mneme::get_due(pool, now).await.context(GetDueMnemesCtx)?;
+        let usage = Usage::get_for_day(pool, now).await?;
+        let items = build(&due, limits, usage);
+        if items.is_empty() {
+            return Ok(None);
+        }
+
+        let session = Self {
+            id: Uuid::new_v4(),
+            // Postgres only keeps microseconds.
+            created_at: now.trunc_subsecs(6),
+            items,
+        };
+        let positions: Vec<_> = (0..session.items.len() as i32).collect();
+        let mut tx = pool.begin().await.context(PersistSessionCtx)?;
+        sqlx::query!(
+            "INSERT INTO review_sessions (id, created_at) VALUES ($1, $2)",
+            session.id,
+            session.created_at
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistSessionCtx)?;
+        sqlx::query!(
+            r#"INSERT INTO review_session_items (session_id, position, mneme_id)
+SELECT $1::uuid, * FROM UNNEST($2::int[], $3::uuid[])"#,
+            session.id,
+            &positions,
+            &session.items
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistSessionCtx)?;
+        tx.commit().await.context(PersistSessionCtx)?;
+
+        Ok(Some(session))
+    }
+
+    /// The most recent session started on the day `now` falls in that still
+    /// has mnemes left to review. Mnemes suspended or buried since the session
+    /// started are not counted.
+    pub async fn current(pool: &PgPool, now: DateTime<Utc>) -> Result<Option<Self>> {
+        let Some(r) = sqlx::query!(
+            r#"SELECT id, created_at FROM review_sessions
+WHERE created_at >= $1
+AND EXISTS (
+  SELECT 1 FROM review_session_items
+  JOIN mnemes ON mnemes.id = review_session_items.mneme_id
+  WHERE session_id = review_sessions.id
+  AND done_at IS NULL
+  AND NOT mnemes.suspended
+  AND (mnemes.buried_until IS NULL OR mnemes.buried_until <= $2)
+)
+ORDER BY created_at DESC
+LIMIT 1"#,
+            Usage::day_start(now),
+            now
+        )
+        .fetch_optional(pool)
+        .await
+        .context(GetSessionCtx)?
+        else {
+            return Ok(None);
+        };
+        let items = sqlx::query_scalar!(
+            "SELECT mneme_id FROM review_session_items WHERE session_id = $1 ORDER BY position",
+            r.id
+        )
+        .fetch_all(pool)
+        .await
+        .context(GetSessionCtx)?;
+        Ok(Some(Self {
+            id: r.id,
+            created_at: r.created_at,
+            items,
+        }))
+    }
+
+    /// Continue the current session, or start a new one if there is none.
+    pub async fn current_or_start(
+        pool: &PgPool,
+        limits: Limits,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Self>> {
+        if let Some(session) = Self::current(pool, now).await? {
+            return Ok(Some(session));
+        }
+        Self::start(pool, limits, now).await
+    }
+
+    /// The mnemes in this session that are left to review at `now`, in order.
+    pub async fn pending(&self, pool: &PgPool, now: DateTime<Utc>) -> Result<Vec<Uuid>> {
+        sqlx::query_scalar!(
+            r#"SELECT mneme_id FROM review_session_items
+JOIN mnemes ON mnemes.id = review_session_items.mneme_id
+WHERE session_id = $1
+AND done_at IS NULL
+AND NOT mnemes.suspended
+AND (mnemes.buried_until IS NULL OR mnemes.buried_until <= $2)
+ORDER BY position"#,
+            self.id,
+            now
+        )
+        .fetch_all(pool)
+        .await
+        .context(GetSessionCtx)
+    }
+}
@@ -0,0 +1,331 @@
+//! Recomputing memory states after the parameters change.
+//!
+//! Every review stores the difficulty, stability and due date computed when it
+//! happened, so new weights or another scheduler only count from the next
+//! review on. Replaying the log of a mneme with the current parameters gives
+//! the state it would be in had they been used all along. Grades, times and
+//! statuses stay as they were, as does everything else about the mneme. Fuzz
+//! is seeded by the log, so it comes out the same, but load balancing depended
+//! on the rest of the deck at the time and is not redone.
+//!
+//! Mnemes created before reviews were logged only have the state they were in
+//! back then. That state is kept as is, unless it comes from another scheduler,
+//! and the log is replayed from there.
+//! Resets in the log start the mneme over, as they did at the time.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+use chrono::{DateTime, Utc};
+use snafu::{ensure, ResultExt, Snafu};
+use sqlx::{types::Uuid, PgPool};
+
+use crate::{
+    mneme::{self, elapsed_days, Mneme, MnemeWithHistory},
+    mneme_state::MnemeState,
+    params::Params,
+    stats::{Bin, INTERVAL_BUCKETS},
+};
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    LoadReviewLog {
+        source: mneme::Error,
+    },
+    PersistReplay {
+        source: sqlx::Error,
+    },
+    #[snafu(display("the review log changed since it was replayed"))]
+    LogChanged,
+    #[snafu(display("the replay came out differently from the one confirmed"))]
+    PlanChanged,
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Whether the log of the mneme starts with the review that created it, which
+/// is due the moment it happens.
+fn starts_at_creation(first: &MnemeState) -> bool {
+    first.index == 0 && first.due_at == first.reviewed_at
+}
+
+/// The mneme as it would be had every review in its log been done with
+/// `params`.
+pub fn replay(params: &Params, item: &MnemeWithHistory) -> MnemeWithHistory {
+    let mut states = item.states();
+    let Some(first) = states.next() else {
+        return item.clone();
+    };
+    let mut mneme = if starts_at_creation(first) {
+        let init = Mneme::init_at_with_id(
+            params,
+            first.grade,
+            first.reviewed_at,
+            item.mneme.id,
+            first.id,
+        );
+        Mneme {
+            next_due: init.next_due,
+            state: MnemeState {
+                latency_ms: first.latency_ms,
+                ..init.state
+            },
+            ..item.mneme.clone()
+        }
+    } else {
+        let mut state = *first;
+        if state.scheduler != params.scheduler {
+            // There is nothing to replay it from, so it takes the difficulty
+            // and stability a first review with the same grade would give.
+            let init = Mneme::init_at_with_id(
+                params,
+                first.grade,
+                first.reviewed_at,
+                item.mneme.id,
+                first.id,
+            );
+            state = MnemeState {
+                difficulty: init.state.difficulty,
+                stability: init.state.stability,
+                scheduler: init.state.scheduler,
+                params_version: params.version,
+                ..state
+            };
+        }
+        Mneme {
+            next_due: item
+                .states()
+                .nth(1)
+                .map_or(item.mneme.next_due, |s| s.due_at),
+            state,
+            ..item.mneme.clone()
+        }
+    };
+    let mut history = Vec::with_capacity(item.history.len());
+    for s in states {
+        let next = if s.reset {
+            mneme.reset_at_with_id(params, s.reviewed_at, s.id)
+        } else {
+            mneme.reviewed_with_id(params, s.grade, s.reviewed_at, s.id)
+        };
+        history.push(mneme.state);
+        mneme = Mneme {
+            state: MnemeState {
+                latency_ms: s.latency_ms,
+                ..next.state
+            },
+            ..next
+        };
+    }
+    MnemeWithHistory { mneme, history }
+}
+
+/// A mneme whose due date moves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Move {
+    pub mneme_id: Uuid,
+    pub old_due: DateTime<Utc>,
+    pub new_due: DateTime<Utc>,
+}
+
+impl Move {
+    /// How far the due date moves, in days, negative if it comes earlier.
+    pub fn days(&self) -> f64 {
+        elapsed_days(self.old_due, self.new_due)
+    }
+}
+
+/// What replaying every log changes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Number of mnemes replayed.
+    pub replayed: usize,
+    /// Mnemes whose log does not go back to their creation.
+    pub partial: usize,
+    /// Mnemes whose state changes, whether or not they move.
+    pub changed: usize,
+    pub moved: Vec<Move>,
+}
+
+impl Report {
+    pub fn earlier(&self) -> usize {
+        self.moved.iter().filter(|m| m.days() < 0.0).count()
+    }
+
+    pub fn later(&self) -> usize {
+        self.moved.iter().filter(|m| m.days() > 0.0).count()
+    }
+
+    /// Mean distance moved either way, in days.
+    pub fn mean_move_days(&self) -> Option<f64> {
+        (!self.moved.is_empty()).then(|| {
+            self.moved.iter().map(|m| m.days().abs()).sum::<f64>() / self.moved.len() as f64
+        })
+    }
+
+    /// How far mnemes move either way, in days, bucketed by
+    /// [`INTERVAL_BUCKETS`].
+    pub fn move_distribution(&self) -> Vec<Bin> {
+        let mut bins: Vec<_> = INTERVAL_BUCKETS
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| Bin {
+                start,
+                end: INTERVAL_BUCKETS.get(i + 1).copied(),
+                count: 0,
+            })
+            .collect();
+        for m in &self.moved {
+            let days = m.days().abs();
+            if let Some(bin) = bins.iter_mut().rev().find(|b| b.start <= days) {
+                bin.count += 1;
+            }
+        }
+        bins
+    }
+}
+
+/// The result of replaying every log, ready to be written.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    pub report: Report,
+    /// Only the mnemes that change.
+    items: Vec<MnemeWithHistory>,
+    /// See [`Replay::fingerprint`].
+    fingerprint: u64,
+}
+
+impl Replay {
+    /// Replay the given logs with `params`.
+    pub fn plan(params: &Params, items: &[MnemeWithHistory]) -> Self {
+        let mut report = Report {
+            replayed: items.len(),
+            ..Default::default()
+        };
+        let mut changed = Vec::new();
+        for item in items {
+            if !item.states().next().is_some_and(starts_at_creation) {
+                report.partial += 1;
+            }
+            let new = replay(params, item);
+            if new.mneme.next_due != item.mneme.next_due {
+                report.moved.push(Move {
+                    mneme_id: item.mneme.id,
+                    old_due: item.mneme.next_due,
+                    new_due: new.mneme.next_due,
+                });
+            }
+            if new.mneme.next_due != item.mneme.next_due || !new.states().eq(item.states()) {
+                changed.push(new);
+            }
+        }
+        report.changed = changed.len();
+        let mut current: Vec<_> = items
+            .iter()
+            .map(|item| (item.mneme.id, item.mneme.state.id))
+            .collect();
+        current.sort_unstable();
+        let mut hasher = DefaultHasher::new();
+        (&params.profile, params.version, current).hash(&mut hasher);
+        Self {
+            report,
+            items: changed,
+            fingerprint: hasher.finish(),
+        }
+    }
+
+    /// Identifies what the replay was prepared from: the parameters and the
+    /// current state of every mneme. Two replays with the same fingerprint
+    /// write the same thing.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// Replay every log in the database with `params`, without writing
+    /// anything yet.
+    pub async fn prepare(pool: &PgPool, params: &Params) -> Result<Self> {
+        let items = MnemeWithHistory::get_all(pool)
+            .await
+            .context(LoadReviewLogCtx)?;
+        Ok(Self::plan(params, &items))
+    }
+
+    /// Rewrite the states and due dates of the mnemes that change, all or
+    /// nothing. Replayed states are recorded as done with the parameters they
+    /// were replayed with, since those are what produced them now. If any of
+    /// those mnemes was reviewed, reset or had a review undone since the
+    /// replay was prepared, nothing is written and [`Error::LogChanged`] is
+    /// returned, so that the replay can be prepared again.
+    pub async fn apply(self, pool: &PgPool) -> Result<Report> {
+        let mut state_ids = Vec::new();
+        let mut due_ats = Vec::new();
+        let mut difficulties = Vec::new();
+        let mut stabilities = Vec::new();
+        let mut versions = Vec::new();
+        let mut schedulers = Vec::new();
+        for s in self.items.iter().flat_map(|item| item.states()) {
+            state_ids.push(s.id);
+            due_ats.push(s.due_at);
+            difficulties.push(s.difficulty);
+            stabilities.push(s.stability);
+            versions.push(s.params_version);
+            schedulers.push(s.scheduler);
+        }
+        let mut mneme_ids = Vec::new();
+        let mut current_state_ids = Vec::new();
+        let mut next_dues = Vec::new();
+        for item in &self.items {
+            mneme_ids.push(item.mneme.id);
+            current_state_ids.push(item.mneme.state.id);
+            next_dues.push(item.mneme.next_due);
+        }
+
+        let mut tx = pool.begin().await.context(PersistReplayCtx)?;
+        sqlx::query!(
+            r#"UPDATE mneme_states s
+SET due_at = r.due_at, difficulty = r.difficulty, stability = r.stability,
+  params_version = r.params_version, scheduler = r.scheduler
+FROM UNNEST($1::uuid[], $2::timestamptz[], $3::float8[], $4::float8[], $5::int[], $6::scheduler_kind[])
+  AS r(id, due_at, difficulty, stability, params_version, scheduler)
+WHERE s.id = r.id"#,
+            &state_ids,
+            &due_ats,
+            &difficulties,
+            &stabilities,
+            &versions,
+            &schedulers as _
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistReplayCtx)?;
+        // A mneme whose current state is another one than was replayed has a
+        // log the plan does not know about.
+        let updated = sqlx::query!(
+            r#"UPDATE mnemes m
+SET next_due = r.next_due
+FROM UNNEST($1::uuid[], $2::uuid[], $3::timestamptz[]) AS r(id, state_id, next_due)
+WHERE m.id = r.id AND m.state_id = r.state_id"#,
+            &mneme_ids,
+            &current_state_ids,
+            &next_dues
+        )
+        .execute(&mut *tx)
+        .await
+        .context(PersistReplayCtx)?
+        .rows_affected();
+        ensure!(updated == mneme_ids.len() as u64, LogChangedCtx);
+        tx.commit().await.context(PersistReplayCtx)?;
+        Ok(self.report)
+    }
+
+    /// Like [`Replay::apply`], but only if this replay has `fingerprint`, e.g.
+    /// that of the one whose report was shown before it was confirmed.
+    /// Otherwise nothing is written and [`Error::PlanChanged`] is returned.
+    pub async fn apply_if_matches(self, pool: &PgPool, fingerprint: u64) -> Result<Report> {
+        ensure!(self.fingerprint == fingerprint, PlanChangedCtx);
+        self.apply(pool).await
+    }
+}
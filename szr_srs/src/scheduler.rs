@@ -0,0 +1,361 @@
+//! How reviews change the memory state of a mneme.
+//!
+//! A [`Scheduler`] works out the difficulty and stability a review leaves a
+//! mneme with, and how long until it should be reviewed again. Which one is
+//! used is a setting of the [`Params`]. Everything else about a review, such
+//! as the status it moves the mneme to, fuzzing and leeches, is the same
+//! whichever it is.
+//!
+//! [`Fsrs`] is the default. [`Sm2`] is the algorithm SuperMemo 2 and, in a
+//! modified form, Anki are built on. It keeps its ease factor as the
+//! difficulty and the current interval in days as the stability. Since the two
+//! mean different things, every state records the scheduler it comes from,
+//! and a mneme is replayed with the new one before a review if that changed.
+//! Only FSRS estimates the probability of recall.
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::{
+    memory_status::MemoryStatus, mneme::Mneme, mneme_state::MnemeState, params::Params,
+    review_grade::ReviewGrade,
+};
+
+/// What a review leaves a mneme with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    pub difficulty: f64,
+    pub stability: f64,
+    /// Time until the mneme is next due, before fuzzing.
+    pub interval: Duration,
+}
+
+pub trait Scheduler {
+    /// After the review that creates a mneme.
+    fn init(&self, params: &Params, grade: ReviewGrade) -> Schedule;
+
+    /// After reviewing `mneme` at `now`. The status it was in before the
+    /// review is still the one in `mneme.state`.
+    fn review(
+        &self,
+        params: &Params,
+        mneme: &Mneme,
+        grade: ReviewGrade,
+        now: DateTime<Utc>,
+    ) -> Schedule;
+}
+
+#[derive(
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[sqlx(type_name = "scheduler_kind")]
+pub enum SchedulerKind {
+    #[default]
+    Fsrs,
+    Sm2,
+}
+
+impl sqlx::postgres::PgHasArrayType for SchedulerKind {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_scheduler_kind")
+    }
+}
+
+impl SchedulerKind {
+    pub const ALL: [Self; 2] = [Self::Fsrs, Self::Sm2];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fsrs => "FSRS",
+            Self::Sm2 => "SM-2",
+        }
+    }
+}
+
+impl Scheduler for SchedulerKind {
+    fn init(&self, params: &Params, grade: ReviewGrade) -> Schedule {
+        match self {
+            Self::Fsrs => Fsrs.init(params, grade),
+            Self::Sm2 => Sm2.init(params, grade),
+        }
+    }
+
+    fn review(
+        &self,
+        params: &Params,
+        mneme: &Mneme,
+        grade: ReviewGrade,
+        now: DateTime<Utc>,
+    ) -> Schedule {
+        match self {
+            Self::Fsrs => Fsrs.review(params, mneme, grade, now),
+            Self::Sm2 => Sm2.review(params, mneme, grade, now),
+        }
+    }
+}
+
+/// An interval of the given number of days, kept within the bounds set by the
+/// parameters.
+fn interval_from_days(p: &Params, d: f64) -> Duration {
+    if p.round_to_days {
+        Duration::days((d.round() as i64).clamp(1, p.max_interval))
+    } else {
+        let d = 86400.0 * d.clamp(1.0, p.max_interval as f64);
+        Duration::seconds(d.round() as i64)
+    }
+}
+
+/// The learning step taken after a first review that was not
+/// [`ReviewGrade::Easy`].
+fn initial_step(p: &Params, grade: ReviewGrade) -> Duration {
+    match grade {
+        ReviewGrade::Fail => p.first_interval,
+        ReviewGrade::Hard => p.second_interval,
+        _ => p.third_interval,
+    }
+}
+
+/// Free Spaced Repetition Scheduler, version 4.5.
+pub struct Fsrs;
+
+impl Fsrs {
+    /// Probability of recall with the given stability after the given number
+    /// of days have passed since the last review.
+    pub(crate) fn retrievability(stability: f64, days: f64) -> f64 {
+        (1.0 + days / (9.0 * stability)).powi(-1)
+    }
+
+    fn stability_pass_update_base(params: &Params, state: &MnemeState, retrievability: f64) -> f64 {
+        let w = &params.weights;
+        w.stab_upd_pass_scale
+            * state.stability.powf(-w.stab_upd_pass_stab)
+            * (w.stab_upd_pass_retr * (1.0 - retrievability)).exp_m1()
+            * (1.0 + params.max_difficulty - state.difficulty)
+    }
+
+    fn stability_fail_update(params: &Params, state: &MnemeState, retrievability: f64) -> f64 {
+        let w = &params.weights;
+        w.stab_upd_fail_scale
+            * state.difficulty.powf(-w.stab_upd_fail_diff)
+            * (w.stab_upd_fail_retr * (1.0 - retrievability)).exp()
+            * ((1.0 + state.stability).powf(w.stab_upd_fail_stab) - 1.0)
+    }
+
+    fn stability_for_grade(
+        params: &Params,
+        state: &MnemeState,
+        grade: ReviewGrade,
+        retrievability: f64,
+    ) -> f64 {
+        match grade {
+            ReviewGrade::Fail => Self::stability_fail_update(params, state, retrievability),
+            _ => {
+                let change_factor = 1.0
+                    + Self::stability_pass_update_base(params, state, retrievability)
+                        * params.stability_pass_update_bonus(grade);
+                state.stability * change_factor
+            }
+        }
+    }
+
+    fn interval_for_grade(
+        params: &Params,
+        state: &MnemeState,
+        grade: ReviewGrade,
+        retrievability: f64,
+    ) -> Duration {
+        Self::theoretical_interval(
+            params,
+            Self::stability_for_grade(params, state, grade, retrievability),
+        )
+    }
+
+    /// Point in time at which retrievability equals the target retention value.
+    fn theoretical_interval(p: &Params, stab: f64) -> Duration {
+        interval_from_days(p, 9.0 * stab * (-1.0 + 1.0 / p.target_retention))
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn init(&self, params: &Params, grade: ReviewGrade) -> Schedule {
+        let difficulty = grade
+            .as_factor()
+            .mul_add(-params.weights.init_diff_scale, params.weights.diff_base)
+            .clamp(params.min_difficulty, params.max_difficulty);
+        let stability = params
+            .initial_stability(grade)
+            .max(params.min_initial_stability);
+        let interval = match grade {
+            ReviewGrade::Easy => Self::theoretical_interval(params, stability),
+            _ => initial_step(params, grade),
+        };
+        Schedule {
+            difficulty,
+            stability,
+            interval,
+        }
+    }
+
+    fn review(
+        &self,
+        params: &Params,
+        mneme: &Mneme,
+        grade: ReviewGrade,
+        now: DateTime<Utc>,
+    ) -> Schedule {
+        let w = &params.weights;
+        let state = &mneme.state;
+        let retrievability =
+            Self::retrievability(state.stability, (now - state.reviewed_at).num_days() as f64);
+        // These parameters will only be updated if we are on a review streak.
+        let mut stability = state.stability;
+        let mut difficulty = state.difficulty;
+
+        let interval_for_grade =
+            |grade| Self::interval_for_grade(params, state, grade, retrievability);
+        // TODO: deduplicate the nodes below where we recompute [`stability_update_base`] a few times?
+        let interval = match state.status {
+            MemoryStatus::Learning | MemoryStatus::Relearning => {
+                let okay_interval = interval_for_grade(ReviewGrade::Okay);
+                let min_easy_interval = params.interval_step + okay_interval;
+                let easy_interval = min_easy_interval.max(interval_for_grade(ReviewGrade::Easy));
+                match grade {
+                    ReviewGrade::Fail => params.second_interval,
+                    ReviewGrade::Hard => params.third_interval,
+                    ReviewGrade::Okay => okay_interval,
+                    ReviewGrade::Easy => easy_interval,
+                }
+            }
+            MemoryStatus::Reviewing => {
+                stability = Self::stability_for_grade(params, state, grade, retrievability);
+                difficulty = w
+                    .diff_upd_mean_rev
+                    .mul_add(
+                        w.diff_base,
+                        (1.0 - w.diff_upd_mean_rev)
+                            * grade
+                                .as_factor()
+                                .mul_add(-w.diff_upd_scale, state.difficulty),
+                    )
+                    .clamp(params.min_difficulty, params.max_difficulty);
+                let theo_hard_interval = interval_for_grade(ReviewGrade::Hard);
+                let theo_okay_interval = interval_for_grade(ReviewGrade::Okay);
+                let theo_easy_interval = interval_for_grade(ReviewGrade::Easy);
+                let hard_interval = theo_hard_interval.min(theo_okay_interval);
+                let okay_interval = theo_okay_interval.max(params.interval_step + hard_interval);
+                let easy_interval = theo_easy_interval.max(params.interval_step + okay_interval);
+                match grade {
+                    ReviewGrade::Fail => params.second_interval,
+                    ReviewGrade::Hard => hard_interval,
+                    ReviewGrade::Okay => okay_interval,
+                    ReviewGrade::Easy => easy_interval,
+                }
+            }
+        };
+        Schedule {
+            difficulty,
+            stability,
+            interval,
+        }
+    }
+}
+
+/// Ease factor every mneme starts out with.
+const SM2_INITIAL_EASE: f64 = 2.5;
+/// Below this, intervals would grow too slowly to ever get anywhere.
+const SM2_MIN_EASE: f64 = 1.3;
+/// The first two intervals after learning, in days. Later ones are the
+/// previous interval times the ease factor.
+const SM2_FIRST_INTERVAL: f64 = 1.0;
+const SM2_SECOND_INTERVAL: f64 = 6.0;
+
+/// SuperMemo 2, with the learning steps of the parameters in front of it.
+/// Failing a review starts the intervals over.
+pub struct Sm2;
+
+impl Sm2 {
+    /// Quality of the answer on SuperMemo's scale from 0 to 5, where anything
+    /// under 3 is a fail.
+    fn quality(grade: ReviewGrade) -> f64 {
+        match grade {
+            ReviewGrade::Fail => 2.0,
+            ReviewGrade::Hard => 3.0,
+            ReviewGrade::Okay => 4.0,
+            ReviewGrade::Easy => 5.0,
+        }
+    }
+
+    fn ease_after(ease: f64, grade: ReviewGrade) -> f64 {
+        let q = 5.0 - Self::quality(grade);
+        (ease + 0.1 - q * (0.08 + q * 0.02)).max(SM2_MIN_EASE)
+    }
+
+    fn scheduled(params: &Params, ease: f64, days: f64) -> Schedule {
+        let days = days.min(params.max_interval as f64);
+        Schedule {
+            difficulty: ease,
+            stability: days,
+            interval: interval_from_days(params, days),
+        }
+    }
+}
+
+impl Scheduler for Sm2 {
+    fn init(&self, params: &Params, grade: ReviewGrade) -> Schedule {
+        let interval = match grade {
+            ReviewGrade::Easy => interval_from_days(params, SM2_FIRST_INTERVAL),
+            _ => initial_step(params, grade),
+        };
+        Schedule {
+            difficulty: SM2_INITIAL_EASE,
+            stability: SM2_FIRST_INTERVAL,
+            interval,
+        }
+    }
+
+    fn review(
+        &self,
+        params: &Params,
+        mneme: &Mneme,
+        grade: ReviewGrade,
+        _now: DateTime<Utc>,
+    ) -> Schedule {
+        let state = &mneme.state;
+        // As in Anki, answers during learning leave the ease factor alone.
+        let ease = match state.status {
+            MemoryStatus::Reviewing => Self::ease_after(state.difficulty, grade),
+            _ => state.difficulty,
+        };
+        let learning = |interval| Schedule {
+            difficulty: ease,
+            stability: SM2_FIRST_INTERVAL,
+            interval,
+        };
+        match (state.status, grade) {
+            (_, ReviewGrade::Fail) => learning(params.second_interval),
+            (MemoryStatus::Learning | MemoryStatus::Relearning, ReviewGrade::Hard) => {
+                learning(params.third_interval)
+            }
+            (MemoryStatus::Learning | MemoryStatus::Relearning, ReviewGrade::Okay) => {
+                Self::scheduled(params, ease, SM2_FIRST_INTERVAL)
+            }
+            (MemoryStatus::Learning | MemoryStatus::Relearning, ReviewGrade::Easy) => {
+                Self::scheduled(params, ease, SM2_SECOND_INTERVAL)
+            }
+            (MemoryStatus::Reviewing, _) if state.stability < SM2_SECOND_INTERVAL => {
+                Self::scheduled(params, ease, SM2_SECOND_INTERVAL)
+            }
+            (MemoryStatus::Reviewing, _) => Self::scheduled(params, ease, state.stability * ease),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Projecting the review load of a deck into the future.
+//!
+//! The simulator steps through the coming days and reviews every mneme that
+//! falls due using the same pure scheduling path as real reviews, asking a
+//! [`RecallModel`] for the grade. Given the same deck, parameters and model it
+//! always produces the same forecast, so the effect of changing a setting can
+//! be seen by running it once with each version of the parameters.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::types::Uuid;
+
+use crate::{
+    mneme::{elapsed_days, Mneme},
+    params::Params,
+    review_grade::ReviewGrade,
+};
+
+/// Decides how the user does on a simulated review.
+pub trait RecallModel {
+    /// The grade given when `mneme` is reviewed `elapsed_days` after its
+    /// previous review.
+    fn grade(&mut self, mneme: &Mneme, elapsed_days: f64) -> ReviewGrade;
+}
+
+impl<F> RecallModel for F
+where
+    F: FnMut(&Mneme, f64) -> ReviewGrade,
+{
+    fn grade(&mut self, mneme: &Mneme, elapsed_days: f64) -> ReviewGrade {
+        self(mneme, elapsed_days)
+    }
+}
+
+/// Recalls each mneme with the probability predicted by its own memory state,
+/// answering [`ReviewGrade::Okay`] or [`ReviewGrade::Fail`]. SM-2 predicts no
+/// such thing, so mnemes it schedules are recalled with
+/// [`SampledRecall::UNMODELLED_RECALL`]. The outcomes are drawn from a fixed
+/// pseudorandom sequence.
+pub struct SampledRecall {
+    state: u64,
+}
+
+impl SampledRecall {
+    /// Probability of recall for mnemes whose memory state does not predict
+    /// one, which is about what SM-2 intervals come to in practice.
+    pub const UNMODELLED_RECALL: f64 = 0.9;
+
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Uniformly distributed in `[0, 1)`.
+    fn next_uniform(&mut self) -> f64 {
+        // Knuth's MMIX LCG, which is plenty for this.
+        self.state = self
+            .state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl RecallModel for SampledRecall {
+    fn grade(&mut self, mneme: &Mneme, elapsed_days: f64) -> ReviewGrade {
+        let recall = mneme
+            .retrievability_after(elapsed_days)
+            .unwrap_or(Self::UNMODELLED_RECALL);
+        if self.next_uniform() < recall {
+            ReviewGrade::Okay
+        } else {
+            ReviewGrade::Fail
+        }
+    }
+}
+
+pub struct Simulator {
+    /// Number of days to project, starting with the first.
+    pub days: u32,
+}
+
+/// What a single simulated day looks like.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DayForecast {
+    /// Start of the day.
+    pub date: DateTime<Utc>,
+    /// Number of reviews performed over the day.
+    pub reviews: usize,
+    /// Number of those reviews that were failed.
+    pub failures: usize,
+    /// Mean retrievability at the end of the day, over the mnemes of the deck
+    /// whose memory state predicts one.
+    pub expected_retention: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Forecast {
+    pub days: Vec<DayForecast>,
+}
+
+impl Forecast {
+    pub fn total_reviews(&self) -> usize {
+        self.days.iter().map(|d| d.reviews).sum()
+    }
+
+    /// Mean of the expected retention over all days.
+    pub fn mean_retention(&self) -> f64 {
+        if self.days.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self.days.iter().map(|d| d.expected_retention).sum();
+        total / self.days.len() as f64
+    }
+}
+
+impl Simulator {
+    /// Project the reviews of `deck` under `params` for the days following
+    /// `start`. Mnemes that are already overdue are reviewed on the first day,
+    /// and suspended ones are left out. The deck has to be in the terms of the
+    /// scheduler of `params`, see [`Mneme::for_scheduler`].
+    pub fn run<M: RecallModel>(
+        &self,
+        params: &Params,
+        deck: &[Mneme],
+        start: DateTime<Utc>,
+        model: &mut M,
+    ) -> Forecast {
+        // Suspended mnemes are out of the picture, and buried ones wait.
+        let mut deck: Vec<_> = deck
+            .iter()
+            .filter(|m| !m.suspended)
+            .map(|m| Mneme {
+                next_due: m.buried_until.map_or(m.next_due, |t| t.max(m.next_due)),
+                ..m.clone()
+            })
+            .collect();
+        let mut days = Vec::with_capacity(self.days as usize);
+
+        for day in 0..self.days {
+            let day_start = start + Duration::days(day as i64);
+            let day_end = day_start + Duration::days(1);
+            let mut reviews = 0;
+            let mut failures = 0;
+
+            for mneme in deck.iter_mut() {
+                while mneme.next_due < day_end {
+                    let now = mneme.next_due.max(day_start);
+                    let elapsed = elapsed_days(mneme.state.reviewed_at, now);
+                    let grade = model.grade(mneme, elapsed);
+                    *mneme = mneme.reviewed_with_id(params, grade, now, Uuid::nil());
+                    reviews += 1;
+                    if grade == ReviewGrade::Fail {
+                        failures += 1;
+                    }
+                    // Guard against learning steps of length zero.
+                    if mneme.next_due <= now {
+                        break;
+                    }
+                }
+            }
+
+            let retrievabilities: Vec<_> = deck
+                .iter()
+                .filter_map(|m| m.retrievability_after(elapsed_days(m.state.reviewed_at, day_end)))
+                .collect();
+            let expected_retention = if retrievabilities.is_empty() {
+                0.0
+            } else {
+                retrievabilities.iter().sum::<f64>() / retrievabilities.len() as f64
+            };
+
+            days.push(DayForecast {
+                date: day_start,
+                reviews,
+                failures,
+                expected_retention,
+            });
+        }
+
+        Forecast { days }
+    }
+}
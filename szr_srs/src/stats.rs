@@ -0,0 +1,268 @@
+//! How reviewing has gone so far.
+//!
+//! Where [`forecast`](crate::forecast) looks ahead, these look back over the
+//! review log, to show whether the scheduler keeps retention where it should
+//! be. Undone reviews are never counted, and days start at midnight UTC as in
+//! the [`queue`](crate::queue).
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use snafu::{ResultExt, Snafu};
+use sqlx::PgExecutor;
+
+use crate::queue::Usage;
+
+#[derive(Debug, Snafu)]
+#[snafu(context(suffix(Ctx)))]
+pub enum Error {
+    GetReviewsPerDay { source: sqlx::Error },
+    GetRetention { source: sqlx::Error },
+    GetDistribution { source: sqlx::Error },
+    GetHeatmap { source: sqlx::Error },
+    GetKnownWords { source: sqlx::Error },
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Lower bounds of the interval and stability buckets, in days.
+pub static INTERVAL_BUCKETS: [f64; 6] = [0.0, 1.0, 7.0, 30.0, 90.0, 365.0];
+/// Lower bounds of the difficulty buckets. Difficulty goes from 1 to 10, so
+/// the last bucket is the only one that includes its upper bound.
+pub static DIFFICULTY_BUCKETS: [f64; 9] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DayReviews {
+    /// Start of the day.
+    pub date: DateTime<Utc>,
+    /// Mnemes created, which counts as their first review.
+    pub new: usize,
+    pub reviews: usize,
+    /// Reviews graded as a fail.
+    pub failed: usize,
+}
+
+/// What was reviewed on each of the `days` days up to and including the one
+/// `now` falls in, oldest first.
+pub async fn reviews_per_day(
+    executor: impl PgExecutor<'_>,
+    now: DateTime<Utc>,
+    days: u32,
+) -> Result<Vec<DayReviews>> {
+    let rows = sqlx::query!(
+        r#"SELECT
+days.day AS "day!",
+COUNT(s.id) FILTER (WHERE s.index = 0) AS "new!",
+COUNT(s.id) FILTER (WHERE s.index > 0 AND NOT s.reset) AS "reviews!",
+COUNT(s.id) FILTER (WHERE s.index > 0 AND NOT s.reset AND s.grade = 'Fail') AS "failed!"
+FROM generate_series($1::timestamptz, $1::timestamptz + ($2::int - 1) * interval '1 day', interval '1 day') AS days(day)
+LEFT JOIN mneme_states s ON s.undone_at IS NULL
+  AND s.reviewed_at >= days.day AND s.reviewed_at < days.day + interval '1 day'
+GROUP BY days.day
+ORDER BY days.day"#,
+        first_day(now, days),
+        days as i32
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetReviewsPerDayCtx)?;
+    Ok(rows
+        .into_iter()
+        .map(|r| DayReviews {
+            date: r.day,
+            new: r.new as usize,
+            reviews: r.reviews as usize,
+            failed: r.failed as usize,
+        })
+        .collect())
+}
+
+fn first_day(now: DateTime<Utc>, days: u32) -> DateTime<Utc> {
+    Usage::day_start(now) - Duration::days(days.saturating_sub(1) as i64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Bin {
+    pub start: f64,
+    /// `None` for the last bin, which has no upper bound.
+    pub end: Option<f64>,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RetentionBin {
+    /// Bounds of the time since the previous review, in days.
+    pub start: f64,
+    pub end: Option<f64>,
+    pub reviews: usize,
+    /// Reviews not graded as a fail.
+    pub passed: usize,
+}
+
+impl RetentionBin {
+    pub fn retention(&self) -> Option<f64> {
+        (self.reviews > 0).then(|| self.passed as f64 / self.reviews as f64)
+    }
+}
+
+/// The bounds of each bucket with the given lower bounds, along with its
+/// number as given by Postgres' `width_bucket`, which starts from 1 since
+/// values under the first bound go in bucket 0.
+fn buckets(thresholds: &[f64]) -> impl Iterator<Item = (i32, f64, Option<f64>)> + '_ {
+    thresholds
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (i as i32 + 1, start, thresholds.get(i + 1).copied()))
+}
+
+/// How often reviews passed, by time since the previous review. Only mnemes
+/// that were under review count, as learning steps are expected to fail more
+/// often.
+pub async fn retention_by_interval(executor: impl PgExecutor<'_>) -> Result<Vec<RetentionBin>> {
+    let rows = sqlx::query!(
+        r#"SELECT
+width_bucket(s.elapsed_days, $1::float8[]) AS "bucket!",
+COUNT(*) AS "reviews!",
+COUNT(*) FILTER (WHERE s.grade <> 'Fail') AS "passed!"
+FROM mneme_states s
+JOIN mneme_states prev ON prev.mneme_id = s.mneme_id AND prev.index = s.index - 1
+WHERE s.undone_at IS NULL AND prev.undone_at IS NULL AND prev.status = 'Reviewing'
+  AND NOT s.reset
+GROUP BY 1"#,
+        &INTERVAL_BUCKETS[..]
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetRetentionCtx)?;
+    let mut counts: HashMap<_, _> = rows
+        .into_iter()
+        .map(|r| (r.bucket, (r.reviews as usize, r.passed as usize)))
+        .collect();
+    Ok(buckets(&INTERVAL_BUCKETS)
+        .map(|(bucket, start, end)| {
+            let (reviews, passed) = counts.remove(&bucket).unwrap_or_default();
+            RetentionBin {
+                start,
+                end,
+                reviews,
+                passed,
+            }
+        })
+        .collect())
+}
+
+/// The current stability of every mneme scheduled by FSRS, in days, bucketed
+/// by [`INTERVAL_BUCKETS`]. SM-2 keeps its interval there instead.
+pub async fn stability_distribution(executor: impl PgExecutor<'_>) -> Result<Vec<Bin>> {
+    let rows = sqlx::query!(
+        r#"SELECT width_bucket(s.stability, $1::float8[]) AS "bucket!", COUNT(*) AS "count!"
+FROM mnemes
+JOIN mneme_states s ON s.id = mnemes.state_id
+WHERE s.scheduler = 'Fsrs'
+GROUP BY 1"#,
+        &INTERVAL_BUCKETS[..]
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetDistributionCtx)?;
+    Ok(to_bins(
+        &INTERVAL_BUCKETS,
+        rows.into_iter().map(|r| (r.bucket, r.count)).collect(),
+    ))
+}
+
+/// The current difficulty of every mneme scheduled by FSRS, bucketed by
+/// [`DIFFICULTY_BUCKETS`]. SM-2 keeps its ease factor there instead.
+pub async fn difficulty_distribution(executor: impl PgExecutor<'_>) -> Result<Vec<Bin>> {
+    let rows = sqlx::query!(
+        r#"SELECT width_bucket(s.difficulty, $1::float8[]) AS "bucket!", COUNT(*) AS "count!"
+FROM mnemes
+JOIN mneme_states s ON s.id = mnemes.state_id
+WHERE s.scheduler = 'Fsrs'
+GROUP BY 1"#,
+        &DIFFICULTY_BUCKETS[..]
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetDistributionCtx)?;
+    Ok(to_bins(
+        &DIFFICULTY_BUCKETS,
+        rows.into_iter().map(|r| (r.bucket, r.count)).collect(),
+    ))
+}
+
+fn to_bins(thresholds: &[f64], mut counts: HashMap<i32, i64>) -> Vec<Bin> {
+    buckets(thresholds)
+        .map(|(bucket, start, end)| Bin {
+            start,
+            end,
+            count: counts.remove(&bucket).unwrap_or_default() as usize,
+        })
+        .collect()
+}
+
+/// Number of reviews by day of the week, starting from Monday, and hour of
+/// the day, in UTC.
+pub async fn review_heatmap(executor: impl PgExecutor<'_>) -> Result<[[usize; 24]; 7]> {
+    let rows = sqlx::query!(
+        r#"SELECT
+EXTRACT(ISODOW FROM reviewed_at AT TIME ZONE 'UTC')::int AS "weekday!",
+EXTRACT(HOUR FROM reviewed_at AT TIME ZONE 'UTC')::int AS "hour!",
+COUNT(*) AS "count!"
+FROM mneme_states
+WHERE undone_at IS NULL AND NOT reset
+GROUP BY 1, 2"#
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetHeatmapCtx)?;
+    let mut r = [[0; 24]; 7];
+    for row in rows {
+        r[row.weekday as usize - 1][row.hour as usize] = row.count as usize;
+    }
+    Ok(r)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct KnownWords {
+    /// Start of the day.
+    pub date: DateTime<Utc>,
+    /// Variants with a card that had made it out of learning by the end of
+    /// the day, even if it has lapsed since.
+    pub known: usize,
+}
+
+/// How many words were known at the end of each of the `days` days up to and
+/// including the one `now` falls in, oldest first.
+pub async fn known_words(
+    executor: impl PgExecutor<'_>,
+    now: DateTime<Utc>,
+    days: u32,
+) -> Result<Vec<KnownWords>> {
+    let rows = sqlx::query!(
+        r#"WITH learned AS (
+  SELECT variant_mnemes.variant_id, MIN(s.reviewed_at) AS at
+  FROM mneme_states s
+  JOIN variant_mnemes ON variant_mnemes.mneme_id = s.mneme_id
+  WHERE s.status = 'Reviewing' AND s.undone_at IS NULL
+  GROUP BY variant_mnemes.variant_id
+)
+SELECT days.day AS "day!", COUNT(learned.at) AS "known!"
+FROM generate_series($1::timestamptz, $1::timestamptz + ($2::int - 1) * interval '1 day', interval '1 day') AS days(day)
+LEFT JOIN learned ON learned.at < days.day + interval '1 day'
+GROUP BY days.day
+ORDER BY days.day"#,
+        first_day(now, days),
+        days as i32
+    )
+    .fetch_all(executor)
+    .await
+    .context(GetKnownWordsCtx)?;
+    Ok(rows
+        .into_iter()
+        .map(|r| KnownWords {
+            date: r.day,
+            known: r.known as usize,
+        })
+        .collect())
+}
@@ -1,9 +1,25 @@
-use chrono::{DateTime, Duration};
+use std::collections::BTreeSet;
+
+use chrono::{DateTime, Duration, Utc};
 use sqlx::{types::Uuid, PgPool};
 use szr_golden::assert_golden_json;
 
 use super::*;
-use crate::params::Params;
+use crate::{
+    anki::{self, AnkiNote, AnkiReview},
+    card,
+    export::{self, ExportedMneme},
+    forecast,
+    fuzz::{self, DueLoad},
+    knowledge, leech,
+    note::Note,
+    optimiser,
+    params::Params,
+    queue,
+    replay::{self, Replay},
+    simulator::{RecallModel, SampledRecall},
+    stats,
+};
 
 // Pure tests
 
@@ -27,6 +43,36 @@ static TEST_WEIGHTS: [f64; 17] = [
     0.0179, 0.3105, 0.3976, 0.0, 2.0902,
 ];
 
+/// Review logs for mnemes whose recall follows the retrievability predicted by
+/// `p`, with a fixed pseudorandom sequence deciding each outcome.
+fn simulated_mnemes(p: &Params, count: u64, num_reviews: u64) -> Vec<MnemeWithHistory> {
+    let mut recall = SampledRecall::new(0x5eed);
+    (0..count)
+        .map(|n| {
+            let mut item = MnemeWithHistory::init_at_with_id(
+                p,
+                ReviewGrade::Okay,
+                DateTime::UNIX_EPOCH,
+                Uuid::from_u64_pair(n, 0),
+                Uuid::from_u64_pair(n, 1),
+            );
+            for k in 0..num_reviews {
+                let now = item.mneme.next_due + Duration::days((n % 3) as i64);
+                let days = (now - item.mneme.state.reviewed_at).num_seconds() as f64 / 86400.0;
+                let grade = recall.grade(&item.mneme, days);
+                item = item.add_review_with_id(p, grade, now, Uuid::from_u64_pair(n, k + 2));
+            }
+            item
+        })
+        .collect()
+}
+
+/// The weights `szr_web` has been using so far.
+static DEFAULT_WEIGHTS: [f64; 17] = [
+    0.4, 0.6, 2.4, 5.8, 4.93, 0.94, 0.86, 0.01, 1.49, 0.14, 0.94, 2.18, 0.05, 0.34, 1.26, 0.29,
+    2.61,
+];
+
 fn interval_history(item: &MnemeWithHistory) -> Vec<i64> {
     let mut h: Vec<_> = item
         .history
@@ -93,21 +139,513 @@ fn test_interval_history_delayed_true() {
     assert_golden_json!((history, item));
 }
 
+#[test]
+fn test_interval_history_sm2() {
+    let p = Params::builder(TEST_WEIGHTS)
+        .scheduler(SchedulerKind::Sm2)
+        .round_to_days(true)
+        .build()
+        .unwrap();
+    let item = sample_mneme(&p, &TEST_GRADES[..], Duration::zero());
+    let history = interval_history(&item);
+    let expected_history = [0, 1, 6, 15, 38, 94, 0, 0, 1, 6, 13, 29, 62];
+    assert_eq!(history, expected_history);
+    assert_eq!(item.mneme.state.difficulty, 2.18);
+}
+
+#[test]
+fn test_interval_history_on_time_fuzzed() {
+    let p = Params::builder(TEST_WEIGHTS).fuzz(true).build().unwrap();
+    let item = sample_mneme(&p, &TEST_GRADES[..], Duration::zero());
+    let history = interval_history(&item);
+    assert_golden_json!((history, item));
+}
+
+#[test]
+fn fuzz_spreads_mnemes_created_together() {
+    let p = Params::builder(TEST_WEIGHTS).fuzz(true).build().unwrap();
+    let init = |n| {
+        Mneme::init_at_with_id(
+            &p,
+            ReviewGrade::Okay,
+            DateTime::UNIX_EPOCH,
+            Uuid::from_u64_pair(n, 0),
+            Uuid::from_u64_pair(n, 1),
+        )
+    };
+    let due: BTreeSet<_> = (0..20).map(|n| init(n).next_due).collect();
+    assert!(due.len() > 10);
+    let (_, _, step) = p.learning_steps();
+    for &d in &due {
+        let interval = d - DateTime::UNIX_EPOCH;
+        assert!(step <= interval && interval <= step + step / 4);
+    }
+    // The spread grows with the step rather than stopping at a few minutes.
+    let (first, last) = (due.first().unwrap(), due.last().unwrap());
+    assert!(*last - *first > step / 8);
+
+    let long = p
+        .to_builder()
+        .learning_steps(Duration::minutes(1), Duration::hours(1), Duration::hours(8))
+        .build()
+        .unwrap();
+    let due: BTreeSet<_> = (0..20)
+        .map(|n| {
+            Mneme::init_at_with_id(
+                &long,
+                ReviewGrade::Okay,
+                DateTime::UNIX_EPOCH,
+                Uuid::from_u64_pair(n, 0),
+                Uuid::from_u64_pair(n, 1),
+            )
+            .next_due
+        })
+        .collect();
+    let (first, last) = (due.first().unwrap(), due.last().unwrap());
+    assert!(*last - *first > Duration::hours(1));
+    assert_eq!(init(3), init(3));
+}
+
+#[test]
+fn load_balancing_picks_lighter_day() {
+    let p = Params::builder(TEST_WEIGHTS)
+        .load_balance(true)
+        .build()
+        .unwrap();
+    let now = DateTime::UNIX_EPOCH;
+    let interval = Duration::days(30);
+    let mut load = DueLoad::default();
+    for k in 25..=35 {
+        for _ in 0..if k == 32 { 1 } else { 10 } {
+            load.add(now + Duration::days(k));
+        }
+    }
+    let balanced = fuzz::adjust_interval(&p, interval, now, Uuid::nil(), 1, Some(&load));
+    assert_eq!(balanced, Duration::days(32));
+    let unbalanced = fuzz::adjust_interval(&p, interval, now, Uuid::nil(), 1, None);
+    assert_eq!(unbalanced, interval);
+}
+
+#[test]
+fn undo_restores_previous_mneme() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..3], Duration::days(1));
+    let next = item.add_review_with_id(
+        &p,
+        ReviewGrade::Fail,
+        item.mneme.next_due,
+        Uuid::from_u64_pair(1, 1),
+    );
+    let undone = next.undo_last_review().unwrap();
+    assert_eq!(undone.mneme, item.mneme);
+    assert_eq!(undone.history, item.history);
+
+    let fresh = MnemeWithHistory::init(&p, ReviewGrade::Okay);
+    assert!(fresh.undo_last_review().is_none());
+}
+
+#[test]
+fn suspended_and_buried_mnemes_are_inactive() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let now = Utc::now();
+    let m = Mneme::init_at(&p, ReviewGrade::Okay, now);
+    assert!(m.is_active(now));
+    let suspended = Mneme {
+        suspended: true,
+        ..m.clone()
+    };
+    assert!(!suspended.is_active(now));
+    let buried = Mneme {
+        buried_until: Some(now + Duration::hours(1)),
+        ..m
+    };
+    assert!(buried.is_buried(now));
+    assert!(!buried.is_active(now));
+    assert!(buried.is_active(now + Duration::hours(1)));
+}
+
+#[test]
+fn predictions_match_reviews() {
+    let p = Params::builder(TEST_WEIGHTS).fuzz(true).build().unwrap();
+    let item = sample_mneme(&p, &TEST_GRADES[..4], Duration::days(3));
+    let m = &item.mneme;
+    let now = m.next_due + Duration::days(2);
+    for grade in [
+        ReviewGrade::Fail,
+        ReviewGrade::Hard,
+        ReviewGrade::Okay,
+        ReviewGrade::Easy,
+    ] {
+        let reviewed = m.reviewed_with_id(&p, grade, now, Uuid::from_u64_pair(1, 1));
+        assert_eq!(
+            m.predicted_interval(&p, grade, now),
+            reviewed.next_due - now
+        );
+    }
+
+    let r = m.retrievability_at(now).unwrap();
+    assert_eq!(m.retrievability_at(m.state.reviewed_at), Some(1.0));
+    assert!(0.0 < r && r < 1.0);
+    assert!(m.retrievability_at(now + Duration::days(30)).unwrap() < r);
+
+    // SM-2 has no model of memory to estimate recall with.
+    let sm2 = p
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap();
+    let m = Mneme::init_at(&sm2, ReviewGrade::Okay, m.created_at);
+    assert_eq!(m.state.scheduler, SchedulerKind::Sm2);
+    assert_eq!(m.retrievability_at(now), None);
+}
+
+#[test]
+fn anki_history_replays_reviews() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let start = DateTime::UNIX_EPOCH;
+    let reviews: Vec<_> = [ReviewGrade::Fail, ReviewGrade::Okay, ReviewGrade::Easy]
+        .into_iter()
+        .enumerate()
+        .map(|(i, grade)| AnkiReview {
+            reviewed_at: start + Duration::days(3 * i as i64),
+            grade,
+        })
+        .collect();
+    let note = AnkiNote {
+        id: 1,
+        fields: vec!["猫".into(), "ネコ".into()],
+        reviews: reviews.clone(),
+    };
+    let fields = anki::Fields {
+        spelling: 0,
+        reading: Some(1),
+    };
+    assert_eq!(note.spelling(fields), Some("猫"));
+    assert_eq!(note.reading(fields).as_deref(), Some("ねこ"));
+
+    let item = note.to_history(&p).unwrap();
+    let grades: Vec<_> = item.states().map(|s| s.grade).collect();
+    let times: Vec<_> = item.states().map(|s| s.reviewed_at).collect();
+    assert_eq!(grades, reviews.iter().map(|r| r.grade).collect::<Vec<_>>());
+    assert_eq!(
+        times,
+        reviews.iter().map(|r| r.reviewed_at).collect::<Vec<_>>()
+    );
+
+    let unreviewed = AnkiNote {
+        reviews: Vec::new(),
+        ..note
+    };
+    assert!(unreviewed.to_history(&p).is_none());
+}
+
+#[test]
+fn export_writes_one_row_per_item() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let start = DateTime::UNIX_EPOCH;
+    let item = |spelling: &str, reading: Option<&str>, suspended: bool| {
+        let mut item = MnemeWithHistory::init_at(&p, ReviewGrade::Okay, start).add_review(
+            &p,
+            ReviewGrade::Easy,
+            start + Duration::days(2),
+        );
+        item.mneme.suspended = suspended;
+        ExportedMneme {
+            variant_id: Uuid::new_v4(),
+            kind: CardKind::Recognition,
+            spelling: spelling.into(),
+            reading: reading.map(Into::into),
+            item,
+        }
+    };
+    let mut mnemes = [
+        item("猫", Some("ねこ"), false),
+        item("犬<猫", None, false),
+        item("鳥", Some("とり"), true),
+    ];
+    // Reset and reviewed again. The reset is not a review of its own.
+    let reset = &mut mnemes[2].item;
+    reset.history.push(reset.mneme.state);
+    reset.mneme = reset
+        .mneme
+        .reset_at_with_id(&p, start + Duration::days(3), Uuid::new_v4());
+    *reset = reset.add_review(&p, ReviewGrade::Okay, start + Duration::days(4));
+
+    let mut out = Vec::new();
+    export::write_mnemes_csv(&mut out, &mnemes).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].starts_with("mneme_id,variant_id,kind,spelling,reading,"));
+    assert!(lines[2].contains(",犬<猫,,"));
+    let column = lines[0]
+        .split(',')
+        .position(|c| c == "num_reviews")
+        .unwrap();
+    let num_reviews: Vec<_> = lines[1..]
+        .iter()
+        .map(|l| l.split(',').nth(column).unwrap())
+        .collect();
+    assert_eq!(num_reviews, ["1", "1", "2"]);
+
+    let mut out = Vec::new();
+    export::write_reviews_csv(&mut out, &mnemes).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(out.lines().count(), 1 + 2 * 2 + 4);
+
+    let mut out = Vec::new();
+    export::write_anki_notes(&mut out, &mnemes, |m| {
+        vec![
+            format!("<b>{}</b>だ", export::escape_html(&m.spelling)),
+            "二つ目".into(),
+        ]
+    })
+    .unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines: Vec<_> = out.lines().collect();
+    assert_eq!(lines.len(), 4 + 2);
+    assert_eq!(lines[4], "猫\tねこ\t<b>猫</b>だ<br>二つ目\tsuzuri");
+    assert_eq!(lines[5], "犬&lt;猫\t\t<b>犬&lt;猫</b>だ<br>二つ目\tsuzuri");
+}
+
+#[test]
+fn optimiser_improves_fit() {
+    let true_params = Params::from_weight_vector(TEST_WEIGHTS);
+    let items = simulated_mnemes(&true_params, 100, 8);
+    let p = Params::from_weight_vector(DEFAULT_WEIGHTS);
+    let optimiser = Optimiser {
+        iterations: 30,
+        ..Default::default()
+    };
+    let fitted = optimiser.fit(&p, &items);
+    assert!(fitted.num_reviews > 0);
+    assert!(
+        fitted.loss < fitted.initial_loss,
+        "{} >= {}",
+        fitted.loss,
+        fitted.initial_loss
+    );
+}
+
+#[test]
+fn only_fsrs_is_evaluated() {
+    let fsrs = Params::from_weight_vector(TEST_WEIGHTS);
+    let sm2 = fsrs
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap();
+    let items = simulated_mnemes(&fsrs, 100, 8);
+    let (fsrs_loss, fsrs_reviews) = optimiser::evaluate(&fsrs, &items).unwrap();
+    assert!(fsrs_reviews > 0);
+    assert!(fsrs_loss.is_finite());
+    assert_eq!(optimiser::evaluate(&sm2, &items), None);
+
+    // Fitting always goes by FSRS, so it gives the same weights either way.
+    let optimiser = Optimiser {
+        iterations: 5,
+        ..Default::default()
+    };
+    assert_eq!(
+        optimiser.fit(&fsrs, &items).weights,
+        optimiser.fit(&sm2, &items).weights
+    );
+}
+
+#[test]
+fn replay_with_same_params_changes_nothing() {
+    let p = Params::builder(TEST_WEIGHTS).fuzz(true).build().unwrap();
+    let items = simulated_mnemes(&p, 20, 6);
+    for item in &items {
+        let replayed = replay::replay(&p, item);
+        assert_eq!(replayed.mneme, item.mneme);
+        assert_eq!(replayed.history, item.history);
+    }
+    let report = Replay::plan(&p, &items).report;
+    assert_eq!(report.replayed, 20);
+    assert_eq!(report.partial, 0);
+    assert_eq!(report.changed, 0);
+    assert!(report.moved.is_empty());
+}
+
+#[test]
+fn replay_with_lower_retention_moves_mnemes_later() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let items = simulated_mnemes(&p, 20, 6);
+    let lower = p.to_builder().target_retention(0.8).build().unwrap();
+    let report = Replay::plan(&lower, &items).report;
+    assert!(!report.moved.is_empty());
+    assert_eq!(report.earlier(), 0);
+    assert_eq!(report.later(), report.moved.len());
+    assert!(report.mean_move_days().unwrap() > 0.0);
+    assert_eq!(
+        report
+            .move_distribution()
+            .iter()
+            .map(|b| b.count)
+            .sum::<usize>(),
+        report.moved.len()
+    );
+}
+
+#[test]
+fn optimiser_keeps_weights_without_reviews() {
+    let p = Params::from_weight_vector(DEFAULT_WEIGHTS);
+    let fitted = Optimiser::default().fit(&p, &[]);
+    assert_eq!(fitted.num_reviews, 0);
+    assert_eq!(fitted.weights, DEFAULT_WEIGHTS);
+}
+
+#[test]
+fn params_builder_rejects_invalid_settings() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    assert!(p.to_builder().target_retention(1.0).build().is_err());
+    assert!(p
+        .to_builder()
+        .learning_steps(
+            Duration::minutes(10),
+            Duration::minutes(5),
+            Duration::minutes(1)
+        )
+        .build()
+        .is_err());
+    assert!(p.to_builder().leech_threshold(0).build().is_err());
+    assert!(p
+        .to_builder()
+        .slow_okay_after(Some(Duration::zero()))
+        .build()
+        .is_err());
+    for profile in ["", "a/b", "../x", "a b", "ä"] {
+        assert!(matches!(
+            p.to_builder().profile(profile).build(),
+            Err(params::Error::InvalidProfileName { .. })
+        ));
+    }
+    assert!(p.to_builder().profile("Fast-2_a").build().is_ok());
+    assert!(p.to_builder().target_retention(0.85).build().is_ok());
+}
+
+fn simulated_deck(p: &Params, count: u64) -> (Vec<Mneme>, DateTime<Utc>) {
+    let deck: Vec<_> = simulated_mnemes(p, count, 4)
+        .into_iter()
+        .map(|item| item.mneme)
+        .collect();
+    let start = deck.iter().map(|m| m.state.reviewed_at).max().unwrap();
+    (deck, start)
+}
+
+#[test]
+fn simulator_is_deterministic() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let (deck, start) = simulated_deck(&p, 5);
+    let simulator = Simulator { days: 14 };
+    let forecast = simulator.run(&p, &deck, start, &mut SampledRecall::new(1));
+    let again = simulator.run(&p, &deck, start, &mut SampledRecall::new(1));
+    assert_eq!(forecast, again);
+    assert_golden_json!(forecast);
+}
+
+#[test]
+fn simulator_higher_retention_costs_more_reviews() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let (deck, start) = simulated_deck(&p, 100);
+    let simulator = Simulator { days: 90 };
+    let stricter = p.to_builder().target_retention(0.95).build().unwrap();
+    let base = simulator.run(&p, &deck, start, &mut SampledRecall::new(1));
+    let raised = simulator.run(&stricter, &deck, start, &mut SampledRecall::new(1));
+    assert!(raised.total_reviews() > base.total_reviews());
+    assert!(raised.mean_retention() > base.mean_retention());
+}
+
+#[test]
+fn simulator_without_days_reviews_nothing() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let (deck, start) = simulated_deck(&p, 10);
+    let mut reviewed = Vec::new();
+    let mut always_okay = |m: &Mneme, _: f64| {
+        reviewed.push(m.id);
+        ReviewGrade::Okay
+    };
+    let forecast = Simulator { days: 0 }.run(&p, &deck, start, &mut always_okay);
+    assert!(forecast.days.is_empty());
+    assert!(reviewed.is_empty());
+}
+
+/// A mneme reviewed with `grades`, each review happening right when it was due.
+fn due_mneme(p: &Params, n: u64, grades: &[ReviewGrade]) -> Mneme {
+    let mut item = MnemeWithHistory::init_at_with_id(
+        p,
+        ReviewGrade::Okay,
+        DateTime::UNIX_EPOCH,
+        Uuid::from_u64_pair(n, 0),
+        Uuid::from_u64_pair(n, 1),
+    );
+    for (k, &grade) in grades.iter().enumerate() {
+        let now = item.mneme.next_due;
+        item = item.add_review_with_id(p, grade, now, Uuid::from_u64_pair(n, k as u64 + 2));
+    }
+    item.mneme
+}
+
+#[test]
+fn queue_respects_daily_limits() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let okay = [ReviewGrade::Okay; 2];
+    let due: Vec<_> = (0..10)
+        .map(|n| due_mneme(&p, n, &[]))
+        .chain((10..20).map(|n| due_mneme(&p, n, &okay)))
+        .collect();
+    assert!(due[10..]
+        .iter()
+        .all(|m| m.state.status == MemoryStatus::Reviewing));
+    let limits = queue::Limits {
+        new_per_day: 5,
+        reviews_per_day: 8,
+    };
+    let usage = queue::Usage { new: 2, reviews: 1 };
+    let batch = queue::build(&due, limits, usage);
+    let new = batch.iter().filter(|id| id.as_u64_pair().0 < 10).count();
+    assert_eq!(new, 3);
+    assert_eq!(batch.len() - new, 7);
+}
+
+#[test]
+fn queue_interleaves_kinds() {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let due: Vec<_> = (0..2)
+        .map(|n| due_mneme(&p, n, &[]))
+        .chain((2..4).map(|n| due_mneme(&p, n, &[ReviewGrade::Fail])))
+        .chain((4..10).map(|n| due_mneme(&p, n, &[ReviewGrade::Okay; 2])))
+        .collect();
+    let batch = queue::build(&due, Default::default(), Default::default());
+    let kinds: Vec<_> = batch.iter().map(|id| id.as_u64_pair().0).collect();
+    // Two learning, six under review and two new, spread over the batch.
+    assert_eq!(kinds.len(), 10);
+    assert!(kinds[..5].iter().any(|&n| n < 2));
+    assert!(kinds[5..].iter().any(|&n| n < 2));
+    assert!(kinds[..5].iter().any(|&n| (2..4).contains(&n)));
+    assert!(kinds[5..].iter().any(|&n| (2..4).contains(&n)));
+}
+
 // SQL tests
 
 #[sqlx::test(migrations = "../migrations")]
 async fn can_create_mneme(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
     let p = Params::from_weight_vector(TEST_WEIGHTS);
-    Mneme::create(&pool, &p, ReviewGrade::Hard).await.unwrap();
+    Mneme::create(&mut conn, &p, ReviewGrade::Hard)
+        .await
+        .unwrap();
     Ok(())
 }
 
 #[sqlx::test(migrations = "../migrations")]
 async fn create_preserves_mneme(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
     let p = Params::from_weight_vector(TEST_WEIGHTS);
     let new_mneme = Mneme::init(&p, ReviewGrade::Easy);
     let id = new_mneme.id;
-    let _ = new_mneme.clone().persist(&pool).await.unwrap();
+    let _ = new_mneme.clone().persist(&mut conn).await.unwrap();
     let db_mneme = Mneme::get_by_id(&pool, id).await.unwrap();
     assert_eq!(new_mneme, db_mneme);
     Ok(())
@@ -115,10 +653,1405 @@ async fn create_preserves_mneme(pool: PgPool) -> sqlx::Result<()> {
 
 #[sqlx::test(migrations = "../migrations")]
 async fn can_review_mneme(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Easy)
+        .await
+        .unwrap();
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Hard, None)
+        .await
+        .unwrap();
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn review_appends_to_history(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    let initial_state = Mneme::get_by_id(&pool, id).await.unwrap().state;
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    let indices: Vec<_> = item.history.iter().map(|s| s.index).collect();
+    assert_eq!(indices, [0, 1]);
+    assert_eq!(item.history[0], initial_state);
+    assert_eq!(item.history[1].grade, ReviewGrade::Fail);
+    assert_eq!(item.mneme.state.index, 2);
+    assert_eq!(item.mneme.state.grade, ReviewGrade::Okay);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn persist_preserves_history(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..], Duration::days(1));
+    let id = item.mneme.id;
+    item.clone().persist(&mut conn).await.unwrap();
+    let db_item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.mneme, db_item.mneme);
+    assert_eq!(item.history, db_item.history);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn replay_rewrites_states_and_due_dates(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let mut items = simulated_mnemes(&p, 3, 4);
+    // Logged from a later review on, as for mnemes from before there were logs.
+    let first = &mut items[0].history[0];
+    first.due_at = first.reviewed_at - Duration::days(1);
+    let kept = *first;
+    for item in items.clone() {
+        item.persist(&mut conn).await.unwrap();
+    }
+
+    let sm2 = p
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    let replay = Replay::prepare(&pool, &sm2).await.unwrap();
+    assert_eq!(replay.report.replayed, 3);
+    assert_eq!(replay.report.partial, 1);
+    assert_eq!(replay.report.changed, 3);
+    let report = replay.apply(&pool).await.unwrap();
+    assert!(!report.moved.is_empty());
+
+    for item in &items {
+        let expected = replay::replay(&sm2, item);
+        let db_item = MnemeWithHistory::get_by_id(&pool, item.mneme.id)
+            .await
+            .unwrap();
+        assert_eq!(db_item.mneme, expected.mneme);
+        assert_eq!(db_item.history, expected.history);
+        assert_eq!(db_item.mneme.state.params_version, sm2.version());
+    }
+    // There is nothing to replay the first state from, but it is an FSRS one,
+    // so it starts over with what SM-2 gives its grade.
+    let db_item = MnemeWithHistory::get_by_id(&pool, items[0].mneme.id)
+        .await
+        .unwrap();
+    let first = db_item.history[0];
+    assert_eq!(
+        (first.id, first.grade, first.due_at, first.reviewed_at),
+        (kept.id, kept.grade, kept.due_at, kept.reviewed_at)
+    );
+    assert_eq!(first.scheduler, SchedulerKind::Sm2);
+    assert_ne!(first.stability, kept.stability);
+
+    let again = Replay::prepare(&pool, &sm2).await.unwrap();
+    assert_eq!(again.report.changed, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn replay_is_not_applied_over_newer_reviews(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let items = simulated_mnemes(&p, 2, 4);
+    for item in items.clone() {
+        item.persist(&mut conn).await.unwrap();
+    }
+    let sm2 = p
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    let replay = Replay::prepare(&pool, &sm2).await.unwrap();
+    assert_eq!(replay.report.changed, 2);
+
+    // Reviewed in the meantime, so the plan no longer fits its log.
+    let id = items[0].mneme.id;
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let before = MnemeWithHistory::get_by_id(&pool, items[1].mneme.id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        replay.apply(&pool).await,
+        Err(replay::Error::LogChanged)
+    ));
+    let after = MnemeWithHistory::get_by_id(&pool, items[1].mneme.id)
+        .await
+        .unwrap();
+    assert_eq!(after.mneme, before.mneme);
+    assert_eq!(after.history, before.history);
+
+    Replay::prepare(&pool, &sm2)
+        .await
+        .unwrap()
+        .apply(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        Mneme::get_by_id(&pool, id).await.unwrap().state.scheduler,
+        SchedulerKind::Sm2
+    );
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn replay_is_only_applied_if_it_matches_the_one_shown(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let items = simulated_mnemes(&p, 2, 4);
+    for item in items.clone() {
+        item.persist(&mut conn).await.unwrap();
+    }
+    let sm2 = p
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    let shown = Replay::prepare(&pool, &sm2).await.unwrap().fingerprint();
+    assert_eq!(
+        Replay::prepare(&pool, &sm2).await.unwrap().fingerprint(),
+        shown
+    );
+    let other_params = Replay::prepare(&pool, &p).await.unwrap();
+    assert_ne!(other_params.fingerprint(), shown);
+
+    // Reviewed after the replay was shown, and before it was confirmed.
+    let id = items[0].mneme.id;
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let before = MnemeWithHistory::get_by_id(&pool, items[1].mneme.id)
+        .await
+        .unwrap();
+    let replay = Replay::prepare(&pool, &sm2).await.unwrap();
+    assert_ne!(replay.fingerprint(), shown);
+    assert!(matches!(
+        replay.apply_if_matches(&pool, shown).await,
+        Err(replay::Error::PlanChanged)
+    ));
+    let after = MnemeWithHistory::get_by_id(&pool, items[1].mneme.id)
+        .await
+        .unwrap();
+    assert_eq!(after.mneme, before.mneme);
+    assert_eq!(after.history, before.history);
+
+    let replay = Replay::prepare(&pool, &sm2).await.unwrap();
+    let fingerprint = replay.fingerprint();
+    replay.apply_if_matches(&pool, fingerprint).await.unwrap();
+    assert_eq!(
+        Mneme::get_by_id(&pool, id).await.unwrap().state.scheduler,
+        SchedulerKind::Sm2
+    );
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn review_replays_mnemes_from_another_scheduler(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..4], Duration::days(1));
+    item.clone().persist(&mut conn).await.unwrap();
+    let sm2 = p
+        .to_builder()
+        .scheduler(SchedulerKind::Sm2)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+
+    let mneme = Mneme::get_by_id(&pool, item.mneme.id).await.unwrap();
+    assert_eq!(mneme.state.scheduler, SchedulerKind::Fsrs);
+    let same = mneme.clone().for_scheduler(&mut conn, &p).await.unwrap();
+    assert_eq!(same, mneme);
+    let converted = mneme.clone().for_scheduler(&mut conn, &sm2).await.unwrap();
+    assert_eq!(converted, replay::replay(&sm2, &item).mneme);
+    assert_eq!(converted.state.scheduler, SchedulerKind::Sm2);
+
+    let state_id = mneme
+        .review(&mut conn, &sm2, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let reviewed = Mneme::get_by_id(&pool, item.mneme.id).await.unwrap();
+    let expected = converted.reviewed_with_id(
+        &sm2,
+        ReviewGrade::Okay,
+        reviewed.state.reviewed_at,
+        state_id,
+    );
+    assert_eq!(reviewed.state, expected.state);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn review_follows_on_from_reviews_made_since_it_was_read(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..4], Duration::days(1));
+    item.clone().persist(&mut conn).await.unwrap();
+    let id = item.mneme.id;
+    let index = item.mneme.state.index;
+
+    let first = Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Hard, None)
+        .await
+        .unwrap();
+    // Read before the review above, as by a second request for the same card.
+    let second = item
+        .mneme
+        .review(&mut conn, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+
+    let states = MnemeState::get_all_for_mneme(&mut *conn, id).await.unwrap();
+    let new: Vec<_> = states
+        .iter()
+        .filter(|s| s.index > index)
+        .map(|s| (s.index, s.id))
+        .collect();
+    assert_eq!(new, [(index + 1, first), (index + 2, second)]);
+    let reviewed = Mneme::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(reviewed.state.id, second);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn optimiser_persists_new_version(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..], Duration::days(1));
+    item.persist(&mut conn).await.unwrap();
+    let optimiser = Optimiser {
+        iterations: 5,
+        ..Default::default()
+    };
+    let (version, fitted) = optimiser.optimise(&pool, &p).await.unwrap();
+    assert!(version > 0);
+    let weights = sqlx::query_scalar!("SELECT weights FROM params WHERE version = $1", version)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(weights, fitted.weights);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn params_roundtrip(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::builder(TEST_WEIGHTS)
+        .profile("test")
+        .scheduler(SchedulerKind::Sm2)
+        .target_retention(0.85)
+        .round_to_days(true)
+        .fuzz(true)
+        .load_balance(true)
+        .leech_threshold(3)
+        .suspend_leeches(true)
+        .limits(queue::Limits {
+            new_per_day: 5,
+            reviews_per_day: 50,
+        })
+        .slow_okay_after(Some(Duration::seconds(15)))
+        .learning_steps(
+            Duration::seconds(30),
+            Duration::minutes(2),
+            Duration::hours(1),
+        )
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    let db_p = Params::get_latest(&pool, "test").await.unwrap();
+    assert_eq!(db_p.version(), p.version());
+    assert_eq!(db_p.profile(), "test");
+    assert_eq!(db_p.scheduler(), SchedulerKind::Sm2);
+    assert_eq!(db_p.target_retention(), 0.85);
+    assert!(db_p.round_to_days());
+    assert!(db_p.fuzz());
+    assert!(db_p.load_balance());
+    assert_eq!(db_p.leech_threshold(), 3);
+    assert!(db_p.suspend_leeches());
+    assert_eq!(db_p.limits().new_per_day, 5);
+    assert_eq!(db_p.limits().reviews_per_day, 50);
+    assert_eq!(db_p.slow_okay_after(), Some(Duration::seconds(15)));
+    assert_eq!(db_p.learning_steps(), p.learning_steps());
+    assert_eq!(db_p.weight_vector(), TEST_WEIGHTS);
+    Ok(())
+}
+
+#[test]
+fn durations_roundtrip_through_settings() {
+    for (text, d) in [
+        ("30s", Duration::seconds(30)),
+        ("10m", Duration::minutes(10)),
+        ("1h", Duration::hours(1)),
+        ("2d", Duration::days(2)),
+        ("1.5s", Duration::milliseconds(1500)),
+    ] {
+        assert_eq!(params::parse_duration(text), Some(d));
+        assert_eq!(params::format_duration(d), text);
+    }
+    assert_eq!(params::parse_duration(" 90 "), Some(Duration::seconds(90)));
+    assert_eq!(params::format_duration(Duration::seconds(90)), "90s");
+    assert_eq!(params::parse_duration("1.5h"), Some(Duration::minutes(90)));
+    assert_eq!(params::parse_duration("10x"), None);
+    assert_eq!(params::parse_duration("1e300d"), None);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn active_profile_is_used_for_reviews(pool: PgPool) -> sqlx::Result<()> {
+    let default = Params::get_active(&pool).await.unwrap();
+    assert_eq!(default.profile(), params::DEFAULT_PROFILE);
+    assert!(matches!(
+        params::set_active_profile(&pool, "other").await,
+        Err(params::Error::UnknownProfile { .. })
+    ));
+    assert!(matches!(
+        params::set_active_profile(&pool, "../other").await,
+        Err(params::Error::InvalidProfileName { .. })
+    ));
+
+    let other = default
+        .to_builder()
+        .profile("other")
+        .target_retention(0.8)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    params::set_active_profile(&pool, "other").await.unwrap();
+    assert_eq!(params::active_profile(&pool).await.unwrap(), "other");
+    assert_eq!(
+        Params::get_active(&pool).await.unwrap().version(),
+        other.version()
+    );
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn reviews_record_profile_version(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let default = Params::get_latest(&pool, params::DEFAULT_PROFILE)
+        .await
+        .unwrap();
+    assert_eq!(default.weight_vector(), DEFAULT_WEIGHTS);
+    let p = default
+        .to_builder()
+        .target_retention(0.8)
+        .build()
+        .unwrap()
+        .persist(&pool)
+        .await
+        .unwrap();
+    assert!(p.version() > default.version());
+    let latest = Params::get_latest(&pool, params::DEFAULT_PROFILE)
+        .await
+        .unwrap();
+    assert_eq!(latest.version(), p.version());
+
+    let id = Mneme::create(&mut conn, &default, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    Mneme::review_by_id(&mut conn, id, &latest, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.history[0].params_version, default.version());
+    assert_eq!(item.mneme.state.params_version, latest.version());
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn get_all_returns_current_states(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    let mnemes = Mneme::get_all(&pool).await.unwrap();
+    assert_eq!(mnemes, [Mneme::get_by_id(&pool, id).await.unwrap()]);
+    Ok(())
+}
+
+/// A mneme created with `grade` as the recognition card of a variant of its
+/// own, so that review sessions can pick it up.
+async fn create_queueable(conn: &mut sqlx::PgConnection, p: &Params, grade: ReviewGrade) -> Uuid {
+    let id = Mneme::create(&mut *conn, p, grade).await.unwrap();
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ($1, '', '', '', '', 'test') RETURNING id AS "id!""#,
+        id.to_string()
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    sqlx::query!(
+        "INSERT INTO variants (lemma_id, spelling, mneme_id) VALUES ($1, $2, $3)",
+        lemma_id,
+        id.to_string(),
+        id
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    id
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn session_is_stable_until_done(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    for _ in 0..3 {
+        create_queueable(&mut conn, &p, ReviewGrade::Fail).await;
+    }
+    // Mnemes that are not the card of any variant are never queued.
+    Mneme::create(&mut conn, &p, ReviewGrade::Fail)
+        .await
+        .unwrap();
+    let later = Utc::now() + Duration::hours(1);
+    let limits = queue::Limits::default();
+    let session = queue::Session::current_or_start(&pool, limits, later)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.items.len(), 3);
+    let again = queue::Session::current_or_start(&pool, limits, later)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session, again);
+
+    for &id in &session.items {
+        assert_eq!(session.pending(&pool, later).await.unwrap()[0], id);
+        Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+            .await
+            .unwrap();
+    }
+    assert!(session.pending(&pool, later).await.unwrap().is_empty());
+    let usage = queue::Usage::get_for_day(&pool, later).await.unwrap();
+    assert_eq!(usage.new, 3);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn undo_keeps_undone_state(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    let created = Mneme::get_by_id(&pool, id).await.unwrap().state.id;
+    assert!(Mneme::undo_review_by_id(&mut conn, id, created)
+        .await
+        .is_err());
+
+    let first = Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let before = Mneme::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(before.state.id, first);
+    let second = Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    Mneme::undo_review_by_id(&mut conn, id, second)
+        .await
+        .unwrap();
+    assert_eq!(Mneme::get_by_id(&pool, id).await.unwrap(), before);
+
+    // Undoing the same review again does not undo the one before it.
+    assert!(matches!(
+        Mneme::undo_review_by_id(&mut conn, id, second).await,
+        Err(mneme::Error::NotCurrentState { .. })
+    ));
+    assert_eq!(Mneme::get_by_id(&pool, id).await.unwrap(), before);
+
+    // The undone review makes way for the next one.
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Easy, None)
+        .await
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.history.last(), Some(&before.state));
+    assert_eq!(item.mneme.state.index, 2);
+    assert_eq!(item.mneme.state.grade, ReviewGrade::Easy);
+    let num_states = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "n!" FROM mneme_states WHERE mneme_id = $1"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(num_states, 4);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn undo_requeues_session_item(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = create_queueable(&mut conn, &p, ReviewGrade::Fail).await;
+    let later = Utc::now() + Duration::hours(1);
+    let session = queue::Session::start(&pool, Default::default(), later)
+        .await
+        .unwrap()
+        .unwrap();
+    let state_id = Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    assert!(session.pending(&pool, later).await.unwrap().is_empty());
+    Mneme::undo_review_by_id(&mut conn, id, state_id)
+        .await
+        .unwrap();
+    assert_eq!(session.pending(&pool, later).await.unwrap(), [id]);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn suspended_and_buried_mnemes_leave_queue(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let mut ids = Vec::new();
+    for _ in 0..3 {
+        ids.push(create_queueable(&mut conn, &p, ReviewGrade::Fail).await);
+    }
+    let later = Utc::now() + Duration::hours(1);
+    let session = queue::Session::start(&pool, Default::default(), later)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.items.len(), 3);
+
+    Mneme::set_suspended(&pool, ids[0], true).await.unwrap();
+    Mneme::set_buried_until(&pool, ids[1], Some(later + Duration::hours(1)))
+        .await
+        .unwrap();
+    assert_eq!(session.pending(&pool, later).await.unwrap(), [ids[2]]);
+    assert!(Mneme::get_by_id(&pool, ids[0]).await.unwrap().suspended);
+
+    // Burial runs out, suspension does not.
+    let much_later = later + Duration::hours(2);
+    let mut pending = session.pending(&pool, much_later).await.unwrap();
+    pending.sort();
+    let mut expected = vec![ids[1], ids[2]];
+    expected.sort();
+    assert_eq!(pending, expected);
+
+    Mneme::set_suspended(&pool, ids[0], false).await.unwrap();
+    assert_eq!(session.pending(&pool, much_later).await.unwrap().len(), 3);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn reset_starts_mneme_over(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    for grade in [ReviewGrade::Okay, ReviewGrade::Easy] {
+        Mneme::review_by_id(&mut conn, id, &p, grade, None)
+            .await
+            .unwrap();
+    }
+    Mneme::reset_by_id(&mut conn, id, &p).await.unwrap();
+
+    // The log is kept, with the reset at the end of it.
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.history.len(), 3);
+    assert_eq!(item.mneme.state.index, 3);
+    assert!(item.mneme.state.reset);
+    assert!(item.mneme.state.is_start());
+    assert!(item.mneme.next_due <= Utc::now());
+    assert!(item.undo_last_review().is_none());
+    let num_undone = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "n!" FROM mneme_states
+WHERE mneme_id = $1 AND undone_at IS NOT NULL"#,
+        id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert_eq!(num_undone, 0);
+    let today = stats::reviews_per_day(&pool, Utc::now(), 1).await.unwrap();
+    assert_eq!((today[0].new, today[0].reviews), (1, 2));
+    assert!(matches!(
+        Mneme::undo_review_by_id(&mut conn, id, item.mneme.state.id).await,
+        Err(mneme::Error::NothingToUndo { .. })
+    ));
+
+    // Replaying the log starts the mneme over at the reset too.
+    let replayed = replay::replay(&p, &item);
+    assert!(replayed.mneme.state.reset);
+    assert_eq!(replayed.mneme.state.stability, item.mneme.state.stability);
+
+    // The reset mneme can be reviewed as usual, as a new one.
+    let usage = queue::Usage::get_for_day(&pool, Utc::now()).await.unwrap();
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.mneme.state.index, 4);
+    assert!(!item.mneme.state.is_start());
+    let after = queue::Usage::get_for_day(&pool, Utc::now()).await.unwrap();
+    assert_eq!(after.new, usage.new + 1);
+    assert_eq!(after.reviews, usage.reviews);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn reviews_roll_back_with_transaction(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let ids = [
+        Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+            .await
+            .unwrap(),
+        Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+            .await
+            .unwrap(),
+    ];
+    let before = Mneme::get_all(&pool).await.unwrap();
+
+    let mut tx = pool.begin().await?;
+    Mneme::review_by_id(&mut tx, ids[0], &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    // A failure partway through leaves the transaction usable...
+    assert!(
+        Mneme::review_by_id(&mut tx, Uuid::nil(), &p, ReviewGrade::Okay, None)
+            .await
+            .is_err()
+    );
+    Mneme::review_by_id(&mut tx, ids[1], &p, ReviewGrade::Okay, None)
+        .await
+        .unwrap();
+    // ...and nothing is applied unless it is committed.
+    tx.rollback().await?;
+    assert_eq!(Mneme::get_all(&pool).await.unwrap(), before);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn persist_fails_without_partial_writes(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let item = sample_mneme(&p, &TEST_GRADES[..3], Duration::days(1));
+    item.clone().persist(&mut conn).await.unwrap();
+
+    let mut clash = MnemeWithHistory::init(&p, ReviewGrade::Okay);
+    clash.history.push(item.mneme.state);
+    let clash_id = clash.mneme.id;
+    assert!(clash.persist(&mut conn).await.is_err());
+    assert!(Mneme::get_by_id(&pool, clash_id).await.is_err());
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn due_histogram_counts_by_day(pool: PgPool) -> sqlx::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let now = Utc::now();
+    let today = queue::Usage::day_start(now);
+    let mut ids = Vec::new();
+    for _ in 0..4 {
+        ids.push(create_queueable(&mut conn, &p, ReviewGrade::Okay).await);
+    }
+    // Not the card of any variant, so it never comes up for review.
+    ids.push(
+        Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+            .await
+            .unwrap(),
+    );
+    // Overdue, due tomorrow, suspended, buried until the day after, and due
+    // tomorrow but without a variant.
+    for (id, due) in ids.iter().zip([
+        today - Duration::days(2),
+        today + Duration::hours(30),
+        today,
+        today,
+        today + Duration::hours(30),
+    ]) {
+        sqlx::query!("UPDATE mnemes SET next_due = $2 WHERE id = $1", id, due)
+            .execute(&pool)
+            .await?;
+    }
+    Mneme::set_suspended(&pool, ids[2], true).await.unwrap();
+    Mneme::set_buried_until(&pool, ids[3], Some(today + Duration::hours(50)))
+        .await
+        .unwrap();
+
+    let histogram = forecast::due_histogram(&pool, now, 4).await.unwrap();
+    let dates: Vec<_> = histogram.iter().map(|d| d.date).collect();
+    assert_eq!(
+        dates,
+        (0..4)
+            .map(|i| today + Duration::days(i))
+            .collect::<Vec<_>>()
+    );
+    let counts: Vec<_> = histogram.iter().map(|d| d.due).collect();
+    assert_eq!(counts, [1, 1, 1, 0]);
+    Ok(())
+}
+
+/// The log of each card of an Anki note, as the kind of each entry and the
+/// button that was pressed.
+type AnkiCardLogs<'a> = &'a [&'a [(i64, i64)]];
+
+/// A minimal Anki collection for the given version of its scheduler with the
+/// given notes, each with the logs of its cards. Entries are a day apart.
+async fn write_anki_collection(
+    scheduler_version: i64,
+    notes: &[(&str, AnkiCardLogs<'_>)],
+) -> std::path::PathBuf {
+    use sqlx::{
+        sqlite::{SqliteConnectOptions, SqliteConnection},
+        Connection,
+    };
+
+    let path = std::env::temp_dir().join(format!("szr-{}.anki2", Uuid::new_v4()));
+    let options = SqliteConnectOptions::new()
+        .filename(&path)
+        .create_if_missing(true);
+    let mut conn = SqliteConnection::connect_with(&options).await.unwrap();
+    for table in [
+        "CREATE TABLE notes (id integer PRIMARY KEY, flds text NOT NULL)",
+        "CREATE TABLE cards (id integer PRIMARY KEY, nid integer NOT NULL)",
+        "CREATE TABLE revlog (id integer PRIMARY KEY, cid integer NOT NULL, type integer NOT NULL, ease integer NOT NULL)",
+        "CREATE TABLE col (id integer PRIMARY KEY, conf text NOT NULL)",
+    ] {
+        sqlx::query(table).execute(&mut conn).await.unwrap();
+    }
+    sqlx::query("INSERT INTO col (id, conf) VALUES (1, ?)")
+        .bind(format!(r#"{{"schedVer": {scheduler_version}}}"#))
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let mut card_id = 0;
+    let mut review_id = 1_600_000_000_000_i64;
+    for (note_id, (fields, cards)) in notes.iter().enumerate() {
+        sqlx::query("INSERT INTO notes (id, flds) VALUES (?, ?)")
+            .bind(note_id as i64)
+            .bind(fields)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        for answers in cards.iter() {
+            card_id += 1;
+            sqlx::query("INSERT INTO cards (id, nid) VALUES (?, ?)")
+                .bind(card_id)
+                .bind(note_id as i64)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+            for (kind, ease) in answers.iter() {
+                review_id += 86_400_000;
+                sqlx::query("INSERT INTO revlog (id, cid, type, ease) VALUES (?, ?, ?, ?)")
+                    .bind(review_id)
+                    .bind(card_id)
+                    .bind(kind)
+                    .bind(ease)
+                    .execute(&mut conn)
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+    path
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn anki_import_matches_variants(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    for (spelling, reading) in [
+        ("猫", "ねこ"),
+        ("生", "なま"),
+        ("生", "せい"),
+        ("鳥", "とり"),
+    ] {
+        sqlx::query!(
+            "INSERT INTO variants (lemma_id, spelling, reading) VALUES ($1, $2, $3)",
+            lemma_id,
+            spelling,
+            reading
+        )
+        .execute(&pool)
+        .await?;
+    }
+    let mut conn = pool.acquire().await?;
+    let bird = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "UPDATE variants SET mneme_id = $1 WHERE spelling = '鳥'",
+        bird
+    )
+    .execute(&pool)
+    .await?;
+
+    let path = write_anki_collection(
+        2,
+        &[
+            // The second card has the longer log, and manual rescheduling is
+            // skipped.
+            (
+                "<b>猫</b>\x1fネコ",
+                &[&[(1, 3)], &[(0, 1), (4, 0), (1, 3), (1, 4)]],
+            ),
+            ("犬\x1fいぬ", &[&[(1, 3)]]),
+            ("生\x1f", &[&[(1, 3)]]),
+            ("鳥\x1fとり", &[&[(1, 3)]]),
+            ("魚\x1fさかな", &[]),
+        ],
+    )
+    .await;
+    let notes = anki::read_collection(&path).await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(notes.len(), 4);
+    assert_eq!(notes[0].fields, ["猫", "ネコ"]);
+    assert_eq!(notes[0].reviews.len(), 3);
+
+    let fields = anki::Fields {
+        spelling: 0,
+        reading: Some(1),
+    };
+    let report = anki::import(&pool, &p, &notes, fields).await.unwrap();
+    assert_eq!(
+        report,
+        anki::ImportReport {
+            imported: vec!["猫".into()],
+            unmatched: vec!["犬".into()],
+            ambiguous: vec!["生".into()],
+            already_known: vec!["鳥".into()],
+        }
+    );
+
+    let cat = sqlx::query_scalar!("SELECT mneme_id FROM variants WHERE spelling = '猫'")
+        .fetch_one(&pool)
+        .await?
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, cat).await.unwrap();
+    let grades: Vec<_> = item.states().map(|s| s.grade).collect();
+    assert_eq!(
+        grades,
+        [ReviewGrade::Fail, ReviewGrade::Okay, ReviewGrade::Easy]
+    );
+    Ok(())
+}
+
+#[sqlx::test]
+async fn anki_answers_are_graded_by_kind_and_scheduler() {
+    // Learning, review and relearning answers, cramming in a filtered deck,
+    // and learning again.
+    let answers: &[(i64, i64)] = &[(0, 1), (0, 2), (0, 3), (1, 2), (2, 2), (3, 3), (0, 4)];
+    let mut grades = Vec::new();
+    for scheduler_version in [1, 2] {
+        let path = write_anki_collection(scheduler_version, &[("猫\x1fねこ", &[answers])]).await;
+        let notes = anki::read_collection(&path).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        grades.push(notes[0].reviews.iter().map(|r| r.grade).collect::<Vec<_>>());
+    }
+    use ReviewGrade::*;
+    // Learning cards only had three buttons under the first scheduler.
+    assert_eq!(grades[0], [Fail, Okay, Easy, Hard, Okay]);
+    assert_eq!(grades[1], [Fail, Hard, Okay, Hard, Hard, Easy]);
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn export_includes_mnemes_with_variants(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    let mut conn = pool.acquire().await?;
+    let cat = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    // Not attached to any variant
+    Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    let variant_id = sqlx::query_scalar!(
+        r#"INSERT INTO variants (lemma_id, spelling, reading, mneme_id)
+VALUES ($1, '猫', 'ねこ', $2) RETURNING id AS "id!""#,
+        lemma_id,
+        cat
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let mnemes = ExportedMneme::get_all(&pool).await.unwrap();
+    assert_eq!(mnemes.len(), 1);
+    assert_eq!(mnemes[0].variant_id, variant_id);
+    assert_eq!(mnemes[0].item.mneme.id, cat);
+    assert_eq!(mnemes[0].reading.as_deref(), Some("ねこ"));
+    Ok(())
+}
+
+async fn create_card(
+    conn: &mut sqlx::PgConnection,
+    p: &Params,
+    variant_id: Uuid,
+    kind: CardKind,
+) -> Uuid {
+    let id = Mneme::create(&mut *conn, p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    card::attach(conn, variant_id, kind, id).await.unwrap();
+    id
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn cards_are_scheduled_separately(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    let variant_id = sqlx::query_scalar!(
+        r#"INSERT INTO variants (lemma_id, spelling, reading)
+VALUES ($1, '猫', 'ねこ') RETURNING id AS "id!""#,
+        lemma_id
+    )
+    .fetch_one(&pool)
+    .await?;
+
+    let mut conn = pool.acquire().await?;
+    let production = create_card(&mut conn, &p, variant_id, CardKind::Production).await;
+    let recognition = create_card(&mut conn, &p, variant_id, CardKind::Recognition).await;
+    assert_eq!(
+        card::cards_for_variant(&pool, variant_id).await.unwrap(),
+        [
+            (CardKind::Recognition, recognition),
+            (CardKind::Production, production)
+        ]
+    );
+    assert_eq!(
+        card::kind_of(&pool, production).await.unwrap(),
+        Some(CardKind::Production)
+    );
+    assert_eq!(
+        card::mneme_for(&pool, variant_id, CardKind::Meaning)
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Reviewing one card leaves the others alone.
+    Mneme::review_by_id(&mut conn, production, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    let item = MnemeWithHistory::get_by_id(&pool, recognition)
+        .await
+        .unwrap();
+    assert_eq!(item.states().count(), 1);
+
+    // A new card of the same kind replaces the old one.
+    let replacement = create_card(&mut conn, &p, variant_id, CardKind::Production).await;
+    assert_eq!(
+        card::mneme_for(&pool, variant_id, CardKind::Production)
+            .await
+            .unwrap(),
+        Some(replacement)
+    );
+
+    let kinds: BTreeSet<_> = ExportedMneme::get_all(&pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|m| m.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        BTreeSet::from([CardKind::Recognition, CardKind::Production])
+    );
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn knowledge_spreads_to_lemmas_and_kanji(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::from_weight_vector(TEST_WEIGHTS);
+    let mut lemma_ids = Vec::new();
+    for spelling in ["分かる", "自分"] {
+        lemma_ids.push(
+            sqlx::query_scalar!(
+                r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ($1, '', '', '', '', 'test') RETURNING id AS "id!""#,
+                spelling
+            )
+            .fetch_one(&pool)
+            .await?,
+        );
+    }
+    let mut variant_ids = Vec::new();
+    for (lemma, spelling, reading, morphemes) in [
+        (
+            0,
+            "分かる",
+            "わかる",
+            &[("分", "わ"), ("か", "か"), ("る", "る")][..],
+        ),
+        (0, "分る", "わかる", &[("分", "わか"), ("る", "る")]),
+        (1, "自分", "じぶん", &[("自", "じ"), ("分", "ぶん")]),
+    ] {
+        let lemma_id = lemma_ids[lemma];
+        let variant_id = sqlx::query_scalar!(
+            r#"INSERT INTO variants (lemma_id, spelling, reading)
+VALUES ($1, $2, $3) RETURNING id AS "id!""#,
+            lemma_id,
+            spelling,
+            reading
+        )
+        .fetch_one(&pool)
+        .await?;
+        for (index, (spelling, reading)) in morphemes.iter().enumerate() {
+            sqlx::query!(
+                r#"INSERT INTO morpheme_occs (variant_id, index, spelling, reading, underlying_reading)
+VALUES ($1, $2, $3, $4, $4)"#,
+                variant_id,
+                index as i32,
+                spelling,
+                reading
+            )
+            .execute(&pool)
+            .await?;
+        }
+        variant_ids.push(variant_id);
+    }
+    let mut conn = pool.acquire().await?;
+    let mneme_id = create_card(&mut conn, &p, variant_ids[0], CardKind::Recognition).await;
+
+    let now = Utc::now() + Duration::days(1);
+    let lemma = knowledge::lemma(&pool, lemma_ids[0], now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(lemma.cards, 1);
+    // Computed in the database, on the same curve as the scheduler's.
+    let mneme = Mneme::get_by_id(&pool, mneme_id).await.unwrap();
+    assert!((lemma.retrievability - mneme.retrievability_at(now).unwrap()).abs() < 1e-9);
+    assert!(lemma.retrievability < 1.0);
+    assert!(knowledge::lemma(&pool, lemma_ids[1], now)
+        .await
+        .unwrap()
+        .is_none());
+
+    let kanji = knowledge::kanji_in(&pool, "自分分", now).await.unwrap();
+    assert_eq!(kanji.len(), 1);
+    assert_eq!(kanji[0].0, '分');
+    assert_eq!(kanji[0].1.cards, 1);
+    assert_eq!(
+        knowledge::familiar_variants(&pool, 0.8, now).await.unwrap(),
+        [variant_ids[1]]
+    );
+    assert!(knowledge::familiar_variants(&pool, 1.1, now)
+        .await
+        .unwrap()
+        .is_empty());
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn stats_match_review_log(pool: PgPool) -> sqlx::Result<()> {
+    use chrono::{Datelike, Timelike};
+
     let p = Params::from_weight_vector(TEST_WEIGHTS);
-    let id = Mneme::create(&pool, &p, ReviewGrade::Easy).await.unwrap();
-    Mneme::review_by_id(&pool, id, &p, ReviewGrade::Hard)
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    let variant_id = sqlx::query_scalar!(
+        r#"INSERT INTO variants (lemma_id, spelling, reading)
+VALUES ($1, '猫', 'ねこ') RETURNING id AS "id!""#,
+        lemma_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    let item = sample_mneme(&p, &TEST_GRADES, Duration::days(1));
+    let states: Vec<_> = item.states().copied().collect();
+    let mut conn = pool.acquire().await?;
+    let mneme_id = item.mneme.id;
+    item.persist(&mut conn).await.unwrap();
+    card::attach(&mut conn, variant_id, CardKind::Recognition, mneme_id)
+        .await
+        .unwrap();
+
+    let first = queue::Usage::day_start(states[0].reviewed_at);
+    let now = states.last().unwrap().reviewed_at;
+    let days = (queue::Usage::day_start(now) - first).num_days() as u32 + 1;
+    let day_of = |t: DateTime<Utc>| (queue::Usage::day_start(t) - first).num_days() as usize;
+
+    let per_day = stats::reviews_per_day(&pool, now, days).await.unwrap();
+    assert_eq!(per_day.len(), days as usize);
+    assert_eq!(per_day[0].date, first);
+    let mut expected = vec![(0, 0, 0); days as usize];
+    for s in &states {
+        let day = &mut expected[day_of(s.reviewed_at)];
+        if s.index == 0 {
+            day.0 += 1;
+        } else {
+            day.1 += 1;
+            day.2 += (s.grade == ReviewGrade::Fail) as usize;
+        }
+    }
+    let actual: Vec<_> = per_day
+        .iter()
+        .map(|d| (d.new, d.reviews, d.failed))
+        .collect();
+    assert_eq!(actual, expected);
+
+    let retention = stats::retention_by_interval(&pool).await.unwrap();
+    let reviewed: Vec<_> = states
+        .windows(2)
+        .filter(|w| w[0].status == MemoryStatus::Reviewing)
+        .map(|w| w[1])
+        .collect();
+    assert_eq!(
+        retention.iter().map(|b| b.reviews).sum::<usize>(),
+        reviewed.len()
+    );
+    assert_eq!(
+        retention.iter().map(|b| b.passed).sum::<usize>(),
+        reviewed
+            .iter()
+            .filter(|s| s.grade != ReviewGrade::Fail)
+            .count()
+    );
+
+    for bins in [
+        stats::stability_distribution(&pool).await.unwrap(),
+        stats::difficulty_distribution(&pool).await.unwrap(),
+    ] {
+        assert_eq!(bins.iter().map(|b| b.count).sum::<usize>(), 1);
+    }
+
+    let heatmap = stats::review_heatmap(&pool).await.unwrap();
+    let mut expected = [[0; 24]; 7];
+    for s in &states {
+        let t = s.reviewed_at;
+        expected[t.weekday().num_days_from_monday() as usize][t.hour() as usize] += 1;
+    }
+    assert_eq!(heatmap, expected);
+
+    let known = stats::known_words(&pool, now, days).await.unwrap();
+    let learned = states
+        .iter()
+        .find(|s| s.status == MemoryStatus::Reviewing)
+        .map(|s| day_of(s.reviewed_at))
+        .unwrap();
+    let actual: Vec<_> = known.iter().map(|k| k.known).collect();
+    let expected: Vec<_> = (0..days as usize)
+        .map(|d| (d >= learned) as usize)
+        .collect();
+    assert_eq!(actual, expected);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn lapsing_mnemes_become_leeches(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::builder(TEST_WEIGHTS)
+        .leech_threshold(2)
+        .suspend_leeches(true)
+        .build()
+        .unwrap();
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    let variant_id = sqlx::query_scalar!(
+        r#"INSERT INTO variants (lemma_id, spelling, reading)
+VALUES ($1, '猫', 'ねこ') RETURNING id AS "id!""#,
+        lemma_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    let mut conn = pool.acquire().await?;
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
+        .await
+        .unwrap();
+    card::attach(&mut conn, variant_id, CardKind::Recognition, id)
+        .await
+        .unwrap();
+
+    // Fails while learning are not lapses.
+    for grade in [ReviewGrade::Fail, ReviewGrade::Okay, ReviewGrade::Fail] {
+        Mneme::review_by_id(&mut conn, id, &p, grade, None)
+            .await
+            .unwrap();
+    }
+    assert_eq!(Mneme::count_lapses(&pool, id).await.unwrap(), 1);
+    assert!(!Mneme::get_by_id(&pool, id).await.unwrap().leech);
+    assert!(leech::get_all(&pool).await.unwrap().is_empty());
+
+    for grade in [ReviewGrade::Okay, ReviewGrade::Fail] {
+        Mneme::review_by_id(&mut conn, id, &p, grade, None)
+            .await
+            .unwrap();
+    }
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.lapses(), 2);
+    assert_eq!(Mneme::count_lapses(&pool, id).await.unwrap(), 2);
+    assert!(item.mneme.leech);
+    assert!(item.mneme.suspended);
+    assert_eq!(
+        leech::get_all(&pool).await.unwrap(),
+        vec![leech::Leech {
+            mneme_id: id,
+            variant_id,
+            kind: CardKind::Recognition,
+            spelling: "猫".to_owned(),
+            reading: Some("ねこ".to_owned()),
+            lapses: 2,
+            suspended: true,
+        }]
+    );
+
+    // Undone lapses no longer count, and undoing the one that made the
+    // mneme a leech takes back the tag and the suspension.
+    Mneme::undo_review_by_id(&mut conn, id, item.mneme.state.id)
+        .await
+        .unwrap();
+    assert_eq!(Mneme::count_lapses(&pool, id).await.unwrap(), 1);
+    let mneme = Mneme::get_by_id(&pool, id).await.unwrap();
+    assert!(!mneme.leech);
+    assert!(!mneme.suspended);
+    assert!(leech::get_all(&pool).await.unwrap().is_empty());
+
+    // A suspension that was there before the lapse stays.
+    Mneme::set_suspended(&pool, id, true).await.unwrap();
+    let state_id = Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    assert!(Mneme::get_by_id(&pool, id).await.unwrap().leech);
+    Mneme::undo_review_by_id(&mut conn, id, state_id)
+        .await
+        .unwrap();
+    let mneme = Mneme::get_by_id(&pool, id).await.unwrap();
+    assert!(!mneme.leech);
+    assert!(mneme.suspended);
+    Mneme::set_suspended(&pool, id, false).await.unwrap();
+
+    Mneme::review_by_id(&mut conn, id, &p, ReviewGrade::Fail, None)
+        .await
+        .unwrap();
+    assert!(Mneme::get_by_id(&pool, id).await.unwrap().leech);
+    Mneme::reset_by_id(&mut conn, id, &p).await.unwrap();
+    assert_eq!(Mneme::count_lapses(&pool, id).await.unwrap(), 0);
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    assert_eq!(item.lapses(), 0);
+    assert!(!item.mneme.leech);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn notes_roundtrip(pool: PgPool) -> sqlx::Result<()> {
+    let lemma_id = sqlx::query_scalar!(
+        r#"INSERT INTO lemmas (spelling, main_pos, second_pos, third_pos, fourth_pos, comes_from)
+VALUES ('x', '', '', '', '', 'test') RETURNING id AS "id!""#
+    )
+    .fetch_one(&pool)
+    .await?;
+    let variant_id = sqlx::query_scalar!(
+        r#"INSERT INTO variants (lemma_id, spelling, reading)
+VALUES ($1, '猫', 'ねこ') RETURNING id AS "id!""#,
+        lemma_id
+    )
+    .fetch_one(&pool)
+    .await?;
+    assert!(Note::get(&pool, variant_id).await.unwrap().is_empty());
+
+    let note = Note {
+        gloss: " cat ".to_owned(),
+        mnemonic: "It *naps* all day.\n".to_owned(),
+        notes: String::new(),
+    };
+    note.save(&pool, variant_id).await.unwrap();
+    let saved = Note::get(&pool, variant_id).await.unwrap();
+    assert_eq!(saved.gloss, "cat");
+    assert_eq!(saved.mnemonic, "It *naps* all day.");
+    assert_eq!(saved.notes, "");
+
+    let updated = Note {
+        notes: "Also a counter word".to_owned(),
+        ..saved
+    };
+    updated.save(&pool, variant_id).await.unwrap();
+    assert_eq!(Note::get(&pool, variant_id).await.unwrap(), updated);
+
+    Note::default().save(&pool, variant_id).await.unwrap();
+    let rows = sqlx::query_scalar!(r#"SELECT COUNT(*) AS "n!" FROM variant_notes"#)
+        .fetch_one(&pool)
+        .await?;
+    assert_eq!(rows, 0);
+    Ok(())
+}
+
+#[sqlx::test(migrations = "../migrations")]
+async fn slow_answers_count_as_hard(pool: PgPool) -> sqlx::Result<()> {
+    let p = Params::builder(TEST_WEIGHTS)
+        .slow_okay_after(Some(Duration::seconds(10)))
+        .build()
+        .unwrap();
+    let mut conn = pool.acquire().await?;
+    let id = Mneme::create(&mut conn, &p, ReviewGrade::Okay)
         .await
         .unwrap();
+    let reviews = [
+        (ReviewGrade::Okay, Some(Duration::seconds(3))),
+        (ReviewGrade::Okay, Some(Duration::seconds(12))),
+        (ReviewGrade::Easy, Some(Duration::seconds(30))),
+        (ReviewGrade::Okay, None),
+    ];
+    for (grade, latency) in reviews {
+        Mneme::review_by_id(&mut conn, id, &p, grade, latency)
+            .await
+            .unwrap();
+    }
+
+    let item = MnemeWithHistory::get_by_id(&pool, id).await.unwrap();
+    let logged: Vec<_> = item
+        .states()
+        .skip(1)
+        .map(|s| (s.grade, s.latency_ms))
+        .collect();
+    assert_eq!(
+        logged,
+        [
+            (ReviewGrade::Okay, Some(3000)),
+            (ReviewGrade::Hard, Some(12000)),
+            (ReviewGrade::Easy, Some(30000)),
+            (ReviewGrade::Okay, None),
+        ]
+    );
+    // Only the first state has no review to time.
+    assert_eq!(item.history[0].latency_ms, None);
     Ok(())
 }
@@ -1,4 +1,7 @@
+#[derive(Clone)]
 pub struct Weights {
+    /// The FSRS weight vector these were built from.
+    pub(crate) vector: [f64; 17],
     /// Initial stability for a card that starts in the [`Grade::Fail`] state.
     /// w[0] in FSRS.
     pub(crate) init_stab_fail: f64,
@@ -43,6 +46,7 @@ pub struct Weights {
 impl Weights {
     pub fn from_weight_vector(w: [f64; 17]) -> Self {
         Self {
+            vector: w,
             init_stab_fail: w[0],
             init_stab_hard: w[1],
             init_stab_okay: w[2],
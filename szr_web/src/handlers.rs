@@ -2,23 +2,30 @@ use std::collections::{BTreeSet, HashMap};
 
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse, Redirect},
+    Form,
 };
 use chrono::Utc;
 use itertools::Itertools;
 use serde::Deserialize;
-use snafu::{ResultExt, Snafu};
-use sqlx::PgPool;
+use snafu::{ensure, ResultExt, Snafu};
+use sqlx::{PgConnection, PgPool};
 use szr_dict::DefContent;
 use szr_html::{Doc, DocRender, RenderExt, Z};
-use szr_srs::{MemoryStatus, Mneme, Params, ReviewGrade};
+use szr_srs::{
+    card,
+    knowledge::Estimate,
+    note::{self, Note},
+    queue::Usage,
+    CardKind, MemoryStatus, Mneme, Params, ReviewGrade,
+};
 use szr_textual::{Line, Token};
 use tracing::warn;
 use uuid::Uuid;
 
 use crate::{
-    layout::{head, is_punctuation, labelled_value, labelled_value_c},
+    layout::{head, is_punctuation, labelled_value, labelled_value_c, markdown},
     models::{
         self, get_mneme_refresh_batch, get_related_words, get_sentences, ContextBlock,
         ContextSentenceToken, DefGroup, LookupData, MnemeRefreshBatch, MnemeRefreshDatum,
@@ -36,8 +43,14 @@ pub enum Error {
     GetNewVariants { source: sqlx::Error },
     GetDueVariants { source: sqlx::Error },
     MnemeError { source: szr_srs::mneme::Error },
-    AssignMnemeToVariant { source: sqlx::Error },
+    GetParams { source: szr_srs::params::Error },
+    AssignMnemeToVariant { source: card::Error },
+    GetCardKind { source: card::Error },
+    AcquireConnection { source: sqlx::Error },
+    Transaction { source: sqlx::Error },
     ToggleFavourite { source: sqlx::Error },
+    GetNote { source: note::Error },
+    SaveNote { source: note::Error },
     GetDocs { source: sqlx::Error },
     GetLookupData { source: models::Error },
     GetRelatedWords { source: models::Error },
@@ -45,15 +58,17 @@ pub enum Error {
     GetMnemeRefreshBatch { source: models::Error },
     GetFrequentNames { source: sqlx::Error },
     GetPhraseHits { source: sqlx::Error },
+    #[snafu(display("latency of {latency_ms} ms is out of range"))]
+    InvalidLatency { latency_ms: i64 },
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Internal error: {}", self),
-        )
-            .into_response()
+        match self {
+            Error::InvalidLatency { .. } => (StatusCode::BAD_REQUEST, self.to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Internal error: {}", self)),
+        }
+        .into_response()
     }
 }
 
@@ -90,37 +105,43 @@ order by id desc
 pub async fn handle_create_mneme(
     State(pool): State<PgPool>,
     Path((variant_id, grade)): Path<(Uuid, ReviewGrade)>,
+    info: Query<ReviewParams>,
 ) -> Result<impl IntoResponse> {
-    let (variant_id, mneme) = render_create_mneme(&pool, variant_id, grade).await?;
-    let r = build_memory_section(MemorySectionData::KnownItem { variant_id, mneme }, false);
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
+    let kind = info.kind.unwrap_or_default();
+    let mut tx = pool.begin().await.context(TransactionCtx)?;
+    let (variant_id, mneme) =
+        render_create_mneme(&mut tx, &params, variant_id, kind, grade).await?;
+    tx.commit().await.context(TransactionCtx)?;
+    let r = build_memory_section(
+        MemorySectionData::KnownItem {
+            variant_id,
+            kind,
+            mneme,
+        },
+        &params,
+        false,
+    );
     Ok(r.render_to_html())
 }
 
+/// Create a mneme and attach it to the variant as its card of `kind`, as part
+/// of the transaction `conn` belongs to.
 pub async fn render_create_mneme(
-    pool: &PgPool,
+    conn: &mut PgConnection,
+    params: &Params,
     variant_id: Uuid,
+    kind: CardKind,
     grade: ReviewGrade,
 ) -> Result<(VariantId, Mneme)> {
-    let w = [
-        0.4, 0.6, 2.4, 5.8, 4.93, 0.94, 0.86, 0.01, 1.49, 0.14, 0.94, 2.18, 0.05, 0.34, 1.26, 0.29,
-        2.61,
-    ];
-    let params = Params::from_weight_vector(w);
-
-    let new_mneme_id = Mneme::create(&pool, &params, grade)
+    let new_mneme_id = Mneme::create(&mut *conn, params, grade)
         .await
         .context(MnemeCtx)?;
-    // TODO transaction
-    sqlx::query!(
-        r#"UPDATE variants SET mneme_id = $2 WHERE id = $1"#,
-        variant_id,
-        new_mneme_id
-    )
-    .execute(pool)
-    .await
-    .context(AssignMnemeToVariantCtx)?;
+    card::attach(&mut *conn, variant_id, kind, new_mneme_id)
+        .await
+        .context(AssignMnemeToVariantCtx)?;
 
-    let mneme = Mneme::get_by_id(&pool, new_mneme_id)
+    let mneme = Mneme::get_by_id(&mut *conn, new_mneme_id)
         .await
         .context(MnemeCtx)?;
 
@@ -163,7 +184,9 @@ join variants v on s.variant_id = v.id
 join mnemes m on m.id = v.mneme_id
 where doc_id = $1
 and line_index = $2
-and m.next_due < CURRENT_TIMESTAMP;
+and m.next_due < CURRENT_TIMESTAMP
+and not m.suspended
+and (m.buried_until is null or m.buried_until <= CURRENT_TIMESTAMP);
 "#,
         doc_id as i32,
         line_index as i32,
@@ -175,13 +198,20 @@ and m.next_due < CURRENT_TIMESTAMP;
     let mut css: Vec<String> = Default::default();
     let now = Utc::now();
 
+    // The whole line is graded at once, or not at all.
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
+    let mut tx = pool.begin().await.context(TransactionCtx)?;
+
     for variant_id in new_variant_ids {
-        let (_, mneme) = render_create_mneme(&pool, variant_id, grade).await?;
+        let (_, mneme) =
+            render_create_mneme(&mut tx, &params, variant_id, CardKind::Recognition, grade)
+                .await?;
         css.push(get_decoration_colour_rule(
             VariantId(variant_id),
             // technically always false, but
             mneme.next_due < now,
             mneme.state.status,
+            mneme.suspended,
         ));
     }
 
@@ -190,14 +220,18 @@ and m.next_due < CURRENT_TIMESTAMP;
         mneme_id,
     } in due_variant_ids
     {
-        let (_, mneme) = render_review_mneme(&pool, variant_id, mneme_id, grade).await?;
+        let (_, mneme) =
+            render_review_mneme(&mut tx, &params, variant_id, mneme_id, grade, None).await?;
         css.push(get_decoration_colour_rule(
             VariantId(variant_id),
             mneme.next_due < now,
             mneme.state.status,
+            mneme.suspended,
         ));
     }
 
+    tx.commit().await.context(TransactionCtx)?;
+
     let r = Z
         .div()
         .hx_swap_oob_raw("beforeend:#dynamic-patch")
@@ -209,47 +243,186 @@ and m.next_due < CURRENT_TIMESTAMP;
 #[derive(Deserialize)]
 pub struct ReviewParams {
     redirect: Option<bool>,
+    /// Which card of the variant to show, recognition if not given.
+    kind: Option<CardKind>,
+    /// Time from the card being shown to it being graded, if it was timed.
+    latency_ms: Option<i64>,
 }
 
+/// Longest time an answer can be timed at. Anything longer is not a real
+/// measurement.
+const MAX_LATENCY_MS: i64 = 24 * 60 * 60 * 1000;
+
 pub async fn handle_review_mneme(
     State(pool): State<PgPool>,
     Path((variant_id, mneme_id, grade)): Path<(Uuid, Uuid, ReviewGrade)>,
     info: Query<ReviewParams>,
+    headers: HeaderMap,
 ) -> Result<impl IntoResponse> {
-    let (variant_id, mneme) = render_review_mneme(&pool, variant_id, mneme_id, grade).await?;
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
+    let latency = match info.latency_ms {
+        Some(latency_ms) => {
+            ensure!(
+                (0..=MAX_LATENCY_MS).contains(&latency_ms),
+                InvalidLatencyCtx { latency_ms }
+            );
+            Some(chrono::Duration::milliseconds(latency_ms))
+        }
+        None => None,
+    };
+    let mut tx = pool.begin().await.context(TransactionCtx)?;
+    let (variant_id, mneme) =
+        render_review_mneme(&mut tx, &params, variant_id, mneme_id, grade, latency).await?;
+    let kind = card::kind_of(&mut *tx, mneme_id)
+        .await
+        .context(GetCardKindCtx)?
+        .unwrap_or_default();
+    tx.commit().await.context(TransactionCtx)?;
     if let Some(true) = info.redirect {
-        return Ok(Redirect::to(&format!(
-            "/srs/review",
-            /* variant_id.0, mneme_id */
-        ))
-        .into_response());
+        // The next card can undo this review, and only this one.
+        let next = format!("/srs/review?undo_mneme={}&undo_state={}", mneme.id, mneme.state.id);
+        // htmx would follow a redirect itself and swap in the page it leads to.
+        if headers.contains_key("HX-Request") {
+            return Ok([("HX-Redirect", next)].into_response());
+        }
+        return Ok(Redirect::to(&next).into_response());
     }
     Ok(
-        build_memory_section(MemorySectionData::KnownItem { variant_id, mneme }, false)
-            .render_to_html()
-            .into_response(),
+        build_memory_section(
+            MemorySectionData::KnownItem {
+                variant_id,
+                kind,
+                mneme,
+            },
+            &params,
+            false,
+        )
+        .render_to_html()
+        .into_response(),
     )
 }
 
+/// Review the mneme as part of the transaction `conn` belongs to.
 pub async fn render_review_mneme(
-    pool: &PgPool,
+    conn: &mut PgConnection,
+    params: &Params,
     variant_id: Uuid,
     mneme_id: Uuid,
     grade: ReviewGrade,
+    latency: Option<chrono::Duration>,
 ) -> Result<(VariantId, Mneme)> {
-    let w = [
-        0.4, 0.6, 2.4, 5.8, 4.93, 0.94, 0.86, 0.01, 1.49, 0.14, 0.94, 2.18, 0.05, 0.34, 1.26, 0.29,
-        2.61,
-    ];
-    let params = Params::from_weight_vector(w);
-    Mneme::review_by_id(pool, mneme_id, &params, grade)
+    Mneme::review_by_id(&mut *conn, mneme_id, params, grade, latency)
+        .await
+        .context(MnemeCtx)?;
+    let mneme = Mneme::get_by_id(&mut *conn, mneme_id)
         .await
         .context(MnemeCtx)?;
-    let mneme = Mneme::get_by_id(pool, mneme_id).await.context(MnemeCtx)?;
 
     Ok((VariantId(variant_id), mneme))
 }
 
+/// Undo the review that left the mneme in the state `state_id`, if it is
+/// still in that state.
+pub async fn handle_undo_review(
+    State(pool): State<PgPool>,
+    Path((variant_id, mneme_id, state_id)): Path<(Uuid, Uuid, Uuid)>,
+) -> Result<impl IntoResponse> {
+    let mut conn = pool.acquire().await.context(AcquireConnectionCtx)?;
+    Mneme::undo_review_by_id(&mut conn, mneme_id, state_id)
+        .await
+        .context(MnemeCtx)?;
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
+    let mneme = Mneme::get_by_id(&pool, mneme_id).await.context(MnemeCtx)?;
+    let mneme = mneme
+        .for_scheduler(&mut conn, &params)
+        .await
+        .context(MnemeCtx)?;
+    let kind = card::kind_of(&pool, mneme_id)
+        .await
+        .context(GetCardKindCtx)?
+        .unwrap_or_default();
+    let variant_id = VariantId(variant_id);
+    Ok(build_memory_section(
+        MemorySectionData::KnownItem {
+            variant_id,
+            kind,
+            mneme,
+        },
+        &params,
+        false,
+    )
+    .render_to_html())
+}
+
+/// Ways to take a mneme out of rotation, or to start it over.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum MnemeAction {
+    Suspend,
+    Unsuspend,
+    /// Until the start of the next day.
+    Bury,
+    Unbury,
+    Reset,
+    /// Untag a leech, e.g. once a better way to remember it has been found.
+    ClearLeech,
+}
+
+pub async fn handle_mneme_action(
+    State(pool): State<PgPool>,
+    Path((variant_id, mneme_id, action)): Path<(Uuid, Uuid, MnemeAction)>,
+    info: Query<ReviewParams>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse> {
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
+    match action {
+        MnemeAction::Suspend => Mneme::set_suspended(&pool, mneme_id, true).await,
+        MnemeAction::Unsuspend => Mneme::set_suspended(&pool, mneme_id, false).await,
+        MnemeAction::Bury => {
+            let tomorrow = Usage::day_start(Utc::now()) + chrono::Duration::days(1);
+            Mneme::set_buried_until(&pool, mneme_id, Some(tomorrow)).await
+        }
+        MnemeAction::Unbury => Mneme::set_buried_until(&pool, mneme_id, None).await,
+        MnemeAction::ClearLeech => Mneme::set_leech(&pool, mneme_id, false).await,
+        MnemeAction::Reset => {
+            let mut conn = pool.acquire().await.context(AcquireConnectionCtx)?;
+            Mneme::reset_by_id(&mut conn, mneme_id, &params).await
+        }
+    }
+    .context(MnemeCtx)?;
+
+    if let Some(true) = info.redirect {
+        // htmx would follow a redirect itself and swap in the page it leads to.
+        if headers.contains_key("HX-Request") {
+            return Ok([("HX-Redirect", "/srs/review")].into_response());
+        }
+        return Ok(Redirect::to("/srs/review").into_response());
+    }
+    let mut conn = pool.acquire().await.context(AcquireConnectionCtx)?;
+    let mneme = Mneme::get_by_id(&pool, mneme_id).await.context(MnemeCtx)?;
+    let mneme = mneme
+        .for_scheduler(&mut conn, &params)
+        .await
+        .context(MnemeCtx)?;
+    let kind = card::kind_of(&pool, mneme_id)
+        .await
+        .context(GetCardKindCtx)?
+        .unwrap_or_default();
+    let variant_id = VariantId(variant_id);
+    Ok(
+        build_memory_section(
+            MemorySectionData::KnownItem {
+                variant_id,
+                kind,
+                mneme,
+            },
+            &params,
+            false,
+        )
+        .render_to_html()
+        .into_response(),
+    )
+}
+
 pub async fn handle_toggle_favourite_line(
     State(pool): State<PgPool>,
     Path((doc_id, line_index)): Path<(i32, i32)>,
@@ -266,9 +439,106 @@ pub async fn handle_toggle_favourite_line(
     Ok(build_star_button(doc_id, line_index, new_status))
 }
 
+/// The notes of a variant, with a button to edit them.
+fn render_notes_section(variant_id: Uuid, note: &Note) -> Doc {
+    let edit_button = Z
+        .a()
+        .role("button")
+        .class("text-gray-600")
+        .hx_get(format!("/variants/{variant_id}/notes/edit"))
+        .hx_swap("none")
+        .c(if note.is_empty() { "Add notes" } else { "Edit" });
+    let text = |text: &str| (!text.is_empty()).then(|| Z.div().lang("ja").c(markdown(text)));
+    let content = Z
+        .div()
+        .class("flex flex-col gap-2")
+        .c((!note.gloss.is_empty())
+            .then(|| labelled_value("Gloss", Z.span().lang("ja").c(note.gloss.as_str()))))
+        .c(text(&note.mnemonic).map(|m| labelled_value("Mnemonic", m)))
+        .c(text(&note.notes))
+        .c(edit_button);
+    Z.div().id("lookup-notes").hx_swap_oob_enable().c(content)
+}
+
+pub async fn handle_lookup_notes_section(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+) -> Result<Doc> {
+    let note = Note::get(&pool, id).await.context(GetNoteCtx)?;
+    Ok(render_notes_section(id, &note))
+}
+
+/// A form in place of the notes of a variant.
+pub async fn handle_edit_notes(State(pool): State<PgPool>, Path(id): Path<Uuid>) -> Result<Doc> {
+    let note = Note::get(&pool, id).await.context(GetNoteCtx)?;
+    let field = |label: &'static str, input: Doc| {
+        Z.tag("label")
+            .class("flex flex-col gap-1")
+            .c(Z.span().class("font-bold text-gray-600").c(label))
+            .c(input.class("w-full px-2").lang("ja"))
+    };
+    let textarea = |name: &'static str, rows: &'static str, value: &str| {
+        Z.tag("textarea").name(name).attr("rows", rows).c(value)
+    };
+    let form = Z
+        .tag("form")
+        .class("flex flex-col gap-2")
+        .hx_post(format!("/variants/{id}/notes"))
+        .hx_swap("none")
+        .c(field("Gloss", textarea("gloss", "1", &note.gloss)))
+        .c(field("Mnemonic", textarea("mnemonic", "4", &note.mnemonic)))
+        .c(field("Notes", textarea("notes", "4", &note.notes)))
+        .c(Z.div()
+            .class("flex flex-row gap-2")
+            .c(Z.button()
+                .type_raw("submit")
+                .class("w-fit px-4 py-1 bg-gray-300")
+                .c("Save"))
+            .c(Z.a()
+                .role("button")
+                .class("text-gray-600 self-center")
+                .hx_get(format!("/variants/{id}/notes"))
+                .hx_swap("none")
+                .c("Cancel")));
+    Ok(Z.div().id("lookup-notes").hx_swap_oob_enable().c(form))
+}
+
+pub async fn handle_save_notes(
+    State(pool): State<PgPool>,
+    Path(id): Path<Uuid>,
+    Form(note): Form<Note>,
+) -> Result<Doc> {
+    note.save(&pool, id).await.context(SaveNoteCtx)?;
+    let note = Note::get(&pool, id).await.context(GetNoteCtx)?;
+    Ok(render_notes_section(id, &note))
+}
+
 pub enum MemorySectionData {
-    NewVariant { variant_id: VariantId },
-    KnownItem { variant_id: VariantId, mneme: Mneme },
+    NewVariant {
+        variant_id: VariantId,
+        kind: CardKind,
+    },
+    KnownItem {
+        variant_id: VariantId,
+        kind: CardKind,
+        mneme: Mneme,
+    },
+}
+
+/// Compact length of an interval, e.g. "10m" or "12d".
+pub fn short_duration(d: chrono::Duration) -> String {
+    let minutes = d.num_minutes().max(1);
+    if minutes < 60 {
+        format!("{minutes}m")
+    } else if minutes < 60 * 24 {
+        format!("{}h", minutes / 60)
+    } else if d.num_days() < 30 {
+        format!("{}d", d.num_days())
+    } else if d.num_days() < 365 {
+        format!("{}mo", d.num_days() / 30)
+    } else {
+        format!("{:.1}y", d.num_days() as f64 / 365.0)
+    }
 }
 
 /// https://docs.rs/relativetime/latest/src/relativetime/lib.rs.html#15-47
@@ -321,8 +591,15 @@ pub fn english_relative_time(secs: u64) -> String {
 
 // Yes, this is ugly. No, I don't know how to work around this short of having Tailwind
 // expose colour variables somehow.
-fn get_decoration_colour_rule(variant_id: VariantId, is_due: bool, status: MemoryStatus) -> String {
-    let colour = if is_due {
+fn get_decoration_colour_rule(
+    variant_id: VariantId,
+    is_due: bool,
+    status: MemoryStatus,
+    is_suspended: bool,
+) -> String {
+    let colour = if is_suspended {
+        "transparent"
+    } else if is_due {
         "rgb(153 27 27)"
     } else {
         match status {
@@ -334,61 +611,149 @@ fn get_decoration_colour_rule(variant_id: VariantId, is_due: bool, status: Memor
     };
 
     format!(
-        ".variant-{} {{ text-decoration-color: {colour}; }} ",
+        ".variant-{} {{ text-decoration-color: {colour}; text-decoration-style: solid; }} ",
         variant_id.0
     )
 }
 
-pub fn review_actions_block(data: &MemorySectionData, redirect: bool) -> Doc {
-    let create_link = |grade| match data {
-        MemorySectionData::NewVariant { variant_id } => {
+/// Words not studied yet, but whose lemma is well known through another
+/// variant, are underlined differently from ones that are entirely new.
+fn get_familiar_decoration_rule(variant_id: VariantId) -> String {
+    format!(
+        ".variant-{} {{ text-decoration-color: #16a34a; text-decoration-style: dotted; }} ",
+        variant_id.0
+    )
+}
+
+/// A button that posts to `link` to undo a review, which `z` presses.
+pub fn undo_button(link: String) -> Doc {
+    Z.a()
+        .role("button")
+        .class("text-gray-600")
+        .id("sidebar-undo-button")
+        .hx_post(link)
+        .hx_trigger("click")
+        .c("Undo")
+}
+
+pub fn review_actions_block(data: &MemorySectionData, params: &Params, redirect: bool) -> Doc {
+    let now = Utc::now();
+    let create_link = |grade: ReviewGrade| match data {
+        MemorySectionData::NewVariant { variant_id, kind } => {
             format!(
-                "/variants/{}/create-mneme/{}?redirect={}",
-                variant_id.0, grade, redirect
+                "/variants/{}/create-mneme/{:?}?redirect={}&kind={:?}",
+                variant_id.0, grade, redirect, kind
             )
         }
-        MemorySectionData::KnownItem { variant_id, mneme } => {
+        MemorySectionData::KnownItem {
+            variant_id, mneme, ..
+        } => {
             format!(
-                "/variants/{}/review/{}/{}?redirect={}",
+                "/variants/{}/review/{}/{:?}?redirect={}",
                 variant_id.0, mneme.id, grade, redirect
             )
         }
     };
 
-    let review_button = |grade, extra_classes, text, id| {
+    let review_button = |grade: ReviewGrade, extra_classes, id| {
         let base_classes = "";
+        // Show when the mneme would come back.
+        let text = match data {
+            MemorySectionData::NewVariant { .. } => format!("{grade:?}"),
+            MemorySectionData::KnownItem { mneme, .. } => {
+                let interval = mneme.predicted_interval(params, grade, now);
+                format!("{grade:?} → {}", short_duration(interval))
+            }
+        };
         let mut r = Z
             .a()
             .role("button")
             .class(format!("{base_classes} {extra_classes}"))
             .id(format!("sidebar-{id}-button"))
             .c(text);
+        let link = create_link(grade);
         if redirect {
-            r = r.href(create_link(grade))
+            // Going through htmx lets the page say how long the answer took.
+            r = r.href(link.clone()).hx_get(link)
         } else {
-            r = r.hx_post(create_link(grade)).hx_trigger(format!("click"))
+            r = r.hx_post(link).hx_trigger(format!("click"))
         }
         r
     };
 
+    // When reviewing in a queue, the last review is of a different mneme, and
+    // the review page has its own button for it.
+    let undo_button = match data {
+        _ if redirect => None,
+        MemorySectionData::KnownItem {
+            variant_id, mneme, ..
+        } if !mneme.state.is_start() => Some(undo_button(format!(
+            "/variants/{}/undo/{}/{}",
+            variant_id.0, mneme.id, mneme.state.id
+        ))),
+        _ => None,
+    };
+
     Z.div().class("flex flex-col gap-2").c(labelled_value_c(
         "Review as",
         Z.div()
             .class("flex flex-row gap-2")
-            .c(review_button("Fail", "text-red-800", "Fail", "fail"))
-            .c(review_button("Hard", "text-yellow-900", "Hard", "hard"))
-            .c(review_button("Okay", "text-green-800", "Okay", "okay"))
-            .c(review_button("Easy", "text-blue-800", "Easy", "easy")),
+            .c(review_button(ReviewGrade::Fail, "text-red-800", "fail"))
+            .c(review_button(ReviewGrade::Hard, "text-yellow-900", "hard"))
+            .c(review_button(ReviewGrade::Okay, "text-green-800", "okay"))
+            .c(review_button(ReviewGrade::Easy, "text-blue-800", "easy"))
+            .c(undo_button),
         "font-bold",
     ))
 }
 
-fn build_memory_section(data: MemorySectionData, redirect: bool) -> (Doc, Doc) {
+fn mneme_actions_block(variant_id: VariantId, mneme: &Mneme, redirect: bool) -> Doc {
+    let now = Utc::now();
+    // These change or throw away review state, so they are only ever POSTed,
+    // never plain links that could be followed or prefetched.
+    let action_button = |action: MnemeAction, text| {
+        let link = format!(
+            "/variants/{}/mneme/{}/{:?}?redirect={}",
+            variant_id.0, mneme.id, action, redirect
+        );
+        Z.a()
+            .role("button")
+            .class("text-gray-600")
+            .c(text)
+            .hx_post(link)
+            .hx_trigger("click")
+    };
+
+    labelled_value_c(
+        "Manage",
+        Z.div()
+            .class("flex flex-row gap-2")
+            .c(if mneme.suspended {
+                action_button(MnemeAction::Unsuspend, "Unsuspend")
+            } else {
+                action_button(MnemeAction::Suspend, "Suspend")
+            })
+            .c(if mneme.is_buried(now) {
+                action_button(MnemeAction::Unbury, "Unbury")
+            } else {
+                action_button(MnemeAction::Bury, "Bury")
+            })
+            .c(action_button(MnemeAction::Reset, "Reset"))
+            .c(mneme
+                .leech
+                .then(|| action_button(MnemeAction::ClearLeech, "Clear leech"))),
+        "font-bold",
+    )
+}
+
+fn build_memory_section(data: MemorySectionData, params: &Params, redirect: bool) -> (Doc, Doc) {
     let mut srs_status_block = Z.div().class("flex flex-col gap-2");
     let mut poll_interval = None;
-    let variant_id = match &data {
-        MemorySectionData::NewVariant { variant_id } => variant_id,
-        MemorySectionData::KnownItem { variant_id, .. } => variant_id,
+    let (variant_id, kind) = match &data {
+        MemorySectionData::NewVariant { variant_id, kind } => (variant_id, *kind),
+        MemorySectionData::KnownItem {
+            variant_id, kind, ..
+        } => (variant_id, *kind),
     };
 
     let mut decoration_colour_rule = None;
@@ -418,16 +783,34 @@ fn build_memory_section(data: MemorySectionData, redirect: bool) -> (Doc, Doc) {
             } else {
                 "right now".to_string()
             };
+            let flag_str = if mneme.suspended {
+                ", suspended"
+            } else if mneme.is_buried(now) {
+                ", buried"
+            } else {
+                ""
+            };
             srs_status_block = srs_status_block.c(labelled_value_c(
                 "Status",
-                format!("{:?} (due {})", mneme.state.status, diff_str),
+                format!("{:?} (due {}){}", mneme.state.status, diff_str, flag_str),
                 "status",
             ));
-            decoration_colour_rule = Some(get_decoration_colour_rule(
-                *variant_id,
-                diff_secs < 0,
-                mneme.state.status,
-            ));
+            if let Some(retrievability) = mneme.retrievability_at(now) {
+                srs_status_block = srs_status_block.c(labelled_value_c(
+                    "Recall",
+                    format!("{:.0}%", 100.0 * retrievability),
+                    "text-gray-800",
+                ));
+            }
+            // Documents are highlighted by how well words are recognised.
+            if kind == CardKind::Recognition {
+                decoration_colour_rule = Some(get_decoration_colour_rule(
+                    *variant_id,
+                    diff_secs < 0 && !mneme.is_buried(now),
+                    mneme.state.status,
+                    mneme.suspended,
+                ));
+            }
         }
     };
 
@@ -437,9 +820,17 @@ fn build_memory_section(data: MemorySectionData, redirect: bool) -> (Doc, Doc) {
         .id("memory")
         .hx_swap_oob_enable();
 
+    let mneme_actions = match &data {
+        MemorySectionData::KnownItem {
+            variant_id, mneme, ..
+        } => Some(mneme_actions_block(*variant_id, mneme, redirect)),
+        MemorySectionData::NewVariant { .. } => None,
+    };
+
     memory_block = memory_block
         .c(srs_status_block)
-        .c(review_actions_block(&data, redirect))
+        .c(review_actions_block(&data, params, redirect))
+        .c(mneme_actions)
         .c(Z.style().c(format!(
             ".variant-{} {{ background-color: rgb(209 213 219); }}",
             variant_id.0
@@ -475,10 +866,12 @@ pub async fn handle_variant_lookup_view(
     info: Option<Query<ReviewParams>>,
 ) -> Result<Html<String>> {
     let mut redirect = false;
+    let mut kind = CardKind::default();
     if let Some(info) = info {
         redirect = info.redirect.unwrap_or(false);
+        kind = info.kind.unwrap_or_default();
     }
-    Ok(render_variant_lookup(pool, VariantId(id), redirect)
+    Ok(render_variant_lookup(pool, VariantId(id), redirect, kind)
         .await?
         .render_to_html())
 }
@@ -671,20 +1064,26 @@ fn build_star_button(doc_id: i32, line_index: i32, is_favourite: bool) -> Doc {
     star_button
 }
 
+/// The lookup view of a variant, with the memory section for its card of
+/// `kind`.
 pub async fn render_variant_lookup(
     pool: PgPool,
     id: VariantId,
     redirect: bool,
+    kind: CardKind,
 ) -> Result<Vec<Doc>> {
     let LookupData {
         meanings,
         variant_id,
         ruby,
-        mneme,
+        cards,
+        lemma_knowledge,
+        kanji_knowledge,
         sibling_variants_ruby,
     } = LookupData::get_by_id(&pool, id)
         .await
         .context(GetLookupDataCtx)?;
+    let params = Params::get_active(&pool).await.context(GetParamsCtx)?;
 
     let mut selected_variant_ruby = Z.h1().lang("ja");
 
@@ -804,11 +1203,66 @@ pub async fn render_variant_lookup(
         all_defs
     });
 
+    // Switch between the cards of the variant, including ones not yet created.
+    let card_switcher = (!redirect).then(|| {
+        labelled_value_c(
+            "Card",
+            Z.div()
+                .class("flex flex-row gap-2")
+                .cs(CardKind::ALL.to_vec(), |k| {
+                    let class = if k == kind {
+                        "font-bold"
+                    } else if cards.iter().any(|(c, _)| *c == k) {
+                        "text-gray-800"
+                    } else {
+                        "text-gray-600 italic"
+                    };
+                    Z.a()
+                        .role("button")
+                        .class(class)
+                        .hx_get(format!("/variants/view/{}?kind={:?}", variant_id.0, k))
+                        .hx_swap("none")
+                        .c(format!("{k:?}"))
+                }),
+            "font-bold",
+        )
+    });
+
+    // Left out when reviewing, where the kanji would give away production cards.
+    let knowledge_row = {
+        let percent = |e: Estimate| format!("{:.0}%", 100.0 * e.retrievability);
+        let parts: Vec<_> = lemma_knowledge
+            .map(|e| format!("lemma {}", percent(e)))
+            .into_iter()
+            .chain(
+                kanji_knowledge
+                    .into_iter()
+                    .map(|(kanji, e)| format!("{kanji} {}", percent(e))),
+            )
+            .collect();
+        (!redirect && !parts.is_empty())
+            .then(|| labelled_value_c("Related", parts.join(" · "), "text-gray-800"))
+    };
+
+    let mneme = cards
+        .into_iter()
+        .find_map(|(k, mneme)| (k == kind).then_some(mneme));
     let memory_section_data = match mneme {
-        None => MemorySectionData::NewVariant { variant_id },
-        Some(mneme) => MemorySectionData::KnownItem { variant_id, mneme },
+        None => MemorySectionData::NewVariant { variant_id, kind },
+        Some(mneme) => {
+            let mut conn = pool.acquire().await.context(AcquireConnectionCtx)?;
+            MemorySectionData::KnownItem {
+                variant_id,
+                kind,
+                mneme: mneme
+                    .for_scheduler(&mut conn, &params)
+                    .await
+                    .context(MnemeCtx)?,
+            }
+        }
     };
-    let (memory_section, memory_dynamic_css) = build_memory_section(memory_section_data, redirect);
+    let (memory_section, memory_dynamic_css) =
+        build_memory_section(memory_section_data, &params, redirect);
 
     if redirect {
         selected_variant_ruby = selected_variant_ruby.class("self-center");
@@ -832,7 +1286,10 @@ pub async fn render_variant_lookup(
         .div()
         .id("lookup-memory")
         .hx_swap_oob_enable()
-        .c(memory_section);
+        .class("flex flex-col gap-2")
+        .c(memory_section)
+        .c(knowledge_row)
+        .c(card_switcher);
 
     let defs_section = Z
         .div()
@@ -854,6 +1311,14 @@ pub async fn render_variant_lookup(
         .hx_get(format!("/variants/view/{}/example-sentences", id.0))
         .c(Z.span().class("text-gray-600 italic").c("Loading…"));
 
+    let notes_section = Z
+        .div()
+        .id("lookup-notes")
+        .hx_swap_oob_enable()
+        .hx_trigger("load")
+        .hx_get(format!("/variants/{}/notes", id.0))
+        .c(Z.span().class("text-gray-600 italic").c("Loading…"));
+
     let links_section = Z
         .div()
         .id("lookup-links")
@@ -866,6 +1331,7 @@ pub async fn render_variant_lookup(
         header_section,
         memory_section,
         defs_section,
+        notes_section,
         examples_section,
         links_section,
         memory_dynamic_css,
@@ -885,8 +1351,10 @@ pub fn render_srs_style_patch(batch: MnemeRefreshBatch) -> Doc {
                  variant_id,
                  is_due,
                  status,
-             }| { get_decoration_colour_rule(variant_id, is_due, status) },
+                 is_suspended,
+             }| { get_decoration_colour_rule(variant_id, is_due, status, is_suspended) },
         ))
+        .c(Z.style().cs(batch.familiar_variants, get_familiar_decoration_rule))
         .c(Z.div().id("dynamic-patch"));
     let mut interval_sec = 60;
     if let Some(next_refresh_in_sec) = batch.next_refresh_in_sec {
@@ -1299,6 +1767,10 @@ pub async fn handle_books_view(
                     .c("Dictionary definitions matching the word are listed here, grouped by ")
                     .c("part of speech."))),
             )
+            .c(section("Notes").c(Z.div().id("lookup-notes").c(Z
+                .span()
+                .class("italic")
+                .c("Your own glosses, mnemonics and notes for the word are kept here."))))
             .c(section("Links").c(Z.div().id("lookup-links").c(Z
                 .span()
                 .class("italic")
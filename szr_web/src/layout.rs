@@ -1,3 +1,4 @@
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag};
 use szr_html::{Doc, Render, Z};
 
 pub fn is_punctuation(s: &str) -> bool {
@@ -22,6 +23,56 @@ pub fn is_punctuation(s: &str) -> bool {
         )
 }
 
+/// Whether user markdown may link to `url`: web pages and paths on this site
+/// are fine, anything with another scheme, such as `javascript:`, is not.
+fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore leading whitespace and control characters.
+    let url = url.trim_start_matches(|c: char| c <= ' ');
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            let scheme = &url[..end];
+            scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+        }
+        _ => true,
+    }
+}
+
+/// HTML for markdown written by the user, to be passed to [`Doc::c`] as is.
+/// Any HTML in the markdown is escaped rather than passed through, and links
+/// and images that are not [safe](is_safe_url) lead nowhere.
+pub fn markdown(src: &str) -> String {
+    let nowhere = || CowStr::Borrowed("#");
+    let events = Parser::new(src).map(|e| match e {
+        Event::Html(s) | Event::InlineHtml(s) => Event::Text(s),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: nowhere(),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: nowhere(),
+            title,
+            id,
+        }),
+        e => e,
+    });
+    let mut r = String::new();
+    html::push_html(&mut r, events);
+    r
+}
+
 pub fn labelled_value_c<'a, V: Render, W: Render>(
     label: W,
     value: V,
@@ -64,3 +115,33 @@ pub fn head() -> Doc {
         .c(tailwind_preamble)
         .c(icons_preamble)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_links() {
+        for url in [
+            "https://example.com",
+            "HTTP://example.com",
+            "/srs/review",
+            "a/b:c",
+            "#x",
+        ] {
+            assert!(is_safe_url(url), "{url}");
+        }
+        for url in [
+            "javascript:alert(1)",
+            " JavaScript:x",
+            "\x01javascript:x",
+            "data:text/html,x",
+        ] {
+            assert!(!is_safe_url(url), "{url}");
+        }
+        let html = markdown("[a](javascript:alert(1)) ![b](data:x) [c](https://example.com)");
+        assert!(!html.contains("javascript"));
+        assert!(!html.contains("data:"));
+        assert!(html.contains(r#"<a href="https://example.com">c</a>"#));
+    }
+}
@@ -3,6 +3,7 @@ mod handlers;
 mod layout;
 mod models;
 mod srs_ui;
+mod stats_ui;
 
 use std::{env, str::FromStr, time::Duration};
 
@@ -138,6 +139,14 @@ async fn main() -> Result<()> {
             "/variants/view/:id/example-sentences",
             get(handlers::handle_lookup_examples_section),
         )
+        .route(
+            "/variants/:id/notes",
+            get(handlers::handle_lookup_notes_section).post(handlers::handle_save_notes),
+        )
+        .route(
+            "/variants/:id/notes/edit",
+            get(handlers::handle_edit_notes),
+        )
         .route(
             "/variants/:id/create-mneme/:grade",
             post(handlers::handle_create_mneme),
@@ -146,6 +155,14 @@ async fn main() -> Result<()> {
             "/variants/:id/review/:mneme_id/:grade",
             post(handlers::handle_review_mneme).get(handlers::handle_review_mneme),
         )
+        .route(
+            "/variants/:id/undo/:mneme_id/:state_id",
+            post(handlers::handle_undo_review),
+        )
+        .route(
+            "/variants/:id/mneme/:mneme_id/:action",
+            post(handlers::handle_mneme_action),
+        )
         .route(
             "/variants/bulk-review-for-line/:doc_id/:line_index/:grade",
             post(handlers::handle_bulk_create_mneme),
@@ -159,10 +176,45 @@ async fn main() -> Result<()> {
             get(handlers::handle_refresh_srs_style_patch),
         )
         .route("/srs/review", get(srs_ui::handlers::review_page))
+        .route(
+            "/srs/undo/:mneme_id/:state_id",
+            post(srs_ui::handlers::undo_review),
+        )
         .route(
             "/srs/review/:id/:mneme_id",
             get(srs_ui::handlers::review_item_page),
         )
+        .route(
+            "/srs/params",
+            get(srs_ui::handlers::active_params_page).post(srs_ui::handlers::new_profile),
+        )
+        .route(
+            "/srs/params/:profile",
+            get(srs_ui::handlers::params_page).post(srs_ui::handlers::update_params),
+        )
+        .route(
+            "/srs/params/:profile/activate",
+            post(srs_ui::handlers::activate_profile),
+        )
+        .route(
+            "/srs/replay/:profile",
+            get(srs_ui::handlers::replay_page).post(srs_ui::handlers::apply_replay),
+        )
+        .route(
+            "/srs/import",
+            get(srs_ui::handlers::import_page).post(srs_ui::handlers::import_anki),
+        )
+        .route(
+            "/srs/export/mnemes.csv",
+            get(srs_ui::handlers::export_mnemes),
+        )
+        .route(
+            "/srs/export/reviews.csv",
+            get(srs_ui::handlers::export_reviews),
+        )
+        .route("/srs/export/anki.txt", get(srs_ui::handlers::export_anki))
+        .route("/srs/leeches", get(srs_ui::handlers::leeches_page))
+        .route("/stats", get(stats_ui::handlers::stats_page))
         .nest_service("/static", ServeDir::new("static"))
         .with_state(pool);
 
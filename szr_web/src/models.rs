@@ -3,6 +3,7 @@ use std::{
     path::Path,
 };
 
+use chrono::Utc;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
 use serde_tuple::Deserialize_tuple;
@@ -22,7 +23,11 @@ use szr_features::{
 };
 use szr_html::{Doc, DocRender, Z};
 use szr_ruby::Span;
-use szr_srs::{MemoryStatus, Mneme};
+use szr_srs::{
+    card,
+    knowledge::{self, Estimate, FAMILIAR_RECALL},
+    CardKind, MemoryStatus, Mneme,
+};
 use tracing::{instrument, trace, trace_span};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -60,6 +65,15 @@ pub enum Error {
     RubyFailure {
         source: szr_ruby::Error,
     },
+    GetCards {
+        source: card::Error,
+    },
+    GetMneme {
+        source: szr_srs::mneme::Error,
+    },
+    GetKnowledge {
+        source: szr_srs::knowledge::Error,
+    },
 }
 
 #[derive(Debug, Hash, PartialEq, Eq, Clone, Copy, sqlx::Type, PartialOrd, Ord, Serialize)]
@@ -817,12 +831,84 @@ ORDER BY
     Ok(ret)
 }
 
+/// Up to `num` sentences from the library for each of the given variants,
+/// each from a different document, for when many variants need a few examples
+/// at once. Documents with the most favourite hits, then the most hits, come
+/// first, and each one gives its first favourite line, or its first line if
+/// none is a favourite.
+#[instrument(skip(pool, variant_ids), err, level = "debug")]
+pub async fn get_example_sentences(
+    pool: &PgPool,
+    variant_ids: &[VariantId],
+    num: u32,
+) -> Result<HashMap<VariantId, Vec<Vec<ContextSentenceToken>>>> {
+    let ids: Vec<Uuid> = variant_ids.iter().map(|id| id.0).collect();
+    let rows = sqlx::query!(
+        r#"
+WITH
+  matches
+    AS (
+      SELECT
+        v.variant_id, v.doc_id, v.line_index,
+        row_number() OVER (PARTITION BY v.variant_id, v.doc_id ORDER BY lines.is_favourite DESC, v.line_index) AS doc_rank,
+        count(*) FILTER (WHERE lines.is_favourite) OVER (PARTITION BY v.variant_id, v.doc_id) AS num_fav_hits,
+        count(*) OVER (PARTITION BY v.variant_id, v.doc_id) AS num_hits
+      FROM
+        valid_context_lines AS v
+        JOIN docs ON docs.id = v.doc_id
+        JOIN lines ON lines.doc_id = v.doc_id AND lines.index = v.line_index
+      WHERE
+        v.variant_id = ANY($1) AND (lines.is_favourite OR docs.is_finished)
+    ),
+  picked
+    AS (
+      SELECT
+        variant_id, doc_id, line_index,
+        row_number() OVER (PARTITION BY variant_id ORDER BY num_fav_hits DESC, num_hits DESC, doc_id) AS rank
+      FROM matches
+      WHERE doc_rank = 1
+    )
+SELECT
+  picked.variant_id AS "variant_id!: VariantId",
+  jsonb_agg(jsonb_build_array(v.id, t.content, v.id = picked.variant_id) ORDER BY t.index ASC)
+    AS "sentence!: Json<Vec<ContextSentenceToken>>"
+FROM
+  picked
+  JOIN tokens AS t ON t.doc_id = picked.doc_id AND t.line_index = picked.line_index
+  JOIN surface_forms AS s ON t.surface_form_id = s.id
+  JOIN variants AS v ON s.variant_id = v.id
+WHERE
+  picked.rank <= $2
+GROUP BY
+  picked.variant_id, picked.rank
+ORDER BY
+  picked.variant_id, picked.rank
+"#,
+        &ids,
+        num as i64,
+    )
+    .fetch_all(pool)
+    .await
+    .context(SqlxFailure)?;
+
+    let mut r: HashMap<VariantId, Vec<_>> = HashMap::new();
+    for row in rows {
+        r.entry(row.variant_id).or_default().push(row.sentence.0);
+    }
+    Ok(r)
+}
+
 pub struct LookupData {
     pub variant_id: VariantId,
     pub meanings: Vec<DefGroup>,
     pub ruby: Option<Vec<RubySpan>>,
     pub sibling_variants_ruby: Vec<VariantRuby>,
-    pub mneme: Option<Mneme>,
+    /// In the order of [`CardKind::ALL`].
+    pub cards: Vec<(CardKind, Mneme)>,
+    /// How well the lemma is known through any of its variants.
+    pub lemma_knowledge: Option<Estimate>,
+    /// How well each kanji in the spelling is known through other words.
+    pub kanji_knowledge: Vec<(char, Estimate)>,
 }
 
 pub struct VariantRuby {
@@ -876,25 +962,37 @@ group by v.id
         })
         .collect();
 
-        let mneme_id = sqlx::query_scalar!(
-            r#"SELECT mneme_id "mneme_id: Uuid" FROM variants where id = $1"#,
+        let card_ids = card::cards_for_variant(pool, variant_id.0)
+            .await
+            .context(GetCards)?;
+        let mut cards = Vec::new();
+        for (kind, mneme_id) in card_ids {
+            let mneme = Mneme::get_by_id(pool, mneme_id).await.context(GetMneme)?;
+            cards.push((kind, mneme));
+        }
+
+        let variant = sqlx::query!(
+            "SELECT lemma_id, spelling FROM variants WHERE id = $1",
             variant_id.0
         )
         .fetch_one(pool)
         .await
-        .expect(&format!("get mneme for variant {}", variant_id.0));
-
-        let mneme = if let Some(mneme_id) = mneme_id {
-            Some(Mneme::get_by_id(pool, mneme_id).await.unwrap())
-        } else {
-            None
-        };
+        .context(SqlxFailure)?;
+        let now = Utc::now();
+        let lemma_knowledge = knowledge::lemma(pool, variant.lemma_id, now)
+            .await
+            .context(GetKnowledge)?;
+        let kanji_knowledge = knowledge::kanji_in(pool, &variant.spelling, now)
+            .await
+            .context(GetKnowledge)?;
 
         let r = Self {
             variant_id,
             meanings,
             ruby,
-            mneme,
+            cards,
+            lemma_knowledge,
+            kanji_knowledge,
             sibling_variants_ruby,
         };
 
@@ -902,23 +1000,26 @@ group by v.id
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Debug, Deserialize)]
 pub struct MnemeRefreshDatum {
     pub variant_id: VariantId,
     pub is_due: bool,
     pub status: MemoryStatus,
+    pub is_suspended: bool,
 }
 
 pub struct MnemeRefreshBatch {
     pub next_refresh_in_sec: Option<i32>,
     pub mneme_refresh_data: Json<Vec<MnemeRefreshDatum>>,
+    /// Variants that have not been studied themselves, but whose lemma is
+    /// well known through another variant.
+    pub familiar_variants: Vec<VariantId>,
 }
 
 // TODO "since book was loaded"?
 #[instrument(level = "debug", skip_all)]
 pub async fn get_mneme_refresh_batch(pool: &PgPool) -> Result<MnemeRefreshBatch> {
-    let data = sqlx::query_as!(
-        MnemeRefreshBatch,
+    let data = sqlx::query!(
         r#"
 select
   extract('epoch' from
@@ -928,8 +1029,10 @@ select
     "next_refresh_in_sec?: i32",
   jsonb_agg(jsonb_build_object(
     'variant_id', variants.id,
-    'is_due', current_timestamp > next_due,
-    'status', status))
+    'is_due', current_timestamp > next_due
+      and (buried_until is null or buried_until <= current_timestamp),
+    'status', status,
+    'is_suspended', suspended))
     "mneme_refresh_data!: Json<Vec<MnemeRefreshDatum>>"
 from mnemes
 join mneme_states on state_id = mneme_states.id
@@ -938,10 +1041,19 @@ join variants on variants.mneme_id = mnemes.id;
     )
     .fetch_one(pool)
     .await
-    .unwrap_or(MnemeRefreshBatch {
-        next_refresh_in_sec: None,
-        mneme_refresh_data: Json(Default::default()),
-    });
+    .map(|r| (r.next_refresh_in_sec, r.mneme_refresh_data))
+    .unwrap_or((None, Json(Default::default())));
 
-    Ok(data)
+    let familiar_variants = knowledge::familiar_variants(pool, FAMILIAR_RECALL, Utc::now())
+        .await
+        .context(GetKnowledge)?
+        .into_iter()
+        .map(VariantId)
+        .collect();
+
+    Ok(MnemeRefreshBatch {
+        next_refresh_in_sec: data.0,
+        mneme_refresh_data: data.1,
+        familiar_variants,
+    })
 }
@@ -1,18 +1,32 @@
+use std::{ffi::OsStr, path::PathBuf};
+
 use anyhow::anyhow;
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{IntoResponse, Redirect, Response},
+    Form,
 };
+use chrono::{Duration, Utc};
+use serde::Deserialize;
 use sqlx::PgPool;
 use szr_html::{Doc, Z};
-use szr_srs::Mneme;
+use szr_srs::{
+    anki, card,
+    export::{self, ExportedMneme},
+    forecast, leech,
+    params::{self, format_duration, parse_duration, DEFAULT_PROFILE},
+    queue::{Limits, Session},
+    replay::{Replay, Report},
+    CardKind, Mneme, Params, SchedulerKind,
+};
 use uuid::Uuid;
 
 use crate::{
-    handlers::{render_srs_style_patch, review_actions_block, MemorySectionData},
-    layout::head,
-    models::{get_mneme_refresh_batch, VariantId},
+    handlers::{render_srs_style_patch, review_actions_block, undo_button, MemorySectionData},
+    layout::{head, labelled_value},
+    models::{get_example_sentences, get_mneme_refresh_batch, ContextSentenceToken, VariantId},
+    stats_ui::handlers::distribution_chart,
 };
 
 // Make our own error that wraps `anyhow::Error`.
@@ -42,40 +56,119 @@ where
 
 type Result<T, E = AppError> = std::result::Result<T, E>;
 
-// TODO queue?
-pub async fn pick_srs_item(pool: &PgPool, offset: u8) -> Result<(Uuid, VariantId)> {
+/// The next mneme to review in the current session, starting a new session
+/// with the daily limits of the active profile if the last one is done.
+pub async fn pick_srs_item(pool: &PgPool) -> Result<Option<(Uuid, VariantId)>> {
+    let limits = Params::get_active(pool).await?.limits();
+    let Some(session) = Session::current_or_start(pool, limits, Utc::now()).await? else {
+        return Ok(None);
+    };
     let r = sqlx::query!(
         r#"
 select
-variants.id "variant_id!: VariantId",
-mnemes.id "mneme_id!: Uuid"
-from mnemes
-join variants on variants.mneme_id = mnemes.id
-order by next_due asc
-offset $1
+variant_mnemes.variant_id "variant_id!: VariantId",
+items.mneme_id
+from review_session_items items
+join variant_mnemes on variant_mnemes.mneme_id = items.mneme_id
+join mnemes on mnemes.id = items.mneme_id
+where items.session_id = $1 and items.done_at is null
+and not mnemes.suspended
+and (mnemes.buried_until is null or mnemes.buried_until <= current_timestamp)
+order by items.position asc
 limit 1"#,
-        offset as i64
+        session.id
     )
-    .fetch_one(pool)
+    .fetch_optional(pool)
     .await?;
 
-    Ok((r.mneme_id, r.variant_id))
+    Ok(r.map(|r| (r.mneme_id, r.variant_id)))
+}
+
+/// The review the next card can undo, which is the one just made.
+#[derive(Deserialize, Clone, Copy)]
+pub struct UndoTarget {
+    undo_mneme: Option<Uuid>,
+    /// The state the review left the mneme in.
+    undo_state: Option<Uuid>,
+}
+
+impl UndoTarget {
+    fn query(self) -> String {
+        match (self.undo_mneme, self.undo_state) {
+            (Some(mneme), Some(state)) => format!("?undo_mneme={mneme}&undo_state={state}"),
+            _ => String::new(),
+        }
+    }
+
+    fn button(self) -> Option<Doc> {
+        Some(undo_button(format!(
+            "/srs/undo/{}/{}",
+            self.undo_mneme?, self.undo_state?
+        )))
+    }
 }
 
 #[axum::debug_handler]
-pub async fn review_page(State(pool): State<PgPool>) -> Result<Redirect> {
-    let (mneme_id, variant_id) = pick_srs_item(&pool, 0).await?;
+pub async fn review_page(
+    State(pool): State<PgPool>,
+    Query(undo): Query<UndoTarget>,
+) -> Result<Response> {
+    let Some((mneme_id, variant_id)) = pick_srs_item(&pool).await? else {
+        let mut upcoming = forecast::due_histogram(&pool, Utc::now(), 30).await?;
+        upcoming.retain(|d| d.due > 0);
+        let body = Z
+            .body()
+            .class("text-gray-600 px-20 py-20")
+            .hx_on("keydown", "onBodyKeypress()")
+            .c(Z.p().c("Nothing left to review for now."))
+            .c(undo.button())
+            .c(Z.div()
+                .class("flex flex-row gap-4")
+                .c(Z.a().href("/stats").c("Statistics"))
+                .c(Z.a().href("/srs/leeches").c("Leeches"))
+                .c(Z.a().href("/srs/params").c("Settings")))
+            .c(Z.p().class("mt-4").c("Coming up:"))
+            .c(Z.ul().cs(upcoming, |d| {
+                Z.li()
+                    .c(format!("{}: {}", d.date.format("%Y-%m-%d"), d.due))
+            }));
+        let head = head().c(Z.script().src("/static/handlers.js"));
+        return Ok(Z.html().c(head).c(body).into_response());
+    };
     Ok(Redirect::to(&format!(
-        "/srs/review/{}/{}",
-        variant_id.0, mneme_id
-    )))
+        "/srs/review/{}/{}{}",
+        variant_id.0,
+        mneme_id,
+        undo.query()
+    ))
+    .into_response())
+}
+
+/// Undo the review that left the mneme in the state `state_id`, unless it has
+/// been reviewed or undone since, and go back to reviewing. That is the card
+/// reviewed next, since the session puts it back in its old place.
+#[axum::debug_handler]
+pub async fn undo_review(
+    State(pool): State<PgPool>,
+    Path((mneme_id, state_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response> {
+    let mut conn = pool.acquire().await?;
+    Mneme::undo_review_by_id(&mut conn, mneme_id, state_id).await?;
+    // htmx would follow a redirect itself and swap in the page it leads to.
+    Ok([("HX-Redirect", "/srs/review")].into_response())
 }
 
 #[axum::debug_handler]
 pub async fn review_item_page(
     State(pool): State<PgPool>,
     Path((variant_id, mneme_id)): Path<(Uuid, Uuid)>,
+    Query(undo): Query<UndoTarget>,
 ) -> Result<Doc> {
+    let kind = card::kind_of(&pool, mneme_id).await?.unwrap_or_default();
+    let reading = sqlx::query_scalar!("SELECT reading FROM variants WHERE id = $1", variant_id)
+        .fetch_one(&pool)
+        .await?
+        .unwrap_or_default();
     let variant_id = VariantId(variant_id);
 
     let refresh_data = get_mneme_refresh_batch(&pool).await.unwrap();
@@ -85,7 +178,10 @@ pub async fn review_item_page(
         .a()
         .hx_swap_oob_enable()
         .hx_trigger("load")
-        .hx_get(format!("/variants/view/{}?redirect=true", variant_id.0))
+        .hx_get(format!(
+            "/variants/view/{}?redirect=true&kind={:?}",
+            variant_id.0, kind
+        ))
         .hx_on("htmx:after-request", "toggleVis()")
         .hx_swap("none");
 
@@ -97,10 +193,36 @@ pub async fn review_item_page(
 
     let handler_scripts = Z.script().src("/static/handlers.js");
 
+    // What the card shows before it is revealed, besides the lookup header
+    // for recognition and meaning cards.
+    let cue = match kind {
+        CardKind::Recognition | CardKind::Meaning => None,
+        CardKind::Production => Some(Z.span().class("text-4xl").lang("ja").c(reading)),
+        CardKind::Listening => Some(
+            Z.a()
+                .role("button")
+                .id("card-play-button")
+                .class("text-4xl")
+                .attr("data-text", reading)
+                .onclick("speak(this.dataset.text)")
+                .c("▶"),
+        ),
+    };
+    let prompt = Z
+        .div()
+        .id("card-prompt")
+        .class("flex flex-col gap-2 px-6 py-3")
+        .c(cue)
+        .c(Z.span()
+            .class("text-gray-600 italic")
+            .c(kind.instructions()))
+        .c(undo.button());
+
     let main = Z
         .div()
         .class("flex flex-col w-full xl:w-8/12 text-lg grow-0 py-2 xl:py-10 px-2 xl:px-32 bg-gray-200 overflow-scroll")
         .c(Z.div().id("load-link").c(link))
+        .c(prompt)
         .c(Z.div()
             .id("lookup-header")
             // TODO make this consistent with the others
@@ -109,6 +231,9 @@ pub async fn review_item_page(
         .c(section("Definitions")
             .id("section-definitions")
             .c(Z.div().id("lookup-definitions").c(Z.span().class("italic"))))
+        .c(section("Notes")
+            .id("section-notes")
+            .c(Z.div().id("lookup-notes").c(Z.span().class("italic"))))
         .c(section("Links")
             .id("section-links")
             .c(Z.div().id("lookup-links").c(Z.span().class("italic"))))
@@ -121,6 +246,7 @@ pub async fn review_item_page(
     let body = Z
         .body()
         .hx_on("keydown", "onBodyKeypress()")
+        .attr("data-card-kind", format!("{kind:?}"))
         .class("h-screen w-screen bg-gray-100 relative flex flex-row overflow-hidden")
         .c(Z.div().class("grow bg-gray-300").id("left-spacer"))
         .c(main)
@@ -136,3 +262,605 @@ pub async fn review_item_page(
 
     Ok(html)
 }
+
+/// The current version of `profile`, or a copy of the default profile if it
+/// does not exist yet. Either way, `profile` has to be a valid name.
+async fn latest_or_default(pool: &PgPool, profile: &str) -> Result<Params> {
+    params::check_profile_name(profile)?;
+    match Params::get_latest(pool, profile).await {
+        Err(params::Error::GetParams {
+            source: sqlx::Error::RowNotFound,
+        }) => Ok(Params::get_latest(pool, DEFAULT_PROFILE)
+            .await?
+            .to_builder()
+            .profile(profile)
+            .build()?),
+        r => Ok(r?),
+    }
+}
+
+/// The settings of the profile reviews are performed with.
+#[axum::debug_handler]
+pub async fn active_params_page(State(pool): State<PgPool>) -> Result<Redirect> {
+    let profile = params::active_profile(&pool).await?;
+    Ok(Redirect::to(&format!("/srs/params/{profile}")))
+}
+
+#[derive(Deserialize)]
+pub struct NewProfileForm {
+    profile: String,
+}
+
+/// Go to the settings of a new profile, which starts out as a copy of the
+/// default one and is stored once it is saved.
+#[axum::debug_handler]
+pub async fn new_profile(Form(form): Form<NewProfileForm>) -> Result<Redirect> {
+    let profile = form.profile.trim();
+    params::check_profile_name(profile)?;
+    Ok(Redirect::to(&format!("/srs/params/{profile}")))
+}
+
+/// Perform reviews with `profile` from now on, storing it first if it is
+/// still a copy of the default one.
+#[axum::debug_handler]
+pub async fn activate_profile(
+    State(pool): State<PgPool>,
+    Path(profile): Path<String>,
+) -> Result<Redirect> {
+    params::check_profile_name(&profile)?;
+    if let Err(params::Error::UnknownProfile { .. }) =
+        params::set_active_profile(&pool, &profile).await
+    {
+        latest_or_default(&pool, &profile)
+            .await?
+            .persist(&pool)
+            .await?;
+        params::set_active_profile(&pool, &profile).await?;
+    }
+    Ok(Redirect::to(&format!("/srs/params/{profile}")))
+}
+
+#[axum::debug_handler]
+pub async fn params_page(State(pool): State<PgPool>, Path(profile): Path<String>) -> Result<Doc> {
+    let params = latest_or_default(&pool, &profile).await?;
+    let profiles = Params::get_all_latest(&pool).await?;
+    let active = params::active_profile(&pool).await?;
+    let (first, second, third) = params.learning_steps();
+
+    let input = |name: &'static str, value: String| {
+        Z.input()
+            .type_raw("number")
+            .name(name)
+            .attr("value", value)
+            .class("w-24 px-2")
+    };
+    let duration_input = |name: &'static str, value: Duration| {
+        Z.input()
+            .type_raw("text")
+            .name(name)
+            .attr("value", format_duration(value))
+            .attr("pattern", r"\s*[0-9.]+\s*[smhd]?\s*")
+            .class("w-24 px-2")
+    };
+    let checkbox = |name: &'static str, checked: bool| {
+        let r = Z.input().type_raw("checkbox").name(name);
+        if checked {
+            r.flag("checked")
+        } else {
+            r
+        }
+    };
+
+    let form = Z
+        .tag("form")
+        .attr("method", "post")
+        .attr("action", format!("/srs/params/{profile}"))
+        .class("flex flex-col gap-2")
+        .c(labelled_value(
+            "Scheduler",
+            Z.tag("select").name("scheduler").class("w-fit px-2").cs(
+                SchedulerKind::ALL.to_vec(),
+                |kind| {
+                    let option = Z.tag("option").attr("value", format!("{kind:?}"));
+                    if kind == params.scheduler() {
+                        option.flag("selected")
+                    } else {
+                        option
+                    }
+                    .c(kind.name())
+                },
+            ),
+        ))
+        .c(labelled_value(
+            "Target retention",
+            input("target_retention", params.target_retention().to_string())
+                .attr("step", "0.01")
+                .attr("min", "0.01")
+                .attr("max", "0.99"),
+        ))
+        .c(labelled_value(
+            "Learning steps (e.g. 30s, 10m, 1h or 1d)",
+            Z.div()
+                .class("flex flex-row gap-2")
+                .c(duration_input("first_interval", first))
+                .c(duration_input("second_interval", second))
+                .c(duration_input("third_interval", third)),
+        ))
+        .c(labelled_value(
+            "Round to days",
+            checkbox("round_to_days", params.round_to_days()),
+        ))
+        .c(labelled_value(
+            "Fuzz due dates",
+            checkbox("fuzz", params.fuzz()),
+        ))
+        .c(labelled_value(
+            "Balance daily load",
+            checkbox("load_balance", params.load_balance()),
+        ))
+        .c(labelled_value(
+            "Okay is hard after (seconds, empty for never)",
+            input(
+                "slow_okay_after",
+                params
+                    .slow_okay_after()
+                    .map(|d| d.num_seconds().to_string())
+                    .unwrap_or_default(),
+            )
+            .attr("min", "1"),
+        ))
+        .c(labelled_value(
+            "New per day",
+            input("new_per_day", params.limits().new_per_day.to_string()).attr("min", "0"),
+        ))
+        .c(labelled_value(
+            "Reviews per day",
+            input(
+                "reviews_per_day",
+                params.limits().reviews_per_day.to_string(),
+            )
+            .attr("min", "0"),
+        ))
+        .c(labelled_value(
+            "Leech after lapses",
+            input("leech_threshold", params.leech_threshold().to_string()).attr("min", "1"),
+        ))
+        .c(labelled_value(
+            "Suspend leeches",
+            checkbox("suspend_leeches", params.suspend_leeches()),
+        ))
+        .c(Z.button()
+            .type_raw("submit")
+            .class("w-fit px-4 py-1 bg-gray-300")
+            .c("Save"));
+
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1()
+            .class("text-2xl font-bold")
+            .c(format!("Profile: {}", params.profile())))
+        .c(labelled_value("Version", params.version().to_string()))
+        .c(if profile == active {
+            Z.p().c("Reviews are performed with this profile.")
+        } else {
+            Z.tag("form")
+                .attr("method", "post")
+                .attr("action", format!("/srs/params/{profile}/activate"))
+                .c(Z.button()
+                    .type_raw("submit")
+                    .class("w-fit px-4 py-1 bg-gray-300")
+                    .c("Use for reviews"))
+        })
+        .c(form)
+        .c(Z.a()
+            .href(format!("/srs/replay/{profile}"))
+            .c("Recompute due dates with these parameters"))
+        .c(Z.ul().cs(profiles, |p| {
+            let name = p.profile().to_owned();
+            let suffix = if name == active { " (active)" } else { "" };
+            Z.li()
+                .c(Z.a().href(format!("/srs/params/{name}")).c(name))
+                .c(suffix)
+        }))
+        .c(Z.tag("form")
+            .attr("method", "post")
+            .attr("action", "/srs/params")
+            .class("flex flex-row gap-2")
+            .c(Z.input()
+                .type_raw("text")
+                .name("profile")
+                .attr("placeholder", "New profile")
+                .class("w-48 px-2"))
+            .c(Z.button()
+                .type_raw("submit")
+                .class("w-fit px-4 py-1 bg-gray-300")
+                .c("Create")));
+
+    Ok(Z.html().c(head()).c(body))
+}
+
+#[derive(Deserialize)]
+pub struct ParamsForm {
+    scheduler: SchedulerKind,
+    target_retention: f64,
+    /// Only sent when ticked, as are the other checkboxes.
+    round_to_days: Option<String>,
+    fuzz: Option<String>,
+    load_balance: Option<String>,
+    suspend_leeches: Option<String>,
+    leech_threshold: i32,
+    new_per_day: usize,
+    reviews_per_day: usize,
+    /// In seconds, empty when slow answers should not count as hard.
+    slow_okay_after: String,
+    /// As read by [`parse_duration`], as are the other learning steps.
+    first_interval: String,
+    second_interval: String,
+    third_interval: String,
+}
+
+fn parse_learning_step(step: &str) -> Result<Duration> {
+    parse_duration(step).ok_or_else(|| anyhow!("invalid learning step {step:?}").into())
+}
+
+/// `None` when left empty, so that a typo does not quietly turn it off.
+fn parse_slow_okay_after(seconds: &str) -> Result<Option<Duration>> {
+    let seconds = seconds.trim();
+    if seconds.is_empty() {
+        return Ok(None);
+    }
+    let seconds: u32 = seconds
+        .parse()
+        .map_err(|_| anyhow!("invalid number of seconds for slow answers {seconds:?}"))?;
+    Ok(Some(Duration::seconds(seconds.into())))
+}
+
+/// Store the submitted settings as a new version of the profile.
+#[axum::debug_handler]
+pub async fn update_params(
+    State(pool): State<PgPool>,
+    Path(profile): Path<String>,
+    Form(form): Form<ParamsForm>,
+) -> Result<Redirect> {
+    latest_or_default(&pool, &profile)
+        .await?
+        .to_builder()
+        .scheduler(form.scheduler)
+        .target_retention(form.target_retention)
+        .round_to_days(form.round_to_days.is_some())
+        .fuzz(form.fuzz.is_some())
+        .load_balance(form.load_balance.is_some())
+        .leech_threshold(form.leech_threshold)
+        .suspend_leeches(form.suspend_leeches.is_some())
+        .limits(Limits {
+            new_per_day: form.new_per_day,
+            reviews_per_day: form.reviews_per_day,
+        })
+        .slow_okay_after(parse_slow_okay_after(&form.slow_okay_after)?)
+        .learning_steps(
+            parse_learning_step(&form.first_interval)?,
+            parse_learning_step(&form.second_interval)?,
+            parse_learning_step(&form.third_interval)?,
+        )
+        .build()?
+        .persist(&pool)
+        .await?;
+    Ok(Redirect::to(&format!("/srs/params/{profile}")))
+}
+
+fn render_replay_report(report: &Report) -> Doc {
+    Z.div()
+        .class("flex flex-col gap-2")
+        .c(labelled_value("Mnemes", report.replayed.to_string()))
+        .c(labelled_value(
+            "Without a full log",
+            report.partial.to_string(),
+        ))
+        .c(labelled_value("Changed", report.changed.to_string()))
+        .c(labelled_value(
+            "Moved",
+            format!(
+                "{} ({} earlier, {} later)",
+                report.moved.len(),
+                report.earlier(),
+                report.later()
+            ),
+        ))
+        .c(labelled_value(
+            "Mean move",
+            report
+                .mean_move_days()
+                .map(|d| format!("{d:.1} days"))
+                .unwrap_or_else(|| "-".to_owned()),
+        ))
+        .c(distribution_chart(report.move_distribution(), "d"))
+}
+
+/// What replaying every review log with the current parameters of `profile`
+/// would change, before anything is written.
+#[axum::debug_handler]
+pub async fn replay_page(State(pool): State<PgPool>, Path(profile): Path<String>) -> Result<Doc> {
+    let params = Params::get_latest(&pool, &profile).await?;
+    let replay = Replay::prepare(&pool, &params).await?;
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1().class("text-2xl font-bold").c(format!(
+            "Recompute with {} (version {})",
+            params.profile(),
+            params.version()
+        )))
+        .c(render_replay_report(&replay.report))
+        .c(Z.tag("form")
+            .attr("method", "post")
+            .attr("action", format!("/srs/replay/{profile}"))
+            .c(Z.input()
+                .type_raw("hidden")
+                .name("fingerprint")
+                .attr("value", replay.fingerprint().to_string()))
+            .c(Z.button()
+                .type_raw("submit")
+                .class("w-fit px-4 py-1 bg-gray-300")
+                .c("Apply")));
+    Ok(Z.html().c(head()).c(body))
+}
+
+#[derive(Deserialize)]
+pub struct ReplayForm {
+    /// Of the replay that was shown.
+    fingerprint: u64,
+}
+
+/// Replay every review log again and write the result, as long as it comes
+/// out the same as the replay that was shown.
+#[axum::debug_handler]
+pub async fn apply_replay(
+    State(pool): State<PgPool>,
+    Path(profile): Path<String>,
+    Form(form): Form<ReplayForm>,
+) -> Result<Doc> {
+    let params = Params::get_latest(&pool, &profile).await?;
+    let report = Replay::prepare(&pool, &params)
+        .await?
+        .apply_if_matches(&pool, form.fingerprint)
+        .await?;
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1().class("text-2xl font-bold").c(format!(
+            "Recomputed with {} (version {})",
+            params.profile(),
+            params.version()
+        )))
+        .c(render_replay_report(&report));
+    Ok(Z.html().c(head()).c(body))
+}
+
+/// Environment variable naming the directory Anki collections are imported
+/// from. Nothing outside of it can be imported.
+const IMPORT_DIR_VAR: &str = "SZR_IMPORT_DIR";
+
+/// The collection called `name` in the import directory. Only plain file
+/// names are accepted, so that the form cannot reach anything outside of it.
+fn import_path(name: &str) -> Result<PathBuf> {
+    let dir = std::env::var_os(IMPORT_DIR_VAR)
+        .ok_or_else(|| anyhow!("set {IMPORT_DIR_VAR} to the directory to import from"))?;
+    let name = name.trim();
+    if name.is_empty() || std::path::Path::new(name).file_name() != Some(OsStr::new(name)) {
+        return Err(anyhow!("{name:?} is not the name of a file in the import directory").into());
+    }
+    Ok(PathBuf::from(dir).join(name))
+}
+
+#[axum::debug_handler]
+pub async fn import_page() -> Doc {
+    let input = |name: &'static str, type_: &'static str| {
+        Z.input().type_raw(type_).name(name).class("w-96 px-2")
+    };
+    let form = Z
+        .tag("form")
+        .attr("method", "post")
+        .attr("action", "/srs/import")
+        .class("flex flex-col gap-2")
+        .c(labelled_value(
+            format!("Collection file in ${IMPORT_DIR_VAR}, e.g. collection.anki2"),
+            input("file_name", "text"),
+        ))
+        .c(labelled_value(
+            "Spelling field (from 0)",
+            input("spelling_field", "number").attr("value", "0"),
+        ))
+        .c(labelled_value(
+            "Reading field (from 0, optional)",
+            input("reading_field", "number").attr("value", "1"),
+        ))
+        .c(Z.button()
+            .type_raw("submit")
+            .class("w-fit px-4 py-1 bg-gray-300")
+            .c("Import"));
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1().class("text-2xl font-bold").c("Import from Anki"))
+        .c(form)
+        .c(labelled_value(
+            "Export",
+            Z.div()
+                .class("flex flex-row gap-2")
+                .c(Z.a().href("/srs/export/mnemes.csv").c("Mnemes (CSV)"))
+                .c(Z.a().href("/srs/export/reviews.csv").c("Reviews (CSV)"))
+                .c(Z.a().href("/srs/export/anki.txt").c("Anki deck")),
+        ));
+    Z.html().c(head()).c(body)
+}
+
+#[derive(Deserialize)]
+pub struct ImportForm {
+    /// Name of the collection in the import directory.
+    file_name: String,
+    spelling_field: usize,
+    /// Empty when not given.
+    reading_field: String,
+}
+
+/// Import the review history of every note that matches a variant, and list
+/// what happened to the others.
+#[axum::debug_handler]
+pub async fn import_anki(State(pool): State<PgPool>, Form(form): Form<ImportForm>) -> Result<Doc> {
+    let fields = anki::Fields {
+        spelling: form.spelling_field,
+        reading: form.reading_field.trim().parse().ok(),
+    };
+    let path = import_path(&form.file_name)?;
+    let params = Params::get_active(&pool).await?;
+    let notes = anki::read_collection(&path).await?;
+    let report = anki::import(&pool, &params, &notes, fields).await?;
+
+    let section = |title: &'static str, spellings: Vec<String>| {
+        labelled_value(
+            title,
+            Z.p()
+                .lang("ja")
+                .c(format!("{} {}", spellings.len(), spellings.join("、"))),
+        )
+    };
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1().class("text-2xl font-bold").c("Imported from Anki"))
+        .c(section("Imported", report.imported))
+        .c(section("No matching word", report.unmatched))
+        .c(section("Several matching words", report.ambiguous))
+        .c(section("Already known", report.already_known));
+    Ok(Z.html().c(head()).c(body))
+}
+
+/// Example sentences per exported note.
+const EXPORT_EXAMPLES: u32 = 2;
+
+fn attachment(content_type: &'static str, filename: &'static str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                // Always ASCII, so no need to quote.
+                &format!("attachment; filename={filename}"),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[axum::debug_handler]
+pub async fn export_mnemes(State(pool): State<PgPool>) -> Result<Response> {
+    let mnemes = ExportedMneme::get_all(&pool).await?;
+    let mut body = Vec::new();
+    export::write_mnemes_csv(&mut body, &mnemes)?;
+    Ok(attachment("text/csv", "mnemes.csv", body))
+}
+
+#[axum::debug_handler]
+pub async fn export_reviews(State(pool): State<PgPool>) -> Result<Response> {
+    let mnemes = ExportedMneme::get_all(&pool).await?;
+    let mut body = Vec::new();
+    export::write_reviews_csv(&mut body, &mnemes)?;
+    Ok(attachment("text/csv", "reviews.csv", body))
+}
+
+/// A deck for Anki's text importer, with a few example sentences from the
+/// library for each word, the word itself in bold.
+#[axum::debug_handler]
+pub async fn export_anki(State(pool): State<PgPool>) -> Result<Response> {
+    let mnemes = ExportedMneme::get_all(&pool).await?;
+    let variant_ids: Vec<_> = mnemes.iter().map(|m| VariantId(m.variant_id)).collect();
+    // The models error is not `Sync`, so it can only be passed on as text.
+    let mut examples = get_example_sentences(&pool, &variant_ids, EXPORT_EXAMPLES)
+        .await
+        .map_err(|e| anyhow!("{e}"))?;
+    let mut body = Vec::new();
+    export::write_anki_notes(&mut body, &mnemes, |m| {
+        let lines = examples
+            .remove(&VariantId(m.variant_id))
+            .unwrap_or_default();
+        lines
+            .into_iter()
+            .map(|line| {
+                line.into_iter()
+                    .map(|token| {
+                        let content = export::escape_html(&token.content);
+                        if token.is_active_word {
+                            format!("<b>{content}</b>")
+                        } else {
+                            content
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    })?;
+    Ok(attachment("text/plain; charset=utf-8", "suzuri.txt", body))
+}
+
+/// Example sentences per leech.
+const LEECH_EXAMPLES: u32 = 3;
+
+/// Every leech with a few sentences it appears in, as material for a better
+/// way to remember it.
+#[axum::debug_handler]
+pub async fn leeches_page(State(pool): State<PgPool>) -> Result<Doc> {
+    let leeches = leech::get_all(&pool).await?;
+    let variant_ids: Vec<_> = leeches.iter().map(|l| VariantId(l.variant_id)).collect();
+    // The models error is not `Sync`, so it can only be passed on as text.
+    let examples = get_example_sentences(&pool, &variant_ids, LEECH_EXAMPLES)
+        .await
+        .map_err(|e| anyhow!("{e}"))?;
+
+    let sentence = |line: &Vec<ContextSentenceToken>| {
+        Z.li().lang("ja").cs(line.iter().collect(), |token| {
+            if token.is_active_word {
+                Z.span().class("font-bold").c(token.content.clone())
+            } else {
+                Z.span().c(token.content.clone())
+            }
+        })
+    };
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-6")
+        .c(Z.h1()
+            .class("text-2xl font-bold")
+            .c(format!("Leeches ({})", leeches.len())))
+        .cs(leeches, |l| {
+            let status = if l.suspended { ", suspended" } else { "" };
+            let clear = format!(
+                "/variants/{}/mneme/{}/ClearLeech?redirect=false",
+                l.variant_id, l.mneme_id
+            );
+            Z.div()
+                .class("flex flex-col gap-1")
+                .c(Z.div()
+                    .class("flex flex-row gap-4 items-baseline")
+                    .c(Z.span().class("text-2xl").lang("ja").c(l.spelling.clone()))
+                    .c(Z.span().lang("ja").c(l.reading.clone().unwrap_or_default()))
+                    .c(Z.span()
+                        .c(format!("{:?}, {} lapses{status}", l.kind, l.lapses)))
+                    .c(Z.a()
+                        .role("button")
+                        .hx_post(clear)
+                        .hx_trigger("click")
+                        .hx_swap("none")
+                        .hx_on("htmx:after-request", "location.reload()")
+                        .c("Clear")))
+                .c(Z.ul().cs(
+                    examples
+                        .get(&VariantId(l.variant_id))
+                        .map(|e| e.iter().collect())
+                        .unwrap_or_default(),
+                    sentence,
+                ))
+        });
+    Ok(Z.html().c(head()).c(body))
+}
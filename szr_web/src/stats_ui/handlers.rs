@@ -0,0 +1,190 @@
+use axum::extract::{Query, State};
+use chrono::Utc;
+use serde::Deserialize;
+use sqlx::PgPool;
+use szr_html::{Doc, Render, Z};
+use szr_srs::stats::{self, Bin, RetentionBin};
+
+use crate::{layout::head, srs_ui::handlers::AppError};
+
+type Result<T, E = AppError> = std::result::Result<T, E>;
+
+#[derive(Deserialize)]
+pub struct StatsParams {
+    /// How far back to go for the charts by day.
+    days: Option<u32>,
+}
+
+const DEFAULT_DAYS: u32 = 30;
+/// About ten years, which keeps the per-day queries and charts a sane size.
+const MAX_DAYS: u32 = 3650;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+fn section(title: &str, content: impl Render) -> Doc {
+    Z.div()
+        .class("flex flex-col gap-3")
+        .c(Z.h2().class("text-xl font-bold").c(title))
+        .c(content)
+}
+
+/// Horizontal bars for `(label, value, text)` rows, scaled to the largest
+/// value.
+fn bar_chart(rows: Vec<(String, usize, String)>) -> Doc {
+    let max = rows
+        .iter()
+        .map(|(_, v, _)| *v)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    Z.div()
+        .class("flex flex-col gap-1")
+        .cs(rows, |(label, value, text)| {
+            Z.div()
+                .class("flex flex-row gap-3 items-center")
+                .c(Z.span().class("w-24 shrink-0 text-right").c(label))
+                .c(Z.div()
+                    .class("w-96 h-4 shrink-0")
+                    .c(Z.div().class("h-full bg-gray-400").attr(
+                        "style",
+                        format!("width: {:.1}%", 100.0 * value as f64 / max as f64),
+                    )))
+                .c(Z.span().c(text))
+        })
+}
+
+/// E.g. "7–30d", or "365d+" for the last bucket.
+pub(crate) fn bucket_label(start: f64, end: Option<f64>, unit: &str) -> String {
+    match end {
+        Some(end) => format!("{start}–{end}{unit}"),
+        None => format!("{start}{unit}+"),
+    }
+}
+
+pub(crate) fn distribution_chart(bins: Vec<Bin>, unit: &str) -> Doc {
+    bar_chart(
+        bins.into_iter()
+            .map(|b| {
+                (
+                    bucket_label(b.start, b.end, unit),
+                    b.count,
+                    b.count.to_string(),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn retention_table(bins: Vec<RetentionBin>) -> Doc {
+    let cell = |c: String| Z.td().class("pe-8").c(c);
+    Z.table()
+        .c(Z.tr()
+            .class("text-left")
+            .c(Z.th().class("pe-8").c("Interval"))
+            .c(Z.th().class("pe-8").c("Reviews"))
+            .c(Z.th().class("pe-8").c("Retention")))
+        .cs(bins, |b| {
+            let retention = b
+                .retention()
+                .map(|r| format!("{:.1}%", 100.0 * r))
+                .unwrap_or_else(|| "–".to_string());
+            Z.tr()
+                .c(cell(bucket_label(b.start, b.end, "d")))
+                .c(cell(b.reviews.to_string()))
+                .c(cell(retention))
+        })
+}
+
+fn heatmap_table(heatmap: [[usize; 24]; 7]) -> Doc {
+    let max = heatmap
+        .iter()
+        .flatten()
+        .copied()
+        .max()
+        .unwrap_or_default()
+        .max(1);
+    Z.table()
+        .class("text-xs text-center")
+        .c(Z.tr().c(Z.th()).cs((0..24).collect(), |hour| {
+            Z.th().class("w-6").c(hour.to_string())
+        }))
+        .cs(
+            WEEKDAYS.into_iter().zip(heatmap).collect(),
+            |(day, hours)| {
+                Z.tr()
+                    .c(Z.th().class("pe-2 text-right").c(day))
+                    .cs(hours.to_vec(), |count| {
+                        Z.td().class("h-6").title(count.to_string()).attr(
+                            "style",
+                            format!(
+                                "background-color: rgb(22 101 52 / {:.2})",
+                                count as f64 / max as f64
+                            ),
+                        )
+                    })
+            },
+        )
+}
+
+/// How reviewing has gone so far, to check that the scheduler is doing its
+/// job. Times are in UTC.
+#[axum::debug_handler]
+pub async fn stats_page(
+    State(pool): State<PgPool>,
+    Query(params): Query<StatsParams>,
+) -> Result<Doc> {
+    let now = Utc::now();
+    let days = params.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+
+    let per_day = stats::reviews_per_day(&pool, now, days).await?;
+    let retention = stats::retention_by_interval(&pool).await?;
+    let stability = stats::stability_distribution(&pool).await?;
+    let difficulty = stats::difficulty_distribution(&pool).await?;
+    let heatmap = stats::review_heatmap(&pool).await?;
+    let known = stats::known_words(&pool, now, days).await?;
+
+    let per_day = bar_chart(
+        per_day
+            .into_iter()
+            .map(|d| {
+                (
+                    d.date.format("%m-%d").to_string(),
+                    d.new + d.reviews,
+                    format!("{} reviews, {} failed, {} new", d.reviews, d.failed, d.new),
+                )
+            })
+            .collect(),
+    );
+    let known = bar_chart(
+        known
+            .into_iter()
+            .map(|k| {
+                (
+                    k.date.format("%m-%d").to_string(),
+                    k.known,
+                    k.known.to_string(),
+                )
+            })
+            .collect(),
+    );
+
+    let body = Z
+        .body()
+        .class("text-gray-600 px-20 py-20 flex flex-col gap-8")
+        .c(Z.h1().class("text-2xl font-bold").c("Statistics"))
+        .c(section("Reviews per day", per_day))
+        .c(section("Retention by interval", retention_table(retention)))
+        .c(section(
+            "Stability (FSRS)",
+            distribution_chart(stability, "d"),
+        ))
+        .c(section(
+            "Difficulty (FSRS)",
+            distribution_chart(difficulty, ""),
+        ))
+        .c(section(
+            "Reviews by time of day (UTC)",
+            heatmap_table(heatmap),
+        ))
+        .c(section("Known words", known));
+    Ok(Z.html().c(head()).c(body))
+}